@@ -28,15 +28,361 @@ function updateStatus(connected) {
     }
 }
 
+// The protocol with the CLI, see src/electron_protocol.rs.
+const PROTOCOL_VERSION = 2;
+
+// Encoded frames (type 2) are decoded here with WebCodecs, for the codecs
+// announced to the CLI. The CLI decodes the others and sends RGBA (type 0).
+// The index is the codec id of the frames.
+const CODECS = [
+    { name: 'vp8', config: 'vp8' },
+    { name: 'vp9', config: 'vp09.00.10.08' },
+    { name: 'av1', config: 'av01.0.05M.08' },
+    { name: 'h264', config: 'avc1.640033' },
+    { name: 'h265', config: 'hev1.1.6.L153.B0' },
+];
+let supportedCodecs = null;
+// One decoder per display, { decoder, codec }.
+const decoders = new Map();
+
+// The displays of the peer with their geometry, and the ones shown. Several
+// displays are shown side by side at their positions on the peer, the canvas
+// covers them and starts at (originX, originY) of the peer.
+let displays = [];
+let shownDisplays = [];
+let originX = 0;
+let originY = 0;
+
+async function detectCodecs() {
+    if (typeof VideoDecoder === 'undefined') {
+        return [];
+    }
+    const codecs = [];
+    for (const c of CODECS) {
+        try {
+            const { supported } = await VideoDecoder.isConfigSupported({ codec: c.config, optimizeForLatency: true });
+            if (supported) {
+                codecs.push(c.name);
+            }
+        } catch (e) {
+            console.warn(`WebCodecs ${c.name} check failed:`, e);
+        }
+    }
+    return codecs;
+}
+
+async function sendHello() {
+    if (supportedCodecs === null) {
+        supportedCodecs = await detectCodecs();
+        console.log('WebCodecs decodes:', supportedCodecs);
+    }
+    if (ws && ws.readyState === WebSocket.OPEN) {
+        ws.send(JSON.stringify({ type: 'hello', version: PROTOCOL_VERSION, video_codecs: supportedCodecs }));
+    }
+}
+
+// Requests of the protocol, the callback gets the response.
+let nextRequestId = 1;
+const pendingRequests = new Map();
+
+function sendRequest(method, params, callback) {
+    if (!ws || ws.readyState !== WebSocket.OPEN) {
+        return;
+    }
+    const id = nextRequestId++;
+    if (callback) {
+        pendingRequests.set(id, callback);
+    }
+    ws.send(JSON.stringify({ type: 'request', id, method, params: params || {} }));
+}
+
+function onResponse(msg) {
+    if (msg.error) {
+        console.error(`Request ${msg.id} failed:`, msg.error);
+    }
+    const callback = pendingRequests.get(msg.id);
+    pendingRequests.delete(msg.id);
+    if (callback) {
+        callback(msg);
+    }
+}
+
+function closeDecoder(display) {
+    const d = decoders.get(display);
+    if (d && d.decoder.state !== 'closed') {
+        d.decoder.close();
+    }
+    decoders.delete(display);
+}
+
+function closeDecoders() {
+    for (const display of [...decoders.keys()]) {
+        closeDecoder(display);
+    }
+}
+
+function resizeCanvas(width, height) {
+    videoWidth = width;
+    videoHeight = height;
+    if (canvas.width !== width || canvas.height !== height) {
+        console.log(`Resizing canvas to ${width}x${height}`);
+        canvas.width = width;
+        canvas.height = height;
+    }
+}
+
+function layoutDisplays() {
+    const shown = shownDisplays.map((i) => displays[i]).filter((d) => d);
+    if (shown.length === 0) {
+        originX = 0;
+        originY = 0;
+        return;
+    }
+    originX = Math.min(...shown.map((d) => d.x));
+    originY = Math.min(...shown.map((d) => d.y));
+    if (shown.length > 1) {
+        resizeCanvas(
+            Math.max(...shown.map((d) => d.x + d.width)) - originX,
+            Math.max(...shown.map((d) => d.y + d.height)) - originY);
+        ctx.clearRect(0, 0, canvas.width, canvas.height);
+    }
+}
+
+// Where a frame of the display goes on the canvas, null if it is not shown.
+function frameRect(display, width, height) {
+    if (displays.length === 0) {
+        // No geometry yet, the frame is the whole canvas.
+        resizeCanvas(width, height);
+        return { x: 0, y: 0, width, height };
+    }
+    if (!shownDisplays.includes(display)) {
+        return null;
+    }
+    const d = displays[display];
+    if (shownDisplays.length === 1 || !d) {
+        resizeCanvas(width, height);
+        return { x: 0, y: 0, width, height };
+    }
+    return { x: d.x - originX, y: d.y - originY, width: d.width, height: d.height };
+}
+
+function drawRgba(data) {
+    // Header: type (1 byte) + display (1 byte) + width (4 bytes) + height (4 bytes)
+    const dataView = new DataView(data);
+    const display = dataView.getUint8(1);
+    const width = dataView.getUint32(2, true);
+    const height = dataView.getUint32(6, true);
+    const rawData = new Uint8Array(data, 10);
+    if (rawData.length !== width * height * 4) {
+        // Ignore mismatch frames to avoid crash
+        return;
+    }
+    const rect = frameRect(display, width, height);
+    if (!rect) {
+        return;
+    }
+    const imageData = new ImageData(new Uint8ClampedArray(rawData), width, height);
+    if (rect.width === width && rect.height === height) {
+        ctx.putImageData(imageData, rect.x, rect.y);
+    } else {
+        createImageBitmap(imageData).then((bitmap) => {
+            ctx.drawImage(bitmap, rect.x, rect.y, rect.width, rect.height);
+            bitmap.close();
+        });
+    }
+}
+
+function drawFrame(display, frame) {
+    const rect = frameRect(display, frame.displayWidth, frame.displayHeight);
+    if (rect) {
+        ctx.drawImage(frame, rect.x, rect.y, rect.width, rect.height);
+    }
+    frame.close();
+}
+
+function decodeFrame(data) {
+    // Header: type (1 byte) + display (1 byte) + codec (1 byte) + key (1 byte)
+    // + pts in ms (8 bytes)
+    const dataView = new DataView(data);
+    const display = dataView.getUint8(1);
+    const codec = dataView.getUint8(2);
+    const key = dataView.getUint8(3) === 1;
+    const pts = Number(dataView.getBigInt64(4, true));
+    if (!CODECS[codec]) {
+        return;
+    }
+    let d = decoders.get(display);
+    if (!d || d.decoder.state === 'closed' || d.codec !== codec) {
+        // A new decoder starts with a key frame, the CLI sends one first.
+        if (!key) {
+            return;
+        }
+        closeDecoder(display);
+        const name = CODECS[codec].name;
+        const decoder = new VideoDecoder({
+            output: (frame) => drawFrame(display, frame),
+            error: (e) => {
+                // The CLI decodes this codec from now on.
+                console.error(`WebCodecs ${name} failed, falling back to RGBA:`, e);
+                supportedCodecs = supportedCodecs.filter((c) => c !== name);
+                closeDecoders();
+                sendRequest('set_video_codecs', { codecs: supportedCodecs });
+            },
+        });
+        decoder.configure({ codec: CODECS[codec].config, optimizeForLatency: true });
+        d = { decoder, codec };
+        decoders.set(display, d);
+    }
+    d.decoder.decode(new EncodedVideoChunk({
+        type: key ? 'key' : 'delta',
+        timestamp: pts * 1000,
+        data: new Uint8Array(data, 12),
+    }));
+}
+
+// Display selection, one display or all of them side by side.
+const displaySelect = document.getElementById('displaySelect');
+
+function updateDisplaySelect() {
+    if (!displaySelect) {
+        return;
+    }
+    displaySelect.innerHTML = '';
+    displays.forEach((d, i) => {
+        const option = document.createElement('option');
+        option.value = String(i);
+        option.textContent = `Display ${i + 1} (${d.width}x${d.height})`;
+        displaySelect.appendChild(option);
+    });
+    if (displays.length > 1) {
+        const option = document.createElement('option');
+        option.value = 'all';
+        option.textContent = 'All displays';
+        displaySelect.appendChild(option);
+    }
+    displaySelect.value = shownDisplays.length > 1 ? 'all' : String(shownDisplays[0] ?? 0);
+    displaySelect.style.display = displays.length > 1 ? '' : 'none';
+}
+
+function setDisplays(list, current) {
+    displays = list || [];
+    if (current !== undefined) {
+        shownDisplays = [current];
+    }
+    shownDisplays = shownDisplays.filter((i) => i < displays.length);
+    if (shownDisplays.length === 0 && displays.length > 0) {
+        shownDisplays = [0];
+    }
+    layoutDisplays();
+    updateDisplaySelect();
+}
+
+function showDisplays(list) {
+    shownDisplays = list;
+    closeDecoders();
+    layoutDisplays();
+    updateDisplaySelect();
+}
+
+if (displaySelect) {
+    displaySelect.addEventListener('change', () => {
+        if (displaySelect.value === 'all') {
+            const all = displays.map((_, i) => i);
+            sendRequest('capture_displays', { set: all });
+            showDisplays(all);
+        } else {
+            const display = parseInt(displaySelect.value);
+            sendRequest('switch_display', { display });
+            showDisplays([display]);
+        }
+    });
+}
+
+// Clipboard sync with the peer. The local clipboard is sent when the window
+// gets the focus and before a paste, the one of the peer is written when it
+// changes. Sent only with the permissions of the peer, like the CLI checks.
+const { clipboard, nativeImage } = require('electron');
+let clipboardSupported = false;
+const permissions = {};
+let lastClipboard = null;
+
+function readClipboard() {
+    const items = [];
+    const text = clipboard.readText();
+    if (text) {
+        items.push({ format: 'text', text });
+    }
+    const html = clipboard.readHTML();
+    if (html) {
+        items.push({ format: 'html', text: html });
+    }
+    const rtf = clipboard.readRTF();
+    if (rtf) {
+        items.push({ format: 'rtf', text: rtf });
+    }
+    const image = clipboard.readImage();
+    if (!image.isEmpty()) {
+        items.push({ format: 'image_png', data: image.toPNG().toString('base64') });
+    }
+    return items;
+}
+
+function syncClipboard() {
+    if (!clipboardSupported || permissions.clipboard === false || permissions.keyboard === false) {
+        return;
+    }
+    const items = readClipboard();
+    const signature = JSON.stringify(items);
+    if (items.length === 0 || signature === lastClipboard) {
+        return;
+    }
+    lastClipboard = signature;
+    sendRequest('set_clipboard', { items });
+}
+
+function writeClipboard(items) {
+    const data = {};
+    for (const item of items) {
+        if (item.format === 'text') {
+            data.text = item.text;
+        } else if (item.format === 'html') {
+            data.html = item.text;
+        } else if (item.format === 'rtf') {
+            data.rtf = item.text;
+        } else if (item.format === 'image_png') {
+            data.image = nativeImage.createFromBuffer(Buffer.from(item.data, 'base64'));
+        } else if (item.format === 'image_rgba') {
+            // Bitmaps of nativeImage are BGRA.
+            const pixels = Buffer.from(item.data, 'base64');
+            for (let i = 0; i + 3 < pixels.length; i += 4) {
+                const r = pixels[i];
+                pixels[i] = pixels[i + 2];
+                pixels[i + 2] = r;
+            }
+            data.image = nativeImage.createFromBitmap(pixels, { width: item.width, height: item.height });
+        }
+    }
+    clipboard.write(data);
+    // Not sent back to the peer.
+    lastClipboard = JSON.stringify(readClipboard());
+}
+
+window.addEventListener('focus', syncClipboard);
+
 // Retry connection logic
 function connect() {
     console.log("Connecting to WebSocket...");
-    ws = new WebSocket("ws://127.0.0.1:21121");
+    // The CLI passes the port and the token of its session.
+    const port = process.env.SDFDESK_BRIDGE_PORT;
+    const token = process.env.SDFDESK_BRIDGE_TOKEN || '';
+    ws = new WebSocket(`ws://127.0.0.1:${port}/?token=${encodeURIComponent(token)}`);
     ws.binaryType = "arraybuffer";
 
     ws.onopen = () => {
-        console.log("WebSocket connected to ws://127.0.0.1:21121");
+        console.log(`WebSocket connected to ws://127.0.0.1:${port}`);
         updateStatus(true);
+        closeDecoders();
+        sendHello();
     };
 
     ws.onerror = (error) => {
@@ -51,152 +397,189 @@ function connect() {
     };
 
     ws.onmessage = (event) => {
+        if (typeof event.data === 'string') {
+            handleMessage(event.data);
+            return;
+        }
         const dataView = new DataView(event.data);
         const type = dataView.getUint8(0);
 
         if (type === 0) { // Video Frame
-            // Parse header: width (4 bytes) + height (4 bytes)
-            // Offset starts at 1
-            const width = dataView.getUint32(1, true); // Little endian
-            const height = dataView.getUint32(5, true); // Little endian
-
-            videoWidth = width;
-            videoHeight = height;
-
-            // Resize canvas if needed
-            if (canvas.width !== width || canvas.height !== height) {
-                console.log(`Resizing canvas to ${width}x${height}`);
-                canvas.width = width;
-                canvas.height = height;
-            }
-
-            // Get raw RGBA data
-            // The header is 1 (type) + 8 (dims) = 9 bytes.
-            const rawData = new Uint8Array(event.data, 9);
-            const imageData = ctx.createImageData(width, height);
+            drawRgba(event.data);
+        } else if (type === 2) { // Encoded Video Frame
+            decodeFrame(event.data);
+        }
+    };
 
-            if (rawData.length === imageData.data.length) {
-                // Copy data
-                imageData.data.set(rawData);
-            } else {
-                // Ignore mismatch frames to avoid crash
-                return;
+    // JSON messages of the protocol
+    function handleMessage(text) {
+        let msg;
+        try {
+            msg = JSON.parse(text);
+        } catch (e) {
+            console.error("Failed to parse JSON message", e);
+            return;
+        }
+        if (msg.type === 'hello') {
+            console.log(`Bridge protocol ${msg.version}, CLI ${msg.server_version}`);
+            if (msg.capabilities.includes('unlock_password')) {
+                showUnlockPw();
             }
-
-            ctx.putImageData(imageData, 0, 0);
-
-        } else if (type === 1) { // JSON Message (Cursor)
-            const textDecoder = new TextDecoder();
-            const jsonText = textDecoder.decode(new Uint8Array(event.data, 1));
-            try {
-                const msg = JSON.parse(jsonText);
-                if (msg.type === 'cursor_data') {
-                    // msg.data is base64 encoded RAW PIXELS (RGBA), NOT a PNG file.
-                    const binaryString = atob(msg.data);
-                    const len = binaryString.length;
-                    const bytes = new Uint8Array(len);
-                    for (let i = 0; i < len; i++) {
-                        bytes[i] = binaryString.charCodeAt(i);
+            clipboardSupported = msg.capabilities.includes('clipboard');
+            sendRequest('get_session', {}, (response) => {
+                if (response.result) {
+                    Object.assign(permissions, response.result.permissions);
+                    if (document.hasFocus()) {
+                        syncClipboard();
                     }
+                }
+                if (response.result && response.result.peer_info) {
+                    console.log('Session:', response.result);
+                    setDisplays(response.result.peer_info.displays, response.result.current_display);
+                }
+            });
+            return;
+        }
+        if (msg.type === 'response') {
+            onResponse(msg);
+            return;
+        }
+        if (msg.type === 'error') {
+            console.error('Bridge error:', msg.message);
+            return;
+        }
+        if (msg.type !== 'event') {
+            return;
+        }
+        const data = msg.data || {};
+        if (msg.event === 'cursor_data') {
+            // The colors are base64 encoded RAW PIXELS (RGBA), NOT a PNG file.
+            const binaryString = atob(data.colors);
+            const len = binaryString.length;
+            const bytes = new Uint8Array(len);
+            for (let i = 0; i < len; i++) {
+                bytes[i] = binaryString.charCodeAt(i);
+            }
 
-                    // Create a temp canvas to generate the image
-                    const tempCanvas = document.createElement('canvas');
-                    tempCanvas.width = msg.width;
-                    tempCanvas.height = msg.height;
-                    const tempCtx = tempCanvas.getContext('2d');
-                    const imgData = tempCtx.createImageData(msg.width, msg.height);
-
-                    // Check size
-                    if (bytes.length === imgData.data.length) {
-                        imgData.data.set(bytes);
-                        tempCtx.putImageData(imgData, 0, 0);
-                        cursorOverlay.src = tempCanvas.toDataURL();
-                        cursorOverlay.style.display = 'block';
-                        cursorOverlay.dataset.hotx = msg.hotx;
-                        cursorOverlay.dataset.hoty = msg.hoty;
-                    } else {
-                        console.warn("Cursor data size mismatch", bytes.length, imgData.data.length);
-                    }
+            // Create a temp canvas to generate the image
+            const tempCanvas = document.createElement('canvas');
+            tempCanvas.width = data.width;
+            tempCanvas.height = data.height;
+            const tempCtx = tempCanvas.getContext('2d');
+            const imgData = tempCtx.createImageData(data.width, data.height);
+
+            // Check size
+            if (bytes.length === imgData.data.length) {
+                imgData.data.set(bytes);
+                tempCtx.putImageData(imgData, 0, 0);
+                cursorOverlay.src = tempCanvas.toDataURL();
+                cursorOverlay.style.display = 'block';
+                cursorOverlay.dataset.hotx = data.hotx;
+                cursorOverlay.dataset.hoty = data.hoty;
+            } else {
+                console.warn("Cursor data size mismatch", bytes.length, imgData.data.length);
+            }
 
-                } else if (msg.type === 'cursor_position') {
-                    const hotx = parseInt(cursorOverlay.dataset.hotx || '0');
-                    const hoty = parseInt(cursorOverlay.dataset.hoty || '0');
-
-                    const rect = canvas.getBoundingClientRect();
-                    const scaleX = rect.width / canvas.width;
-                    const scaleY = rect.height / canvas.height;
-
-                    const screenX = rect.left + (msg.x * scaleX) - (hotx * scaleX);
-                    const screenY = rect.top + (msg.y * scaleY) - (hoty * scaleY);
-
-                    cursorOverlay.style.left = `${screenX}px`;
-                    cursorOverlay.style.top = `${screenY}px`;
-                } else if (msg.type === 'error') {
-                    console.error('Connection error:', msg);
-
-                    const errorDiv = document.createElement('div');
-                    errorDiv.style.cssText = `
-                        position: fixed;
-                        top: 50%;
-                        left: 50%;
-                        transform: translate(-50%, -50%);
-                        background-color: rgba(220, 38, 38, 0.95);
-                        color: white;
-                        padding: 30px;
-                        border-radius: 10px;
-                        z-index: 10000;
-                        max-width: 400px;
-                        text-align: center;
-                        box-shadow: 0 4px 20px rgba(0,0,0,0.5);
-                        font-family: Arial, sans-serif;
-                    `;
-
-                    errorDiv.innerHTML = `
-                        <h2 style="margin: 0 0 10px 0; font-size: 20px;">${msg.title || 'Error'}</h2>
-                        <p style="margin: 0 0 20px 0; font-size: 16px;">${msg.message || 'An error occurred'}</p>
-                        <button onclick="this.parentElement.remove()" style="
-                            padding: 10px 30px;
-                            font-size: 14px;
-                            border: none;
-                            border-radius: 5px;
-                            background-color: white;
-                            color: #dc2626;
-                            cursor: pointer;
-                            font-weight: bold;
-                        ">Close</button>
-                    `;
-
-                    document.body.appendChild(errorDiv);
-
-                    setTimeout(() => {
-                        if (errorDiv.parentNode) {
-                            errorDiv.remove();
-                        }
-                    }, 15000);
-                }
-            } catch (e) {
-                console.error("Failed to parse JSON message", e);
+        } else if (msg.event === 'cursor_position') {
+            const hotx = parseInt(cursorOverlay.dataset.hotx || '0');
+            const hoty = parseInt(cursorOverlay.dataset.hoty || '0');
+
+            const rect = canvas.getBoundingClientRect();
+            const scaleX = rect.width / canvas.width;
+            const scaleY = rect.height / canvas.height;
+
+            const screenX = rect.left + ((data.x - originX) * scaleX) - (hotx * scaleX);
+            const screenY = rect.top + ((data.y - originY) * scaleY) - (hoty * scaleY);
+
+            cursorOverlay.style.left = `${screenX}px`;
+            cursorOverlay.style.top = `${screenY}px`;
+        } else if (msg.event === 'permission') {
+            permissions[data.name] = data.value;
+        } else if (msg.event === 'clipboard') {
+            writeClipboard(data.items);
+        } else if (msg.event === 'peer_info') {
+            setDisplays(data.displays, data.current_display);
+        } else if (msg.event === 'displays') {
+            setDisplays(data.displays);
+        } else if (msg.event === 'switch_display') {
+            const d = displays[data.display];
+            if (d) {
+                Object.assign(d, { x: data.x, y: data.y, width: data.width, height: data.height });
             }
+            if (shownDisplays.length <= 1) {
+                shownDisplays = [data.display];
+            }
+            layoutDisplays();
+            updateDisplaySelect();
+        } else if (msg.event === 'current_display') {
+            if (shownDisplays.length <= 1 && data.display < displays.length) {
+                shownDisplays = [data.display];
+                layoutDisplays();
+                updateDisplaySelect();
+            }
+        } else if (msg.event === 'msgbox') {
+            console.error('Connection error:', data);
+
+            const errorDiv = document.createElement('div');
+            errorDiv.style.cssText = `
+                position: fixed;
+                top: 50%;
+                left: 50%;
+                transform: translate(-50%, -50%);
+                background-color: rgba(220, 38, 38, 0.95);
+                color: white;
+                padding: 30px;
+                border-radius: 10px;
+                z-index: 10000;
+                max-width: 400px;
+                text-align: center;
+                box-shadow: 0 4px 20px rgba(0,0,0,0.5);
+                font-family: Arial, sans-serif;
+            `;
+
+            errorDiv.innerHTML = `
+                <h2 style="margin: 0 0 10px 0; font-size: 20px;">${data.title || 'Error'}</h2>
+                <p style="margin: 0 0 20px 0; font-size: 16px;">${data.text || 'An error occurred'}</p>
+                <button onclick="this.parentElement.remove()" style="
+                    padding: 10px 30px;
+                    font-size: 14px;
+                    border: none;
+                    border-radius: 5px;
+                    background-color: white;
+                    color: #dc2626;
+                    cursor: pointer;
+                    font-weight: bold;
+                ">Close</button>
+            `;
+
+            document.body.appendChild(errorDiv);
+
+            setTimeout(() => {
+                if (errorDiv.parentNode) {
+                    errorDiv.remove();
+                }
+            }, 15000);
         }
-    };
+    }
 
     // Input handling
     function sendInput(event) {
         if (ws.readyState === WebSocket.OPEN) {
-            ws.send(JSON.stringify(event));
+            ws.send(JSON.stringify({ type: 'input', event }));
         }
     }
 
     function getScaledCoordinates(e) {
         const rect = canvas.getBoundingClientRect();
-        if (videoWidth === 0 || videoHeight === 0) return { x: Math.round(e.offsetX), y: Math.round(e.offsetY) };
+        // Positions on the peer, the canvas starts at (originX, originY).
+        if (videoWidth === 0 || videoHeight === 0) return { x: originX + Math.round(e.offsetX), y: originY + Math.round(e.offsetY) };
 
         const scaleX = videoWidth / rect.width;
         const scaleY = videoHeight / rect.height;
 
         return {
-            x: Math.round(e.offsetX * scaleX),
-            y: Math.round(e.offsetY * scaleY)
+            x: originX + Math.round(e.offsetX * scaleX),
+            y: originY + Math.round(e.offsetY * scaleY)
         };
     }
 
@@ -252,6 +635,10 @@ function connect() {
         // Don't capture input field key events
         if (e.target.tagName === 'INPUT') return;
 
+        // The peer pastes what is copied here.
+        if ((e.ctrlKey || e.metaKey) && e.key.toLowerCase() === 'v') {
+            syncClipboard();
+        }
         sendInput({
             type: 'keydown',
             key: e.key
@@ -282,7 +669,15 @@ function connect() {
 
     // Send ID (username) - use env var if provided
     const unlockId = process.env.SDFDESK_UNLOCK_ID || '';
-    const unlockPw = process.env.SDFDESK_UNLOCK_PW || '';
+    // The password itself stays in the CLI, which types it on request. The
+    // CLI tells whether it has one in its hello.
+    let unlockPw = false;
+    function showUnlockPw() {
+        unlockPw = true;
+        document.getElementById('dividerPW').style.display = 'block';
+        document.getElementById('btnSendPW').style.display = 'inline-block';
+        console.log('Unlock PW provided via CLI, showing Send PW button');
+    }
 
     // Show buttons if credentials were provided via CLI
     if (unlockId) {
@@ -290,11 +685,6 @@ function connect() {
         document.getElementById('btnSendID').style.display = 'inline-block';
         console.log('Unlock ID provided via CLI, showing Send UserId button');
     }
-    if (unlockPw) {
-        document.getElementById('dividerPW').style.display = 'block';
-        document.getElementById('btnSendPW').style.display = 'inline-block';
-        console.log('Unlock PW provided via CLI, showing Send PW button');
-    }
 
     document.getElementById('btnSendID').addEventListener('click', () => {
         if (!unlockId) {
@@ -317,9 +707,7 @@ function connect() {
         }
         console.log('Sending Password from CLI...');
         sendInput({
-            type: 'send_text',
-            text: unlockPw,
-            enter: true  // Press Enter after password
+            type: 'send_unlock_pw'  // Typed by the CLI, followed by Enter
         });
     });
 }
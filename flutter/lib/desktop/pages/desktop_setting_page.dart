@@ -1080,8 +1080,7 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
                             if (value ==
                                     passwordValues[passwordKeys
                                         .indexOf(kUsePermanentPassword)] &&
-                                (await bind.mainGetPermanentPassword())
-                                    .isEmpty) {
+                                !(await bind.mainHasPermanentPassword())) {
                               setPasswordDialog(notEmptyCallback: callback);
                             } else {
                               await callback();
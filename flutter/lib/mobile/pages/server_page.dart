@@ -148,7 +148,7 @@ class _DropDownAction extends StatelessWidget {
             }
 
             if (value == kUsePermanentPassword &&
-                (await bind.mainGetPermanentPassword()).isEmpty) {
+                !(await bind.mainHasPermanentPassword())) {
               setPasswordDialog(notEmptyCallback: callback);
             } else {
               callback();
@@ -1163,6 +1163,10 @@ class RustdeskImpl {
     return Future.value('');
   }
 
+  Future<bool> mainHasPermanentPassword({dynamic hint}) {
+    return Future.value(false);
+  }
+
   Future<String> mainGetFingerprint({dynamic hint}) {
     return Future.value('');
   }
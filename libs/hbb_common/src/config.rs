@@ -16,6 +16,7 @@ use regex::Regex;
 use serde as de;
 use serde_derive::{Deserialize, Serialize};
 use serde_json;
+use sha2::{Digest, Sha256};
 use sodiumoxide::base64;
 use sodiumoxide::crypto::sign;
 
@@ -186,6 +187,14 @@ pub struct Config {
     password: String,
     #[serde(default, deserialize_with = "deserialize_string")]
     salt: String,
+    // base64 of SHA256(password + salt), when the permanent password is
+    // provisioned without its plain text, see `set_permanent_password_hash`.
+    #[serde(
+        default,
+        skip_serializing_if = "String::is_empty",
+        deserialize_with = "deserialize_string"
+    )]
+    password_hash: String,
     #[serde(default, deserialize_with = "deserialize_keypair")]
     key_pair: KeyPair, // sk, pk
     #[serde(default, deserialize_with = "deserialize_bool")]
@@ -567,6 +576,10 @@ impl Config {
         let (password, _, store1) = decrypt_str_or_original(&config.password, PASSWORD_ENC_VERSION);
         config.password = password;
         store |= store1;
+        let (password_hash, _, store1) =
+            decrypt_str_or_original(&config.password_hash, PASSWORD_ENC_VERSION);
+        config.password_hash = password_hash;
+        store |= store1;
         let mut id_valid = false;
         let (id, encrypted, store2) = decrypt_str_or_original(&config.enc_id, PASSWORD_ENC_VERSION);
         if encrypted {
@@ -608,6 +621,8 @@ impl Config {
         let mut config = self.clone();
         config.password =
             encrypt_str_or_original(&config.password, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN);
+        config.password_hash =
+            encrypt_str_or_original(&config.password_hash, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN);
         config.enc_id = encrypt_str_or_original(&config.id, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN);
         config.id = "".to_owned();
         Config::store_(&config, "");
@@ -1091,17 +1106,56 @@ impl Config {
             return;
         }
         let mut config = CONFIG.write().unwrap();
-        if password == config.password {
+        if password == config.password && config.password_hash.is_empty() {
             return;
         }
         config.password = password.into();
+        config.password_hash = "".into();
         config.store();
         Self::clear_trusted_devices();
     }
 
+    /// Sets the permanent password by SHA256(password + salt), which is all the
+    /// login needs, so provisioning files do not carry the password itself.
+    pub fn set_permanent_password_hash(salt: &str, hash: &[u8]) {
+        let hash = base64::encode(hash, base64::Variant::Original);
+        let mut config = CONFIG.write().unwrap();
+        if config.password.is_empty() && config.salt == salt && config.password_hash == hash {
+            return;
+        }
+        config.password = "".into();
+        config.salt = salt.into();
+        config.password_hash = hash;
+        config.store();
+        Self::clear_trusted_devices();
+    }
+
+    /// SHA256(password + salt) of the permanent password, empty if there is none.
+    pub fn get_permanent_password_hash() -> Vec<u8> {
+        let password = Self::get_permanent_password();
+        if !password.is_empty() {
+            let mut hasher = Sha256::new();
+            hasher.update(&password);
+            hasher.update(&Self::get_salt());
+            return hasher.finalize()[..].into();
+        }
+        let hash = CONFIG.read().unwrap().password_hash.clone();
+        base64::decode(&hash, base64::Variant::Original).unwrap_or_default()
+    }
+
+    pub fn has_permanent_password() -> bool {
+        !Self::get_permanent_password().is_empty()
+            || !CONFIG.read().unwrap().password_hash.is_empty()
+    }
+
+    /// Empty if the permanent password is set by its hash only, which replaces
+    /// the preset one too.
     pub fn get_permanent_password() -> String {
-        let mut password = CONFIG.read().unwrap().password.clone();
-        if password.is_empty() {
+        let (mut password, has_hash) = {
+            let config = CONFIG.read().unwrap();
+            (config.password.clone(), !config.password_hash.is_empty())
+        };
+        if password.is_empty() && !has_hash {
             if let Some(v) = HARD_SETTINGS.read().unwrap().get("password") {
                 password = v.to_owned();
             }
@@ -1109,18 +1163,28 @@ impl Config {
         password
     }
 
+    /// Refused while the permanent password is set by its hash, which is only
+    /// valid with the salt it was made with.
     pub fn set_salt(salt: &str) {
         let mut config = CONFIG.write().unwrap();
         if salt == config.salt {
             return;
         }
+        if !config.password_hash.is_empty() {
+            log::warn!("Salt not changed, the permanent password hash depends on it");
+            return;
+        }
         config.salt = salt.into();
         config.store();
     }
 
     pub fn get_salt() -> String {
-        let mut salt = CONFIG.read().unwrap().salt.clone();
-        if salt.is_empty() {
+        let (mut salt, has_hash) = {
+            let config = CONFIG.read().unwrap();
+            (config.salt.clone(), !config.password_hash.is_empty())
+        };
+        // A hash may have been made with an empty salt.
+        if salt.is_empty() && !has_hash {
             salt = Config::get_auto_password(6);
             Config::set_salt(&salt);
         }
@@ -1763,6 +1827,25 @@ pub struct LocalConfig {
     // Various data for flutter ui
     #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
     ui_flutter: HashMap<String, String>,
+    #[serde(default, deserialize_with = "deserialize_hashmap_forward_profiles")]
+    forward_profiles: HashMap<String, ForwardProfile>,
+}
+
+/// A named set of port forwards to one peer, the peer of its tunnels.
+/// The password is not kept here, the one remembered in the peer's `PeerConfig` is used.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ForwardProfile {
+    // Port-forward specs, in the same format as the command line
+    #[serde(default, deserialize_with = "deserialize_vec_string")]
+    pub tunnels: Vec<String>,
+    // Remember the password in `PeerConfig` after the first successful login
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub remember_password: bool,
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub force_relay: bool,
+    // Sources (IPs or CIDRs) allowed to connect to the local listeners, all if empty
+    #[serde(default, deserialize_with = "deserialize_vec_string")]
+    pub allow_from: Vec<String>,
 }
 
 impl LocalConfig {
@@ -1824,6 +1907,32 @@ impl LocalConfig {
         LOCAL_CONFIG.read().unwrap().fav.clone()
     }
 
+    pub fn get_forward_profiles() -> HashMap<String, ForwardProfile> {
+        LOCAL_CONFIG.read().unwrap().forward_profiles.clone()
+    }
+
+    pub fn get_forward_profile(name: &str) -> Option<ForwardProfile> {
+        LOCAL_CONFIG.read().unwrap().forward_profiles.get(name).cloned()
+    }
+
+    pub fn set_forward_profile(name: String, profile: ForwardProfile) {
+        let mut config = LOCAL_CONFIG.write().unwrap();
+        if config.forward_profiles.get(&name) == Some(&profile) {
+            return;
+        }
+        config.forward_profiles.insert(name, profile);
+        config.store();
+    }
+
+    pub fn remove_forward_profile(name: &str) -> bool {
+        let mut config = LOCAL_CONFIG.write().unwrap();
+        if config.forward_profiles.remove(name).is_none() {
+            return false;
+        }
+        config.store();
+        true
+    }
+
     pub fn get_option(k: &str) -> String {
         get_or(
             &OVERWRITE_LOCAL_SETTINGS,
@@ -2358,6 +2467,7 @@ deserialize_default!(deserialize_size, Size);
 deserialize_default!(deserialize_hashmap_string_string, HashMap<String, String>);
 deserialize_default!(deserialize_hashmap_string_bool,  HashMap<String, bool>);
 deserialize_default!(deserialize_hashmap_resolutions, HashMap<String, Resolution>);
+deserialize_default!(deserialize_hashmap_forward_profiles, HashMap<String, ForwardProfile>);
 
 #[inline]
 fn get_or(
@@ -2531,6 +2641,10 @@ pub mod keys {
     pub const OPTION_DIRECT_SERVER: &str = "direct-server";
     pub const OPTION_DIRECT_ACCESS_PORT: &str = "direct-access-port";
     pub const OPTION_WHITELIST: &str = "whitelist";
+    pub const OPTION_TUNNEL_ALLOWLIST: &str = "tunnel-allowlist";
+    pub const OPTION_TUNNEL_DENYLIST: &str = "tunnel-denylist";
+    pub const OPTION_ALLOW_TUNNEL_GATEWAY_PORTS: &str = "allow-tunnel-gateway-ports";
+    pub const OPTION_AUDIT_LOG: &str = "audit-log";
     pub const OPTION_ALLOW_AUTO_DISCONNECT: &str = "allow-auto-disconnect";
     pub const OPTION_AUTO_DISCONNECT_TIMEOUT: &str = "auto-disconnect-timeout";
     pub const OPTION_ALLOW_ONLY_CONN_WINDOW_OPEN: &str = "allow-only-conn-window-open";
@@ -2730,6 +2844,10 @@ pub mod keys {
         OPTION_DIRECT_SERVER,
         OPTION_DIRECT_ACCESS_PORT,
         OPTION_WHITELIST,
+        OPTION_TUNNEL_ALLOWLIST,
+        OPTION_TUNNEL_DENYLIST,
+        OPTION_ALLOW_TUNNEL_GATEWAY_PORTS,
+        OPTION_AUDIT_LOG,
         OPTION_ALLOW_AUTO_DISCONNECT,
         OPTION_AUTO_DISCONNECT_TIMEOUT,
         OPTION_ALLOW_ONLY_CONN_WINDOW_OPEN,
@@ -3087,6 +3205,54 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_forward_profiles_deserialize() {
+        let cfg = toml::from_str::<LocalConfig>(
+            r#"
+            remote_id = "123"
+            [forward_profiles.db]
+            tunnels = ["123456789:5432:5432", "socks:123456789:1080"]
+            force_relay = true
+            [forward_profiles.bad]
+            tunnels = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.remote_id, "123");
+        assert_eq!(
+            cfg.forward_profiles.get("db"),
+            Some(&ForwardProfile {
+                tunnels: vec![
+                    "123456789:5432:5432".to_owned(),
+                    "socks:123456789:1080".to_owned()
+                ],
+                remember_password: false,
+                force_relay: true,
+                allow_from: vec![],
+            })
+        );
+        assert_eq!(cfg.forward_profiles.get("bad"), Some(&ForwardProfile::default()));
+    }
+
+    #[test]
+    fn test_salt_of_password_hash() {
+        let saved = Config::get();
+        let hash = [1u8; 32];
+        Config::set_permanent_password_hash("salt1", &hash);
+        Config::set_salt("salt2");
+        assert_eq!(Config::get_salt(), "salt1");
+        assert_eq!(Config::get_permanent_password_hash(), hash.to_vec());
+        // no new salt for a hash made with an empty one
+        Config::set_permanent_password_hash("", &hash);
+        assert_eq!(Config::get_salt(), "");
+        assert_eq!(Config::get_permanent_password_hash(), hash.to_vec());
+        // a plain password drops the hash, the salt can change again
+        Config::set_permanent_password("password");
+        Config::set_salt("salt2");
+        assert_eq!(Config::get_salt(), "salt2");
+        Config::set(saved);
+    }
+
     #[test]
     fn test_store_load() {
         let peerconfig_id = "123456789";
@@ -362,7 +362,7 @@ impl Proxy {
     ) -> ResultType<tokio::net::TcpStream> {
         let stream = super::timeout(
             self.ms_timeout,
-            crate::tcp::new_socket(local, true)?.connect(proxy),
+            crate::tcp::set_keepalive(crate::tcp::new_socket(local, true)?).connect(proxy),
         )
         .await??;
         stream.set_nodelay(true).ok();
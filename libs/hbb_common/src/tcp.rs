@@ -80,6 +80,29 @@ pub(crate) fn new_socket(addr: std::net::SocketAddr, reuse: bool) -> Result<TcpS
     Ok(socket)
 }
 
+const KEEPALIVE_IDLE: std::time::Duration = std::time::Duration::from_secs(30);
+
+// Raw streams (port forwarding) have no application level heartbeat, let the
+// OS detect a peer that is gone without closing the connection.
+pub(crate) fn set_keepalive(socket: TcpSocket) -> TcpSocket {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::{FromRawFd, IntoRawFd};
+        let sock2 = unsafe { socket2::Socket::from_raw_fd(socket.into_raw_fd()) };
+        sock2.set_keepalive(Some(KEEPALIVE_IDLE)).ok();
+        unsafe { TcpSocket::from_raw_fd(sock2.into_raw_fd()) }
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::prelude::{FromRawSocket, IntoRawSocket};
+        let sock2 = unsafe { socket2::Socket::from_raw_socket(socket.into_raw_socket()) };
+        sock2.set_keepalive(Some(KEEPALIVE_IDLE)).ok();
+        unsafe { TcpSocket::from_raw_socket(sock2.into_raw_socket()) }
+    }
+    #[cfg(not(any(unix, windows)))]
+    socket
+}
+
 impl FramedStream {
     pub async fn new<T: ToSocketAddrs + std::fmt::Display>(
         remote_addr: T,
@@ -93,6 +116,7 @@ impl FramedStream {
                 crate::config::Config::get_any_listen_addr(remote_addr.is_ipv4())
             };
             if let Ok(socket) = new_socket(local, true) {
+                let socket = set_keepalive(socket);
                 if let Ok(Ok(stream)) =
                     super::timeout(ms_timeout, socket.connect(remote_addr)).await
                 {
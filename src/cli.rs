@@ -1,4 +1,4 @@
-use crate::client::*;
+use crate::{cli_secret::Secret, client::*};
 use async_trait::async_trait;
 use hbb_common::{
     config::PeerConfig,
@@ -8,36 +8,71 @@ use hbb_common::{
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
+    serde_json,
     tokio::{self, sync::mpsc},
-    Stream,
+    ResultType, Stream,
 };
-use std::sync::{Arc, RwLock};
+use cidr_utils::cidr::IpCidr;
+use std::{
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+use zeroize::Zeroizing;
+
+/// The password of a peer. Once a login succeeded only its salted hash is
+/// kept, the way `PeerConfig` stores a remembered password, and later logins
+/// use the hash.
+#[derive(Default)]
+struct Password {
+    plain: Secret,
+    hash: Zeroizing<Vec<u8>>,
+}
 
 #[derive(Clone)]
 pub struct Session {
     id: String,
     lc: Arc<RwLock<LoginConfigHandler>>,
     sender: mpsc::UnboundedSender<Data>,
-    password: String,
+    /// Shared by the forks, so that a re-entered password is used by all of them.
+    password: Arc<RwLock<Password>>,
+    /// Whether the password given on login is remembered in `PeerConfig`.
+    remember: bool,
 }
 
 impl Session {
     pub fn new(id: &str, sender: mpsc::UnboundedSender<Data>, key: &str) -> Self {
-        let mut password = "".to_owned();
+        Self::new_with_conn_type(id, sender, key, ConnType::DEFAULT_CONN)
+    }
+
+    pub fn new_with_conn_type(
+        id: &str,
+        sender: mpsc::UnboundedSender<Data>,
+        key: &str,
+        conn_type: ConnType,
+    ) -> Self {
+        let mut password = Secret::default();
         if !key.is_empty() {
-            password = key.to_owned();
+            password = Secret::new(key.to_owned());
         } else if PeerConfig::load(id).password.is_empty() {
-            password = rpassword::prompt_password("Enter password: ").unwrap();
+            match crate::cli_secret::prompt("Enter password: ") {
+                Ok(v) => password = v,
+                // The login asks again, or fails without a password.
+                Err(err) => log::error!("Failed to read the password: {}", err),
+            }
         }
         let session = Self {
             id: id.to_owned(),
             sender,
-            password,
+            password: Arc::new(RwLock::new(Password {
+                plain: password,
+                ..Default::default()
+            })),
+            remember: true,
             lc: Default::default(),
         };
         session.lc.write().unwrap().initialize(
             id.to_owned(),
-            ConnType::DEFAULT_CONN,
+            conn_type,
             None,
             false,
             None,
@@ -46,6 +81,40 @@ impl Session {
         );
         session
     }
+
+    fn password(&self) -> Secret {
+        self.password.read().unwrap().plain.clone()
+    }
+
+    /// A session to the same peer with the same password, but with its own
+    /// login state and UI data, so that several tunnels can log in at the
+    /// same time without seeing each other's replies.
+    fn fork(&self) -> (Self, mpsc::UnboundedReceiver<Data>) {
+        let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+        let session = Self {
+            id: self.id.clone(),
+            sender,
+            password: self.password.clone(),
+            remember: self.remember,
+            lc: Default::default(),
+        };
+        session.lc.write().unwrap().initialize(
+            self.id.clone(),
+            ConnType::DEFAULT_CONN,
+            None,
+            false,
+            None,
+            None,
+            None,
+        );
+        {
+            let lc = self.lc.read().unwrap();
+            let mut forked = session.lc.write().unwrap();
+            forked.force_relay = lc.force_relay;
+            forked.remember = lc.remember;
+        }
+        (session, receiver)
+    }
 }
 
 #[async_trait]
@@ -54,29 +123,47 @@ impl Interface for Session {
         return self.lc.clone();
     }
 
+    fn fork_session(&self) -> Option<(Self, mpsc::UnboundedReceiver<Data>)> {
+        Some(self.fork())
+    }
+
     fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
 
     fn msgbox(&self, msgtype: &str, title: &str, text: &str, link: &str) {
         match msgtype {
             "input-password" => {
                 self.sender
-                    .send(Data::Login(("".to_owned(), "".to_owned(), self.password.clone(), true)))
+                    .send(Data::Login(("".to_owned(), "".to_owned(), self.password().to_string(), self.remember)))
                     .ok();
             }
+            "re-input-password" if crate::cli_events::is_enabled() => {
+                // Scripts can not answer a prompt, the login error is reported already.
+                self.sender.send(Data::Close).ok();
+            }
             "re-input-password" => {
                 log::error!("{}: {}", title, text);
-                match rpassword::prompt_password("Enter password: ") {
+                match crate::cli_secret::prompt("Enter password: ") {
                     Ok(password) => {
-                        let login_data = Data::Login(("".to_owned(), "".to_owned(), password, true));
+                        *self.password.write().unwrap() = Password {
+                            plain: password.clone(),
+                            ..Default::default()
+                        };
+                        let login_data = Data::Login(("".to_owned(), "".to_owned(), password.to_string(), self.remember));
                         self.sender.send(login_data).ok();
                     }
                     Err(e) => {
                         log::error!("reinput password failed, {:?}", e);
+                        self.sender.send(Data::Close).ok();
                     }
                 }
             }
             msg if msg.contains("error") => {
                 log::error!("{}: {}: {}", msgtype, title, text);
+                crate::cli_events::set_exit_code(crate::cli_events::exit_code_of(text));
+                crate::cli_events::emit(
+                    "error",
+                    serde_json::json!({ "id": self.id, "title": title, "error": text }),
+                );
             }
             _ => {
                 log::info!("{}: {}: {}", msgtype, title, text);
@@ -85,18 +172,62 @@ impl Interface for Session {
     }
 
     fn handle_login_error(&self, err: &str) -> bool {
+        crate::cli_events::set_exit_code(crate::cli_events::exit_code_of(err));
+        crate::cli_events::emit(
+            "login_error",
+            serde_json::json!({ "id": self.id, "error": err }),
+        );
         handle_login_error(self.lc.clone(), err, self)
     }
 
     fn handle_peer_info(&self, pi: PeerInfo) {
-        self.lc.write().unwrap().handle_peer_info(&pi);
+        let direct = self.lc.read().unwrap().direct;
+        crate::cli_events::emit(
+            "connected",
+            serde_json::json!({
+                "id": self.id,
+                "conn": if direct == Some(true) { "direct" } else { "relay" },
+            }),
+        );
+        crate::cli_events::emit(
+            "peer_info",
+            serde_json::json!({
+                "id": self.id,
+                "username": pi.username,
+                "hostname": pi.hostname,
+                "platform": pi.platform,
+                "version": pi.version,
+            }),
+        );
+        let hash = self.lc.read().unwrap().password.clone();
+        if !hash.is_empty() {
+            *self.password.write().unwrap() = Password {
+                hash: Zeroizing::new(hash),
+                ..Default::default()
+            };
+        }
+        let mut lc = self.lc.write().unwrap();
+        lc.handle_peer_info(&pi);
+        lc.peer_info = Some(pi);
     }
 
-    async fn handle_hash(&self, pass: &str, hash: Hash, peer: &mut Stream) {
+    async fn handle_hash(&self, _pass: &str, hash: Hash, peer: &mut Stream) {
         log::info!(
             "password={}",
             hbb_common::password_security::temporary_password()
         );
+        // Rather than the password the connection was started with, another
+        // tunnel to the peer may have re-entered it or logged in with it since.
+        let (pass, last) = {
+            let password = self.password.read().unwrap();
+            (password.plain.clone(), password.hash.clone())
+        };
+        {
+            let mut lc = self.lc.write().unwrap();
+            if lc.password.is_empty() {
+                lc.password = last.to_vec();
+            }
+        }
         handle_hash(self.lc.clone(), &pass, hash, self, peer).await;
     }
 
@@ -128,21 +259,56 @@ impl Interface for Session {
     }
 }
 
+/// Connect to the peer and log in, with the connection type of the session.
+/// Returns `None` if the login is given up, e.g. on a wrong password.
+/// The health check connection, if any, lives as long as the returned sender.
+pub(crate) async fn connect_and_login(
+    handler: &Session,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+) -> ResultType<Option<(Stream, Option<mpsc::UnboundedSender<()>>)>> {
+    let conn_type = handler.lc.read().unwrap().conn_type;
+    crate::port_forward::try_connect_and_login(
+        &handler.id,
+        &handler.password(),
+        receiver,
+        handler.clone(),
+        None,
+        key,
+        token,
+        conn_type,
+        &mut Vec::new(),
+    )
+    .await
+}
+
+/// For a client started by hand. Printed rather than logged, the token does not
+/// belong in the log files.
+fn print_bridge_endpoint(endpoint: &crate::electron_interface::BridgeEndpoint) {
+    eprintln!("Start the client with these environment variables:");
+    for (k, v) in endpoint.envs() {
+        eprintln!("  {}={}", k, v);
+    }
+}
+
 #[tokio::main(flavor = "current_thread")]
-pub async fn connect_test(id: &str, key: String, token: String, unlock_id: String, unlock_pw: String, rdp_id: String, rdp_pw: String) {
+pub async fn connect_test(id: &str, key: Secret, token: String, unlock_id: String, unlock_pw: Secret, rdp_id: String, rdp_pw: Secret, bridge_port: u16) {
     // Set RDP credentials for headless mode (will be included in LoginRequest)
     if !rdp_id.is_empty() && !rdp_pw.is_empty() {
-        crate::client::set_client_rdp_credentials(rdp_id.clone(), rdp_pw.clone());
+        crate::client::set_client_rdp_credentials(rdp_id.clone(), rdp_pw);
         log::info!("RDP credentials set for headless mode");
     }
     
-    let port = 21121; // Changed from 21118 to avoid conflict with direct server
     // Create a shared sender that will be populated by io_loop
     let sender: Arc<RwLock<Option<mpsc::UnboundedSender<Data>>>> = Default::default();
     
-    match crate::electron_interface::start_electron_server(port, sender.clone()).await {
-        Ok(handler) => {
-            log::info!("Electron server started on port {}", port);
+    match crate::electron_interface::start_electron_server(bridge_port, sender.clone()).await {
+        Ok((handler, endpoint)) => {
+            log::info!("Electron server started on port {}", endpoint.port);
+            if !unlock_pw.is_empty() {
+                crate::electron_interface::set_unlock_password(unlock_pw);
+            }
             
             // Launch Electron client
             let mut electron_child = None;
@@ -156,15 +322,14 @@ pub async fn connect_test(id: &str, key: String, token: String, unlock_id: Strin
                             if client_exe.exists() {
                                 log::info!("Found client executable: {:?}", client_exe);
                                 // Use tokio::process::Command for async management
-                                // Pass unlock credentials as environment variables
+                                // The unlock password stays in this process, the client
+                                // learns from the bridge handshake that it can ask us to type it.
                                 let mut cmd = tokio::process::Command::new(&client_exe);
                                 cmd.kill_on_drop(true);
+                                cmd.envs(endpoint.envs());
                                 if !unlock_id.is_empty() {
                                     cmd.env("SDFDESK_UNLOCK_ID", &unlock_id);
                                 }
-                                if !unlock_pw.is_empty() {
-                                    cmd.env("SDFDESK_UNLOCK_PW", &unlock_pw);
-                                }
                                 match cmd.spawn() 
                                 {
                                     Ok(child) => {
@@ -184,6 +349,7 @@ pub async fn connect_test(id: &str, key: String, token: String, unlock_id: Strin
                     if !launched {
                         log::error!("Could not find or launch any client executable. Checked: {:?}", client_names);
                         log::info!("Please manually run 'sdf-client.exe' or 'sdfdesk-client.exe'");
+                        print_bridge_endpoint(&endpoint);
                     }
                 }
             }
@@ -215,9 +381,13 @@ pub async fn connect_test(id: &str, key: String, token: String, unlock_id: Strin
             };
 
             if !key.is_empty() {
-                session.password = key.to_owned();
+                session.password = key.to_string();
             } else if PeerConfig::load(id).password.is_empty() {
-                session.password = rpassword::prompt_password("Enter password: ").unwrap();
+                match crate::cli_secret::prompt("Enter password: ") {
+                    Ok(password) => session.password = password.to_string(),
+                    // The client asks for it on login.
+                    Err(err) => log::error!("Failed to read the password: {}", err),
+                }
             }
 
             session.lc.write().unwrap().initialize(
@@ -244,7 +414,7 @@ pub async fn connect_test(id: &str, key: String, token: String, unlock_id: Strin
                         // io_task will be aborted when main returns? No, spawn_blocking tasks detach.
                         // But we can't easily abort a blocking task.
                         // However, we can just exit the process.
-                        std::process::exit(0);
+                        std::process::exit(crate::cli_events::exit_code());
                     }
                     _ = io_task => {
                         log::info!("Session ended. Terminating Electron client.");
@@ -266,47 +436,492 @@ pub async fn connect_test(id: &str, key: String, token: String, unlock_id: Strin
                 io_task.await.unwrap();
             }
         }
-        Err(e) => log::error!("Failed to start Electron server: {}", e),
+        Err(e) => {
+            log::error!("Failed to start Electron server: {}", e);
+            crate::cli_events::error(id, &format!("Failed to start Electron server: {}", e));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PortForwardMode {
+    /// Listen locally, the peer connects to the destination.
+    Local,
+    /// The peer listens, connections are forwarded to a local destination.
+    Reverse,
+    /// Local SOCKS5 server, the destination is chosen per connection.
+    Socks,
+    /// Like `Local`, but for UDP datagrams.
+    Udp,
+    /// Like `Local`, but all connections share one session.
+    Mux,
+}
+
+/// One tunnel, in one of the following formats:
+/// - `remote-id:[bind-address:]local-port:remote-port[:remote-host]` (`-p`)
+/// - `reverse:remote-id:remote-port:local-port[:local-host]` (`-R`)
+/// - `socks:remote-id:[bind-address:]local-port` (`-D`)
+/// - `udp:remote-id:[bind-address:]local-port:remote-port[:remote-host]`
+/// - `mux:remote-id:[bind-address:]local-port:remote-port[:remote-host]`
+///
+/// Local listeners bind to loopback unless a bind address is given.
+/// IPv6 addresses are written in brackets, e.g. `[::1]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortForwardSpec {
+    pub id: String,
+    pub mode: PortForwardMode,
+    pub local_host: String,
+    pub local_port: i32,
+    pub remote_host: String,
+    pub remote_port: i32,
+}
+
+const REVERSE_PREFIX: &str = "reverse:";
+const SOCKS_PREFIX: &str = "socks:";
+const UDP_PREFIX: &str = "udp:";
+const MUX_PREFIX: &str = "mux:";
+const DEFAULT_BIND_HOST: &str = "127.0.0.1";
+
+/// Split a spec on `:`, keeping bracketed IPv6 addresses in one piece.
+fn split_spec(s: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut start = 0;
+    let mut in_bracket = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '[' => in_bracket = true,
+            ']' => in_bracket = false,
+            ':' if !in_bracket => {
+                res.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    res.push(&s[start..]);
+    res
+}
+
+/// Take the optional bind address in front of the local port, `n` is the
+/// number of fields without it.
+fn take_bind_host(options: &mut Vec<&str>, n: usize) -> String {
+    if options.len() > n && options[0].parse::<i32>().is_err() {
+        let host = options.remove(0);
+        if !host.is_empty() {
+            return host.to_owned();
+        }
+    }
+    DEFAULT_BIND_HOST.to_owned()
+}
+
+impl PortForwardSpec {
+    pub fn parse_reverse(s: &str) -> Result<Self, String> {
+        let options = split_spec(s.trim());
+        if options.len() < 3 || options[0].is_empty() {
+            return Err(format!("Wrong reverse port-forward options: {}", s));
+        }
+        let remote_port = options[1]
+            .parse::<i32>()
+            .map_err(|_| format!("Wrong remote-port: {}", options[1]))?;
+        let local_port = options[2]
+            .parse::<i32>()
+            .map_err(|_| format!("Wrong local-port: {}", options[2]))?;
+        if remote_port <= 0 || local_port <= 0 {
+            return Err(format!("Wrong reverse port-forward ports: {}", s));
+        }
+        let local_host = if options.len() > 3 && !options[3].is_empty() {
+            options[3].to_owned()
+        } else {
+            "localhost".to_owned()
+        };
+        Ok(Self {
+            id: options[0].to_owned(),
+            mode: PortForwardMode::Reverse,
+            local_host,
+            local_port,
+            remote_host: "".to_owned(),
+            remote_port,
+        })
+    }
+
+    pub fn parse_socks(s: &str) -> Result<Self, String> {
+        let mut options = split_spec(s.trim());
+        if options.len() < 2 || options.len() > 3 || options[0].is_empty() {
+            return Err(format!("Wrong dynamic port-forward options: {}", s));
+        }
+        let id = options.remove(0);
+        let local_host = take_bind_host(&mut options, 1);
+        if options.len() != 1 {
+            return Err(format!("Wrong dynamic port-forward options: {}", s));
+        }
+        let local_port = options[0]
+            .parse::<i32>()
+            .map_err(|_| format!("Wrong local-port: {}", options[0]))?;
+        if local_port <= 0 {
+            return Err(format!("Wrong local-port: {}", options[0]));
+        }
+        Ok(Self {
+            id: id.to_owned(),
+            mode: PortForwardMode::Socks,
+            local_host,
+            local_port,
+            remote_host: "".to_owned(),
+            remote_port: 0,
+        })
+    }
+}
+
+impl std::str::FromStr for PortForwardSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(s) = s.trim().strip_prefix(REVERSE_PREFIX) {
+            return Self::parse_reverse(s);
+        }
+        if let Some(s) = s.trim().strip_prefix(SOCKS_PREFIX) {
+            return Self::parse_socks(s);
+        }
+        let (s, mode) = if let Some(s) = s.trim().strip_prefix(UDP_PREFIX) {
+            (s, PortForwardMode::Udp)
+        } else if let Some(s) = s.trim().strip_prefix(MUX_PREFIX) {
+            (s, PortForwardMode::Mux)
+        } else {
+            (s, PortForwardMode::Local)
+        };
+        let mut options = split_spec(s.trim());
+        if options.len() < 3 || options[0].is_empty() {
+            return Err(format!("Wrong port-forward options: {}", s));
+        }
+        let id = options.remove(0);
+        let local_host = take_bind_host(&mut options, 2);
+        if options.len() < 2 {
+            return Err(format!("Wrong port-forward options: {}", s));
+        }
+        let local_port = options[0]
+            .parse::<i32>()
+            .map_err(|_| format!("Wrong local-port: {}", options[0]))?;
+        let remote_port = options[1]
+            .parse::<i32>()
+            .map_err(|_| format!("Wrong remote-port: {}", options[1]))?;
+        let remote_host = if options.len() > 2 && !options[2].is_empty() {
+            options[2].to_owned()
+        } else {
+            "localhost".to_owned()
+        };
+        if mode != PortForwardMode::Local && (local_port <= 0 || remote_port <= 0) {
+            return Err(format!("Wrong port-forward ports: {}", s));
+        }
+        Ok(Self {
+            id: id.to_owned(),
+            mode,
+            local_host,
+            local_port,
+            remote_host,
+            remote_port,
+        })
+    }
+}
+
+impl std::fmt::Display for PortForwardSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.mode {
+            PortForwardMode::Reverse => write!(
+                f,
+                "{}{}:{}:{}:{}",
+                REVERSE_PREFIX, self.id, self.remote_port, self.local_port, self.local_host
+            ),
+            PortForwardMode::Socks => write!(
+                f,
+                "{}{}:{}:{}",
+                SOCKS_PREFIX, self.id, self.local_host, self.local_port
+            ),
+            PortForwardMode::Local | PortForwardMode::Udp | PortForwardMode::Mux => {
+                let prefix = match self.mode {
+                    PortForwardMode::Udp => UDP_PREFIX,
+                    PortForwardMode::Mux => MUX_PREFIX,
+                    _ => "",
+                };
+                write!(
+                    f,
+                    "{}{}:{}:{}:{}:{}",
+                    prefix,
+                    self.id,
+                    self.local_host,
+                    self.local_port,
+                    self.remote_port,
+                    self.remote_host
+                )
+            }
+        }
     }
 }
 
+/// Read port-forward specs from a tunnels file, one `-p` spec per line.
+/// Blank lines and lines starting with `#` are ignored.
+pub fn load_port_forward_file(path: &str) -> Result<Vec<PortForwardSpec>, String> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    content
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.parse::<PortForwardSpec>())
+        .collect()
+}
+
+/// Session settings shared by all tunnels, e.g. from a forwarding profile.
+#[derive(Debug, Default, Clone)]
+pub struct PortForwardOptions {
+    pub force_relay: bool,
+    pub remember_password: bool,
+    /// Sources allowed to connect to local listeners, all if empty.
+    pub allow_from: Vec<IpCidr>,
+}
+
+/// Parse `--allow-from` values, each a comma separated list of IPs or CIDRs.
+pub fn parse_allow_from<'a>(v: impl IntoIterator<Item = &'a str>) -> Result<Vec<IpCidr>, String> {
+    let mut res = Vec::new();
+    for s in v {
+        for x in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            res.push(IpCidr::from_str(x).map_err(|_| format!("Wrong CIDR: {}", x))?);
+        }
+    }
+    Ok(res)
+}
+
+/// Parse the tunnels of a forwarding profile, they must all go to the same peer.
+pub fn load_port_forward_profile(name: &str) -> Result<(Vec<PortForwardSpec>, PortForwardOptions), String> {
+    let Some(profile) = hbb_common::config::LocalConfig::get_forward_profile(name) else {
+        return Err(format!("No such profile: {}", name));
+    };
+    let mut specs: Vec<PortForwardSpec> = Vec::new();
+    for t in profile.tunnels.iter() {
+        let spec = t.parse::<PortForwardSpec>()?;
+        if let Some(first) = specs.first() {
+            if spec.id != first.id {
+                return Err(format!("Tunnel {} of profile {} is not to {}", t, name, first.id));
+            }
+        }
+        specs.push(spec);
+    }
+    Ok((
+        specs,
+        PortForwardOptions {
+            force_relay: profile.force_relay,
+            remember_password: profile.remember_password,
+            allow_from: parse_allow_from(profile.allow_from.iter().map(|x| x.as_str()))?,
+        },
+    ))
+}
+
+/// Run all tunnels in one process. Tunnels to the same peer share the
+/// password, so it is asked for (and remembered) only once. Each session
+/// still looks the peer up and punches a hole on its own, a `mux:` tunnel
+/// carries all its connections over one session.
 #[tokio::main(flavor = "current_thread")]
-pub async fn start_one_port_forward(
-    id: String,
-    port: i32,
-    remote_host: String,
-    remote_port: i32,
-    key: String,
+pub async fn start_port_forwards(
+    specs: Vec<PortForwardSpec>,
+    key: Secret,
     token: String,
+    options: PortForwardOptions,
 ) {
     crate::common::test_rendezvous_server();
     crate::common::test_nat_type();
-    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender, &key);
-    if let Err(err) = crate::port_forward::listen(
-        handler.id.clone(),
-        handler.password.clone(),
-        port,
-        handler.clone(),
-        receiver,
-        &key,
-        &token,
-        handler.lc.clone(),
-        remote_host,
-        remote_port,
-    )
-    .await
+    let mut peers: Vec<(String, Vec<PortForwardSpec>)> = Vec::new();
+    for spec in specs {
+        if let Some((_, v)) = peers.iter_mut().find(|(id, _)| id == &spec.id) {
+            v.push(spec);
+        } else {
+            peers.push((spec.id.clone(), vec![spec]));
+        }
+    }
+    let peers = peers
+        .into_iter()
+        .map(|(id, specs)| {
+            start_peer_port_forwards(id, specs, key.clone(), token.clone(), options.clone())
+        });
+    hbb_common::futures::future::join_all(peers).await;
+}
+
+async fn start_peer_port_forwards(
+    id: String,
+    specs: Vec<PortForwardSpec>,
+    key: Secret,
+    token: String,
+    options: PortForwardOptions,
+) {
+    let (sender, _) = mpsc::unbounded_channel::<Data>();
+    let mut handler = Session::new(&id, sender, &key);
     {
-        log::error!("Failed to listen on {}: {}", port, err);
+        let mut lc = handler.lc.write().unwrap();
+        lc.force_relay |= options.force_relay;
+        lc.remember |= options.remember_password;
+        // A password remembered before stays remembered.
+        handler.remember = lc.remember;
+    }
+    let mut tunnels = Vec::new();
+    for spec in specs {
+        let (handler, receiver) = handler.fork();
+        let key = key.clone();
+        let token = token.clone();
+        let allow_from = options.allow_from.clone();
+        tunnels.push(tokio::spawn(async move {
+            start_one_port_forward(handler, spec, receiver, &key, &token, &allow_from).await;
+        }));
+    }
+    log::info!("[{}] {} tunnel(s) started", id, tunnels.len());
+    hbb_common::futures::future::join_all(tunnels).await;
+    log::info!("[{}] all tunnels exit", id);
+}
+
+async fn start_one_port_forward(
+    handler: Session,
+    spec: PortForwardSpec,
+    receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    allow_from: &[IpCidr],
+) {
+    let res = match spec.mode {
+        PortForwardMode::Local => {
+            log::info!(
+                "[{}] tunnel {}:{} -> {}:{} starting",
+                spec.id,
+                spec.local_host,
+                spec.local_port,
+                spec.remote_host,
+                spec.remote_port
+            );
+            crate::port_forward::listen(
+                handler.id.clone(),
+                handler.password(),
+                spec.local_host.clone(),
+                spec.local_port,
+                handler.clone(),
+                receiver,
+                key,
+                token,
+                spec.remote_host.clone(),
+                spec.remote_port,
+                allow_from,
+            )
+            .await
+        }
+        PortForwardMode::Reverse => {
+            log::info!(
+                "[{}] reverse tunnel remote :{} -> {}:{} starting",
+                spec.id,
+                spec.remote_port,
+                spec.local_host,
+                spec.local_port
+            );
+            crate::port_forward::listen_reverse(
+                handler.id.clone(),
+                handler.password(),
+                spec.local_host.clone(),
+                spec.local_port,
+                handler.clone(),
+                receiver,
+                key,
+                token,
+                handler.lc.clone(),
+                spec.remote_host.clone(),
+                spec.remote_port,
+            )
+            .await
+        }
+        PortForwardMode::Udp => {
+            log::info!(
+                "[{}] UDP tunnel {}:{} -> {}:{} starting",
+                spec.id,
+                spec.local_host,
+                spec.local_port,
+                spec.remote_host,
+                spec.remote_port
+            );
+            crate::port_forward::listen_udp(
+                handler.id.clone(),
+                handler.password(),
+                spec.local_host.clone(),
+                spec.local_port,
+                handler.clone(),
+                receiver,
+                key,
+                token,
+                spec.remote_host.clone(),
+                spec.remote_port,
+                allow_from,
+            )
+            .await
+        }
+        PortForwardMode::Mux => {
+            log::info!(
+                "[{}] mux tunnel {}:{} -> {}:{} starting",
+                spec.id,
+                spec.local_host,
+                spec.local_port,
+                spec.remote_host,
+                spec.remote_port
+            );
+            crate::port_forward::listen_mux(
+                handler.id.clone(),
+                handler.password(),
+                spec.local_host.clone(),
+                spec.local_port,
+                handler.clone(),
+                receiver,
+                key,
+                token,
+                spec.remote_host.clone(),
+                spec.remote_port,
+                allow_from,
+            )
+            .await
+        }
+        PortForwardMode::Socks => {
+            log::info!(
+                "[{}] SOCKS5 tunnel {}:{} starting",
+                spec.id,
+                spec.local_host,
+                spec.local_port
+            );
+            crate::port_forward::listen_socks(
+                handler.id.clone(),
+                handler.password(),
+                spec.local_host.clone(),
+                spec.local_port,
+                handler.clone(),
+                receiver,
+                key,
+                token,
+                allow_from,
+            )
+            .await
+        }
+    };
+    if let Err(err) = res {
+        log::error!(
+            "[{}] tunnel :{} failed to listen: {}",
+            spec.id,
+            spec.local_port,
+            err
+        );
+        crate::cli_events::set_exit_code(crate::cli_events::EXIT_ERROR);
+        crate::cli_events::emit(
+            "error",
+            serde_json::json!({ "id": spec.id, "tunnel": spec.to_string(), "error": err.to_string() }),
+        );
     }
-    log::info!("port forward (:{}) exit", port);
+    log::info!("[{}] tunnel :{} exit", spec.id, spec.local_port);
 }
 
 #[derive(Clone)]
 pub struct CliConnectionManager {}
 
 impl crate::ui_cm_interface::InvokeUiCM for CliConnectionManager {
-    fn add_connection(&self, _client: &crate::ui_cm_interface::Client) {}
+    fn add_connection(&self, client: &crate::ui_cm_interface::Client) {
+        crate::cli_policy::on_connection(client);
+    }
     fn remove_connection(&self, _id: i32, _close: bool) {}
     fn new_message(&self, _id: i32, _text: String) {}
     fn change_theme(&self, _dark: String) {}
@@ -324,14 +939,13 @@ pub fn start_cm_no_ui() {
 }
 
 #[tokio::main(flavor = "current_thread")]
-pub async fn start_local_server(id: &str, key: String, _token: String) {
-    let port = 21121; // Changed from 21118
+pub async fn start_local_server(id: &str, key: Secret, _token: String, bridge_port: u16) {
     // Create a shared sender that will be populated by io_loop
     let sender: Arc<RwLock<Option<mpsc::UnboundedSender<Data>>>> = Default::default();
     
-    match crate::electron_interface::start_electron_server(port, sender.clone()).await {
-        Ok(handler) => {
-            log::info!("Electron server started on port {}", port);
+    match crate::electron_interface::start_electron_server(bridge_port, sender.clone()).await {
+        Ok((handler, endpoint)) => {
+            log::info!("Electron server started on port {}", endpoint.port);
             
             // Auto-launch Electron client
             if let Ok(mut exe_path) = std::env::current_exe() {
@@ -339,9 +953,10 @@ pub async fn start_local_server(id: &str, key: String, _token: String) {
                 let client_exe = exe_path.join("sdfdesk-client.exe");
                 
                 log::info!("Attempting to launch client: {:?}", client_exe);
-                if let Err(e) = std::process::Command::new(client_exe).spawn() {
+                if let Err(e) = std::process::Command::new(client_exe).envs(endpoint.envs()).spawn() {
                     log::error!("Failed to auto-launch client: {}", e);
                     log::info!("Please manually run 'sdfdesk-client 1.0.0.exe'");
+                    print_bridge_endpoint(&endpoint);
                 }
             }
             
@@ -372,9 +987,13 @@ pub async fn start_local_server(id: &str, key: String, _token: String) {
             };
 
             if !key.is_empty() {
-                session.password = key.to_owned();
+                session.password = key.to_string();
             } else if PeerConfig::load(id).password.is_empty() {
-                session.password = rpassword::prompt_password("Enter password: ").unwrap();
+                match crate::cli_secret::prompt("Enter password: ") {
+                    Ok(password) => session.password = password.to_string(),
+                    // The client asks for it on login.
+                    Err(err) => log::error!("Failed to read the password: {}", err),
+                }
             }
 
             session.lc.write().unwrap().initialize(
@@ -394,3 +1013,94 @@ pub async fn start_local_server(id: &str, key: String, _token: String) {
         Err(e) => log::error!("Failed to start Electron server: {}", e),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_port_forward_spec() {
+        assert_eq!(
+            "123456789:8080:80".parse::<PortForwardSpec>().unwrap(),
+            PortForwardSpec {
+                id: "123456789".to_owned(),
+                mode: PortForwardMode::Local,
+                local_host: "127.0.0.1".to_owned(),
+                local_port: 8080,
+                remote_host: "localhost".to_owned(),
+                remote_port: 80,
+            }
+        );
+        assert_eq!(
+            "123456789:2222:22:10.0.0.5"
+                .parse::<PortForwardSpec>()
+                .unwrap()
+                .remote_host,
+            "10.0.0.5"
+        );
+        assert!("123456789:8080".parse::<PortForwardSpec>().is_err());
+        assert!("123456789:abc:80".parse::<PortForwardSpec>().is_err());
+        assert!(":8080:80".parse::<PortForwardSpec>().is_err());
+
+        let spec = "reverse:123456789:2222:22".parse::<PortForwardSpec>().unwrap();
+        assert_eq!(spec.mode, PortForwardMode::Reverse);
+        assert_eq!(spec.remote_port, 2222);
+        assert_eq!(spec.local_port, 22);
+        assert_eq!(spec.local_host, "localhost");
+        assert_eq!(
+            PortForwardSpec::parse_reverse("123456789:2222:22:192.168.1.10")
+                .unwrap()
+                .local_host,
+            "192.168.1.10"
+        );
+        assert!(PortForwardSpec::parse_reverse("123456789:0:22").is_err());
+
+        let spec = "socks:123456789:1080".parse::<PortForwardSpec>().unwrap();
+        assert_eq!(spec.mode, PortForwardMode::Socks);
+        assert_eq!(spec.local_port, 1080);
+        assert_eq!(spec.local_host, "127.0.0.1");
+        assert!(PortForwardSpec::parse_socks("123456789:1080:80").is_err());
+
+        let spec = "udp:123456789:5353:53:10.0.0.1"
+            .parse::<PortForwardSpec>()
+            .unwrap();
+        assert_eq!(spec.mode, PortForwardMode::Udp);
+        assert_eq!(spec.local_port, 5353);
+        assert_eq!(spec.remote_port, 53);
+        assert_eq!(spec.remote_host, "10.0.0.1");
+        assert!("udp:123456789:0:53".parse::<PortForwardSpec>().is_err());
+        assert_eq!(
+            "mux:123456789:8080:80"
+                .parse::<PortForwardSpec>()
+                .unwrap()
+                .mode,
+            PortForwardMode::Mux
+        );
+
+        let spec = "123456789:0.0.0.0:8080:80".parse::<PortForwardSpec>().unwrap();
+        assert_eq!(spec.local_host, "0.0.0.0");
+        assert_eq!(spec.local_port, 8080);
+        assert_eq!(spec.remote_port, 80);
+        assert_eq!(spec.remote_host, "localhost");
+        let spec = "123456789:[::1]:8080:80:[fd00::5]"
+            .parse::<PortForwardSpec>()
+            .unwrap();
+        assert_eq!(spec.local_host, "[::1]");
+        assert_eq!(spec.remote_host, "[fd00::5]");
+        let spec = "socks:123456789:0.0.0.0:1080"
+            .parse::<PortForwardSpec>()
+            .unwrap();
+        assert_eq!(spec.local_host, "0.0.0.0");
+        assert_eq!(spec.local_port, 1080);
+        assert!("123456789:127.0.0.1:8080".parse::<PortForwardSpec>().is_err());
+
+        for s in [
+            "123456789:127.0.0.1:8080:80:localhost",
+            "reverse:123456789:2222:22:192.168.1.10",
+            "socks:123456789:127.0.0.1:1080",
+            "udp:123456789:[::]:5353:53:10.0.0.1",
+        ] {
+            assert_eq!(s.parse::<PortForwardSpec>().unwrap().to_string(), s);
+        }
+    }
+}
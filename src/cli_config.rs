@@ -0,0 +1,506 @@
+// Declarative server settings, `sdfdesk config export` and `sdfdesk config apply`.
+//
+// The file is TOML, every setting is optional and the missing ones are left
+// alone:
+//
+//     [server]
+//     rendezvous = "hbbs.example.com"
+//     relay = "hbbr.example.com"
+//     key = "OeVuKk5nlHiXp+APNn0Y3pC1Iwpwn44JGqrQCsWqmBw="
+//
+//     [security]
+//     password_hash = "..."        # with its salt, as exported
+//     salt = "..."
+//     approve_mode = "password"
+//     whitelist = ["10.0.0.0/8"]
+//     direct_server = true
+//     direct_access_port = 21118
+//
+//     [permissions]
+//     keyboard = true
+//     file_transfer = false
+//
+//     [options]
+//     "allow-auto-disconnect" = "Y"
+//
+// `[server] config` takes the custom server string of a custom client name
+// instead (see custom_server.rs), and `[security] password` a plain password.
+// An empty string resets a setting to its default. Export writes the hash of
+// the permanent password with its salt. That is enough to log in, so an
+// exported file is a credential: it is created readable by the owner only and
+// must be kept like the password itself.
+
+use crate::custom_server::get_custom_server_from_string;
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{
+    bail,
+    config::{keys, option2bool, Config, Config2},
+    sodiumoxide::base64,
+    toml, ResultType,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+const PERMISSIONS: [(&str, &str); 11] = [
+    ("keyboard", keys::OPTION_ENABLE_KEYBOARD),
+    ("clipboard", keys::OPTION_ENABLE_CLIPBOARD),
+    ("file_transfer", keys::OPTION_ENABLE_FILE_TRANSFER),
+    ("camera", keys::OPTION_ENABLE_CAMERA),
+    ("terminal", keys::OPTION_ENABLE_TERMINAL),
+    ("audio", keys::OPTION_ENABLE_AUDIO),
+    ("tunnel", keys::OPTION_ENABLE_TUNNEL),
+    ("remote_restart", keys::OPTION_ENABLE_REMOTE_RESTART),
+    ("record_session", keys::OPTION_ENABLE_RECORD_SESSION),
+    ("block_input", keys::OPTION_ENABLE_BLOCK_INPUT),
+    (
+        "remote_config_modification",
+        keys::OPTION_ALLOW_REMOTE_CONFIG_MODIFICATION,
+    ),
+];
+
+const APPROVE_MODES: [&str; 3] = ["", "password", "click"];
+const VERIFICATION_METHODS: [&str; 4] = [
+    "",
+    "use-temporary-password",
+    "use-permanent-password",
+    "use-both-passwords",
+];
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Provision {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<Server>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security: Option<Security>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<BTreeMap<String, bool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Server {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rendezvous: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Security {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approve_mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification_method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whitelist: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direct_server: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direct_access_port: Option<u16>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PasswordChange {
+    Plain(String),
+    Hash { salt: String, hash: Vec<u8> },
+}
+
+/// What `apply` changes, options as (key, old value, new value).
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan {
+    pub options: Vec<(String, String, String)>,
+    pub password: Option<PasswordChange>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty() && self.password.is_none()
+    }
+
+    /// One line for each change. The password is not shown.
+    pub fn diff(&self) -> Vec<String> {
+        let show = |v: &str| {
+            if v.is_empty() {
+                "(default)".to_owned()
+            } else {
+                format!("{:?}", v)
+            }
+        };
+        let mut lines: Vec<String> = self
+            .options
+            .iter()
+            .map(|(k, old, new)| format!("{}: {} -> {}", k, show(old), show(new)))
+            .collect();
+        if self.password.is_some() {
+            lines.push("permanent password: changed".to_owned());
+        }
+        lines
+    }
+}
+
+pub fn parse(content: &str) -> ResultType<Provision> {
+    Ok(toml::from_str(content)?)
+}
+
+/// The current settings.
+pub fn export() -> ResultType<String> {
+    let options = Config2::get().options;
+    let get = |k: &str| Config::get_option(k);
+    let mut security = Security {
+        approve_mode: Some(get(keys::OPTION_APPROVE_MODE)),
+        verification_method: Some(get(keys::OPTION_VERIFICATION_METHOD)),
+        whitelist: Some(
+            get(keys::OPTION_WHITELIST)
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_owned())
+                .collect(),
+        ),
+        direct_server: Some(Config::get_bool_option(keys::OPTION_DIRECT_SERVER)),
+        direct_access_port: get(keys::OPTION_DIRECT_ACCESS_PORT).parse().ok(),
+        ..Default::default()
+    };
+    if Config::has_permanent_password() {
+        security.password_hash = Some(base64::encode(
+            Config::get_permanent_password_hash(),
+            base64::Variant::Original,
+        ));
+        security.salt = Some(Config::get_salt());
+    }
+    let provision = Provision {
+        server: Some(Server {
+            config: None,
+            rendezvous: Some(get(keys::OPTION_CUSTOM_RENDEZVOUS_SERVER)),
+            relay: Some(get(keys::OPTION_RELAY_SERVER)),
+            api: Some(get(keys::OPTION_API_SERVER)),
+            key: Some(get(keys::OPTION_KEY)),
+        }),
+        security: Some(security),
+        permissions: Some(
+            PERMISSIONS
+                .iter()
+                .map(|(name, k)| (name.to_string(), Config::get_bool_option(k)))
+                .collect(),
+        ),
+        options: Some(
+            options
+                .into_iter()
+                .filter(|(k, _)| !is_covered(k))
+                .collect(),
+        ),
+    };
+    Ok(toml::to_string_pretty(&provision)?)
+}
+
+/// Write an export to `path`, readable by the owner only.
+pub fn write_export(path: &str, content: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // `mode` only applies to a new file.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(content.as_bytes())
+}
+
+/// Validates the file and compares it with `current`, the stored options.
+pub fn plan(provision: &Provision, current: &HashMap<String, String>) -> ResultType<Plan> {
+    let mut wanted: Vec<(String, String)> = Vec::new();
+    let mut set = |k: &str, v: String| wanted.push((k.to_owned(), v));
+    if let Some(server) = provision.server.as_ref() {
+        let mut server_config = None;
+        if let Some(s) = server.config.as_ref() {
+            if server.rendezvous.is_some() || server.relay.is_some() || server.key.is_some() {
+                bail!("[server] config can not be given with rendezvous, relay or key");
+            }
+            server_config = Some(get_custom_server_from_string(s)?);
+        }
+        let rendezvous = server_config
+            .as_ref()
+            .map(|s| s.host.clone())
+            .or(server.rendezvous.clone());
+        let relay = server_config
+            .as_ref()
+            .map(|s| s.relay.clone())
+            .or(server.relay.clone());
+        let api = server_config
+            .as_ref()
+            .map(|s| s.api.clone())
+            .or(server.api.clone());
+        let key = server_config
+            .as_ref()
+            .map(|s| s.key.clone())
+            .or(server.key.clone());
+        for (k, v) in [
+            (keys::OPTION_CUSTOM_RENDEZVOUS_SERVER, rendezvous),
+            (keys::OPTION_RELAY_SERVER, relay),
+            (keys::OPTION_API_SERVER, api),
+        ] {
+            if let Some(v) = v {
+                check_address(k, &v)?;
+                set(k, v);
+            }
+        }
+        if let Some(key) = key {
+            if !key.is_empty()
+                && base64::decode(&key, base64::Variant::Original).map_or(true, |k| k.len() != 32)
+            {
+                bail!("[server] key is not a base64 public key");
+            }
+            set(keys::OPTION_KEY, key);
+        }
+    }
+    let mut password = None;
+    if let Some(security) = provision.security.as_ref() {
+        password = check_password(security)?;
+        if let Some(v) = security.approve_mode.as_ref() {
+            if !APPROVE_MODES.contains(&v.as_str()) {
+                bail!("[security] approve_mode must be one of {:?}", APPROVE_MODES);
+            }
+            set(keys::OPTION_APPROVE_MODE, v.clone());
+        }
+        if let Some(v) = security.verification_method.as_ref() {
+            if !VERIFICATION_METHODS.contains(&v.as_str()) {
+                bail!(
+                    "[security] verification_method must be one of {:?}",
+                    VERIFICATION_METHODS
+                );
+            }
+            set(keys::OPTION_VERIFICATION_METHOD, v.clone());
+        }
+        if let Some(v) = security.whitelist.as_ref() {
+            for x in v {
+                if x != "0.0.0.0" && IpCidr::from_str(x).is_err() {
+                    bail!("[security] whitelist: invalid IP or CIDR {}", x);
+                }
+            }
+            set(keys::OPTION_WHITELIST, v.join(","));
+        }
+        if let Some(v) = security.direct_server {
+            set(
+                keys::OPTION_DIRECT_SERVER,
+                bool2option(keys::OPTION_DIRECT_SERVER, v),
+            );
+        }
+        if let Some(v) = security.direct_access_port {
+            if v == 0 {
+                bail!("[security] direct_access_port must not be 0");
+            }
+            set(keys::OPTION_DIRECT_ACCESS_PORT, v.to_string());
+        }
+    }
+    if let Some(permissions) = provision.permissions.as_ref() {
+        for (name, v) in permissions {
+            let Some((_, k)) = PERMISSIONS.iter().find(|(n, _)| *n == name.as_str()) else {
+                bail!(
+                    "[permissions] unknown {}, known are {:?}",
+                    name,
+                    PERMISSIONS.map(|(n, _)| n)
+                );
+            };
+            set(k, bool2option(k, *v));
+        }
+    }
+    if let Some(options) = provision.options.as_ref() {
+        for (k, v) in options {
+            if is_covered(k) {
+                bail!("[options] {} has its own setting, set it there", k);
+            }
+            set(k, v.clone());
+        }
+    }
+    let options = wanted
+        .into_iter()
+        .filter_map(|(k, new)| {
+            let old = current.get(&k).cloned().unwrap_or_default();
+            (old != new).then_some((k, old, new))
+        })
+        .collect();
+    Ok(Plan { options, password })
+}
+
+/// Applies the plan, all options are stored at once.
+pub fn apply(plan: &Plan) {
+    if !plan.options.is_empty() {
+        let mut options = Config2::get().options;
+        for (k, _, v) in plan.options.iter() {
+            if v.is_empty() {
+                options.remove(k);
+            } else {
+                options.insert(k.clone(), v.clone());
+            }
+        }
+        Config::set_options(options);
+    }
+    match plan.password.as_ref() {
+        Some(PasswordChange::Plain(password)) => Config::set_permanent_password(password),
+        Some(PasswordChange::Hash { salt, hash }) => {
+            Config::set_permanent_password_hash(salt, hash)
+        }
+        None => {}
+    }
+}
+
+/// The password to set, `None` if it is the current one or not given.
+fn check_password(security: &Security) -> ResultType<Option<PasswordChange>> {
+    match (
+        security.password.as_ref(),
+        security.password_hash.as_ref(),
+        security.salt.as_ref(),
+    ) {
+        (None, None, None) => Ok(None),
+        (Some(password), None, None) => {
+            if *password == Config::get_permanent_password() {
+                Ok(None)
+            } else {
+                Ok(Some(PasswordChange::Plain(password.clone())))
+            }
+        }
+        (None, Some(hash), Some(salt)) => {
+            let hash = match base64::decode(hash, base64::Variant::Original) {
+                Ok(hash) if hash.len() == 32 => hash,
+                _ => bail!("[security] password_hash is not a base64 SHA256 hash"),
+            };
+            if salt.is_empty() {
+                bail!("[security] salt must not be empty");
+            }
+            if hash == Config::get_permanent_password_hash() && *salt == Config::get_salt() {
+                Ok(None)
+            } else {
+                Ok(Some(PasswordChange::Hash {
+                    salt: salt.clone(),
+                    hash,
+                }))
+            }
+        }
+        (Some(_), _, _) => bail!("[security] give either password, or password_hash and salt"),
+        _ => bail!("[security] password_hash and salt go together"),
+    }
+}
+
+fn check_address(k: &str, v: &str) -> ResultType<()> {
+    if v.chars().any(|c| c.is_whitespace() || c == ',') {
+        bail!("{}: invalid address {:?}", k, v);
+    }
+    Ok(())
+}
+
+fn bool2option(k: &str, v: bool) -> String {
+    // The default value is stored as empty, like the toggles of the GUI do.
+    if v == option2bool(k, "") {
+        "".to_owned()
+    } else if v {
+        "Y".to_owned()
+    } else {
+        "N".to_owned()
+    }
+}
+
+/// Options which have a setting of their own in the file.
+fn is_covered(k: &str) -> bool {
+    [
+        keys::OPTION_CUSTOM_RENDEZVOUS_SERVER,
+        keys::OPTION_RELAY_SERVER,
+        keys::OPTION_API_SERVER,
+        keys::OPTION_KEY,
+        keys::OPTION_APPROVE_MODE,
+        keys::OPTION_VERIFICATION_METHOD,
+        keys::OPTION_WHITELIST,
+        keys::OPTION_DIRECT_SERVER,
+        keys::OPTION_DIRECT_ACCESS_PORT,
+    ]
+    .contains(&k)
+        || PERMISSIONS.iter().any(|(_, key)| *key == k)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        let provision = parse(
+            r#"
+            [server]
+            rendezvous = "hbbs.example.com"
+            relay = ""
+
+            [security]
+            approve_mode = "password"
+            whitelist = ["10.0.0.0/8", "192.168.1.5"]
+            direct_server = true
+
+            [permissions]
+            keyboard = false
+            clipboard = true
+
+            [options]
+            "allow-auto-disconnect" = "Y"
+            "#,
+        )
+        .unwrap();
+        let current = HashMap::from([
+            ("relay-server".to_owned(), "old.example.com".to_owned()),
+            ("approve-mode".to_owned(), "password".to_owned()),
+        ]);
+        let plan = plan(&provision, &current).unwrap();
+        assert_eq!(plan.password, None);
+        let s = |a: &str, b: &str, c: &str| (a.to_owned(), b.to_owned(), c.to_owned());
+        assert_eq!(
+            plan.options,
+            vec![
+                s("custom-rendezvous-server", "", "hbbs.example.com"),
+                s("relay-server", "old.example.com", ""),
+                s("whitelist", "", "10.0.0.0/8,192.168.1.5"),
+                s("direct-server", "", "Y"),
+                s("enable-keyboard", "", "N"),
+                s("allow-auto-disconnect", "", "Y"),
+            ]
+        );
+        assert_eq!(
+            plan.diff()[1],
+            "relay-server: \"old.example.com\" -> (default)"
+        );
+    }
+
+    #[test]
+    fn test_plan_errors() {
+        let err = |s: &str| plan(&parse(s).unwrap(), &HashMap::new()).is_err();
+        assert!(parse("[server]\nhost = \"x\"").is_err());
+        assert!(err("[security]\napprove_mode = \"always\""));
+        assert!(err("[security]\nwhitelist = [\"10.0.0.0/33\"]"));
+        assert!(err("[security]\npassword_hash = \"AAAA\"\nsalt = \"abc\""));
+        assert!(err("[security]\npassword = \"a\"\nsalt = \"abc\""));
+        assert!(err("[server]\nkey = \"short\""));
+        assert!(err("[permissions]\nmouse = true"));
+        assert!(err("[options]\n\"approve-mode\" = \"click\""));
+    }
+}
@@ -0,0 +1,119 @@
+// Machine readable output of the command line tool.
+//
+// With `--json`, every event is printed to stdout as one JSON object per line,
+// with `event` and `time` (milliseconds since the epoch) fields. Logs go to
+// stderr and the log file, so stdout only carries events.
+// The process exit code tells why the command failed, see `EXIT_*`.
+
+use crate::client::{
+    LOGIN_MSG_2FA_WRONG, LOGIN_MSG_NO_PASSWORD_ACCESS, LOGIN_MSG_PASSWORD_EMPTY,
+    LOGIN_MSG_PASSWORD_WRONG, REQUIRE_2FA,
+};
+use hbb_common::{get_time, serde_json};
+use std::{
+    io::Write,
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+};
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_AUTH_FAILED: i32 = 2;
+pub const EXIT_PEER_OFFLINE: i32 = 3;
+pub const EXIT_TIMEOUT: i32 = 4;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static EXIT_CODE: AtomicI32 = AtomicI32::new(EXIT_OK);
+
+pub fn set_enabled(v: bool) {
+    ENABLED.store(v, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Print an event if `--json` is on. `fields` should be a JSON object.
+pub fn emit(event: &str, fields: serde_json::Value) {
+    if !is_enabled() {
+        return;
+    }
+    let mut fields = match fields {
+        serde_json::Value::Object(map) => map,
+        _ => Default::default(),
+    };
+    fields.insert("event".to_owned(), event.into());
+    fields.insert("time".to_owned(), get_time().into());
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "{}", serde_json::Value::Object(fields)).ok();
+    out.flush().ok();
+}
+
+/// Print a line for humans, or a `message` event with `--json`, stdout only
+/// carries events then.
+pub fn message(text: &str) {
+    if is_enabled() {
+        emit("message", serde_json::json!({ "message": text }));
+    } else {
+        println!("{}", text);
+    }
+}
+
+/// Record the reason of a failure, the first one wins.
+pub fn set_exit_code(code: i32) {
+    EXIT_CODE
+        .compare_exchange(EXIT_OK, code, Ordering::SeqCst, Ordering::SeqCst)
+        .ok();
+}
+
+/// Forget a failure that was recovered from, e.g. a re-entered password.
+pub fn clear_exit_code() {
+    EXIT_CODE.store(EXIT_OK, Ordering::SeqCst);
+}
+
+pub fn exit_code() -> i32 {
+    EXIT_CODE.load(Ordering::SeqCst)
+}
+
+/// Map a login or connection error to an exit code.
+pub fn exit_code_of(err: &str) -> i32 {
+    if [
+        LOGIN_MSG_PASSWORD_EMPTY,
+        LOGIN_MSG_PASSWORD_WRONG,
+        LOGIN_MSG_2FA_WRONG,
+        LOGIN_MSG_NO_PASSWORD_ACCESS,
+        REQUIRE_2FA,
+    ]
+    .contains(&err)
+    {
+        return EXIT_AUTH_FAILED;
+    }
+    let err = err.to_lowercase();
+    if err.contains("offline") || err.contains("does not exist") {
+        EXIT_PEER_OFFLINE
+    } else if err.contains("timeout") || err.contains("timed out") || err.contains("deadline") {
+        EXIT_TIMEOUT
+    } else {
+        EXIT_ERROR
+    }
+}
+
+/// Report an error that ends the command.
+pub fn error(id: &str, err: &str) {
+    set_exit_code(exit_code_of(err));
+    emit("error", serde_json::json!({ "id": id, "error": err }));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exit_code_of() {
+        assert_eq!(exit_code_of(LOGIN_MSG_PASSWORD_WRONG), EXIT_AUTH_FAILED);
+        assert_eq!(exit_code_of("Remote desktop is offline"), EXIT_PEER_OFFLINE);
+        assert_eq!(exit_code_of("ID does not exist"), EXIT_PEER_OFFLINE);
+        assert_eq!(exit_code_of("Timeout"), EXIT_TIMEOUT);
+        assert_eq!(exit_code_of("deadline has elapsed"), EXIT_TIMEOUT);
+        assert_eq!(exit_code_of("Failed to listen"), EXIT_ERROR);
+    }
+}
@@ -0,0 +1,670 @@
+// Headless file transfer, `sdfdesk push` and `sdfdesk pull`.
+//
+// This is the protocol of the file manager: the sending side reads the files
+// with a `TransferJob` and sends the digest (size and modification time) of
+// each file before its data. The receiving side checks it against the
+// destination and confirms to send the file from an offset, or to skip it.
+// A partly received file is kept as `<name>.download` next to the digest of
+// the source in `<name>.digest`, so running the same command again resumes it.
+
+use crate::{
+    cli::{connect_and_login, Session},
+    cli_secret::Secret,
+    client::*,
+};
+use hbb_common::{
+    bail,
+    fs::{self, can_enable_overwrite_detection, get_string, DigestCheckResult},
+    log,
+    message_proto::*,
+    protobuf::Message as _,
+    rendezvous_proto::ConnType,
+    serde_json::json,
+    tokio::{
+        self,
+        sync::mpsc,
+        time::{self, Duration, Instant},
+    },
+    ResultType, Stream,
+};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// Local files to the peer.
+    Push,
+    /// Files of the peer to local.
+    Pull,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Push => write!(f, "push"),
+            Direction::Pull => write!(f, "pull"),
+        }
+    }
+}
+
+/// What to do with a file that exists at the destination. Identical files,
+/// same size and modification time, are always skipped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverwritePolicy {
+    Skip,
+    Overwrite,
+    /// Overwrite if the source is newer.
+    Newer,
+}
+
+impl std::str::FromStr for OverwritePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "overwrite" => Ok(Self::Overwrite),
+            "newer" => Ok(Self::Newer),
+            _ => Err(format!(
+                "Wrong overwrite policy: {}, expected skip, overwrite or newer",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileTransferOptions {
+    pub overwrite: OverwritePolicy,
+    pub include_hidden: bool,
+    pub force_relay: bool,
+}
+
+/// Copy `source` to `dest`, `source` is local for `Push` and on the peer for `Pull`.
+/// The content of a directory is copied into the destination directory.
+/// A single file is copied into `dest` if it is a directory or ends with a
+/// path separator, or else to `dest` itself.
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_file_transfer(
+    id: String,
+    direction: Direction,
+    source: String,
+    dest: String,
+    key: Secret,
+    token: String,
+    options: FileTransferOptions,
+) {
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new_with_conn_type(&id, sender, &key, ConnType::FILE_TRANSFER);
+    handler.get_lch().write().unwrap().force_relay |= options.force_relay;
+    let res = match connect_and_login(&handler, &mut receiver, &key, &token).await {
+        Ok(Some((stream, _hc))) => {
+            let (local, remote) = match direction {
+                Direction::Push => (source, dest),
+                Direction::Pull => (dest, source),
+            };
+            let mut transfer = Transfer {
+                id: id.clone(),
+                direction,
+                local,
+                remote,
+                options,
+                handler,
+                read_jobs: Vec::new(),
+                write_jobs: Vec::new(),
+                files: 0,
+                skipped: 0,
+                progress: Progress::new(&id),
+            };
+            transfer.run(stream, &mut receiver).await
+        }
+        Ok(None) => return,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = res {
+        log::error!("[{}] {} failed: {}", id, direction, err);
+        crate::cli_events::error(&id, &err.to_string());
+    }
+}
+
+struct Transfer {
+    id: String,
+    direction: Direction,
+    local: String,
+    remote: String,
+    options: FileTransferOptions,
+    handler: Session,
+    read_jobs: Vec<fs::TransferJob>,
+    write_jobs: Vec<fs::TransferJob>,
+    files: usize,
+    skipped: usize,
+    progress: Progress,
+}
+
+impl Transfer {
+    async fn run(
+        &mut self,
+        mut stream: Stream,
+        receiver: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        let job_id = fs::get_next_job_id();
+        self.start(job_id, &mut stream).await?;
+        let started = Instant::now();
+        let mut timer = crate::rustdesk_interval(time::interval(MILLI1));
+        loop {
+            tokio::select! {
+                res = stream.next() => match res {
+                    Some(Ok(bytes)) => {
+                        let msg = Message::parse_from_bytes(&bytes)?;
+                        if self.handle_msg(msg, &mut stream).await? {
+                            break;
+                        }
+                    }
+                    Some(Err(err)) => {
+                        bail!("Connection closed: {}", err);
+                    }
+                    None => {
+                        bail!("Reset by the peer");
+                    }
+                },
+                _ = timer.tick(), if !self.read_jobs.is_empty() => {
+                    fs::handle_read_jobs(&mut self.read_jobs, &mut stream).await?;
+                    if let Some(job) = fs::get_job_immutable(job_id, &self.read_jobs) {
+                        let file = current_file(job);
+                        self.progress.update(job.finished_size(), job.total_size(), &file);
+                    }
+                }
+                d = receiver.recv() => {
+                    if matches!(d, Some(Data::Close) | None) {
+                        bail!("Closed");
+                    }
+                }
+            }
+        }
+        self.progress.finish();
+        let elapsed = started.elapsed().as_secs_f64();
+        log::info!(
+            "[{}] {} {} <-> {} done, {} file(s), {} skipped, {:.1}s",
+            self.id,
+            self.direction,
+            self.local,
+            self.remote,
+            self.files,
+            self.skipped,
+            elapsed
+        );
+        if crate::cli_events::is_enabled() {
+            crate::cli_events::emit(
+                "transfer_done",
+                json!({
+                    "id": self.id,
+                    "direction": self.direction.to_string(),
+                    "files": self.files,
+                    "skipped": self.skipped,
+                    "bytes": self.progress.done,
+                    "seconds": elapsed,
+                }),
+            );
+        } else {
+            eprintln!(
+                "{} file(s), {} skipped, {} in {:.1}s",
+                self.files,
+                self.skipped,
+                readable_size(self.progress.done),
+                elapsed
+            );
+        }
+        Ok(())
+    }
+
+    async fn start(&mut self, job_id: i32, stream: &mut Stream) -> ResultType<()> {
+        let version = self.handler.get_lch().read().unwrap().version;
+        let od = can_enable_overwrite_detection(version);
+        if !od && self.options.overwrite != OverwritePolicy::Overwrite {
+            log::warn!(
+                "[{}] the peer does not support overwrite detection, existing files are overwritten",
+                self.id
+            );
+        }
+        match self.direction {
+            Direction::Push => {
+                if Path::new(&self.local).is_file() && is_dir_path(&self.remote) {
+                    self.remote = format!("{}{}", self.remote, file_name(&self.local));
+                }
+                let mut job = fs::TransferJob::new_read(
+                    job_id,
+                    fs::JobType::Generic,
+                    self.remote.clone(),
+                    fs::DataSource::FilePath(PathBuf::from(&self.local)),
+                    0,
+                    self.options.include_hidden,
+                    false,
+                    od,
+                )?;
+                // Let the peer resume the files it has partly received.
+                job.is_resume = true;
+                #[cfg(not(windows))]
+                let files = job.files().clone();
+                #[cfg(windows)]
+                let mut files = job.files().clone();
+                #[cfg(windows)]
+                if self.handler.get_lch().read().unwrap().info.platform != "Windows" {
+                    fs::transform_windows_path(&mut files);
+                }
+                self.files = files.len();
+                log::info!(
+                    "[{}] push {} to {}, {} file(s), {}",
+                    self.id,
+                    self.local,
+                    self.remote,
+                    self.files,
+                    readable_size(job.total_size())
+                );
+                stream
+                    .send(&fs::new_receive(
+                        job_id,
+                        self.remote.clone(),
+                        0,
+                        files,
+                        job.total_size(),
+                    ))
+                    .await?;
+                self.read_jobs.push(job);
+            }
+            Direction::Pull => {
+                let mut job = fs::TransferJob::new_write(
+                    job_id,
+                    fs::JobType::Generic,
+                    self.remote.clone(),
+                    fs::DataSource::FilePath(PathBuf::from(&self.local)),
+                    0,
+                    self.options.include_hidden,
+                    true,
+                    Vec::new(),
+                    od,
+                );
+                job.is_resume = true;
+                self.write_jobs.push(job);
+                stream
+                    .send(&fs::new_send(
+                        job_id,
+                        fs::JobType::Generic,
+                        self.remote.clone(),
+                        0,
+                        self.options.include_hidden,
+                    ))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true once the transfer is done.
+    async fn handle_msg(&mut self, msg: Message, stream: &mut Stream) -> ResultType<bool> {
+        match msg.union {
+            Some(message::Union::FileResponse(fr)) => match fr.union {
+                Some(file_response::Union::Dir(fd)) => {
+                    self.handle_dir(fd);
+                }
+                Some(file_response::Union::Digest(digest)) => {
+                    if digest.is_upload {
+                        self.handle_upload_digest(digest, stream).await?;
+                    } else {
+                        self.handle_download_digest(digest, stream).await?;
+                    }
+                }
+                Some(file_response::Union::Block(block)) => {
+                    if let Some(job) = fs::get_job(block.id, &mut self.write_jobs) {
+                        job.write(block).await?;
+                        let file = current_file(job);
+                        self.progress
+                            .update(job.finished_size(), job.total_size(), &file);
+                    }
+                }
+                Some(file_response::Union::Done(d)) => {
+                    if let Some(job) = fs::remove_job(d.id, &mut self.write_jobs) {
+                        job.modify_time();
+                        self.progress.done = job.finished_size();
+                    }
+                    // For a push, the peer reports when all files are written.
+                    return Ok(true);
+                }
+                Some(file_response::Union::Error(e)) => {
+                    // The partly received file is kept to resume it next time.
+                    fs::remove_job(e.id, &mut self.write_jobs);
+                    bail!("{}", e.error);
+                }
+                _ => {}
+            },
+            Some(message::Union::FileAction(action)) => {
+                if let Some(file_action::Union::SendConfirm(c)) = action.union {
+                    if let Some(job) = fs::get_job(c.id, &mut self.read_jobs) {
+                        if c.skip() {
+                            self.skipped += 1;
+                            report_skipped(&self.id, &current_file(job));
+                        }
+                        job.confirm(&c).await;
+                    }
+                }
+            }
+            Some(message::Union::TestDelay(t)) => {
+                self.handler.handle_test_delay(t, stream).await;
+            }
+            Some(message::Union::MessageBox(msgbox)) => {
+                if msgbox.msgtype.contains("error") {
+                    bail!("{}", msgbox.text);
+                }
+                self.handler
+                    .msgbox(&msgbox.msgtype, &msgbox.title, &msgbox.text, &msgbox.link);
+            }
+            Some(message::Union::Misc(misc)) => {
+                if let Some(misc::Union::CloseReason(c)) = misc.union {
+                    bail!("{}", c);
+                }
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /// The files to pull.
+    fn handle_dir(&mut self, fd: FileDirectory) {
+        #[cfg(windows)]
+        let entries = fd.entries.to_vec();
+        #[cfg(not(windows))]
+        let mut entries = fd.entries.to_vec();
+        #[cfg(not(windows))]
+        if self.handler.get_lch().read().unwrap().info.platform == "Windows" {
+            fs::transform_windows_path(&mut entries);
+        }
+        let Some(job) = fs::get_job(fd.id, &mut self.write_jobs) else {
+            return;
+        };
+        // A single file has an empty name, it is written to the path of the job.
+        if entries.len() == 1
+            && entries[0].name.is_empty()
+            && (is_dir_path(&self.local) || Path::new(&self.local).is_dir())
+        {
+            let path = PathBuf::from(&self.local).join(file_name(&self.remote));
+            job.data_source = fs::DataSource::FilePath(path);
+        }
+        job.total_size = entries.iter().map(|x| x.size).sum();
+        self.files = entries.len();
+        log::info!(
+            "[{}] pull {} to {}, {} file(s), {}",
+            self.id,
+            self.remote,
+            self.local,
+            self.files,
+            readable_size(job.total_size)
+        );
+        job.set_files(entries);
+    }
+
+    /// The peer has the file we push, `digest` is the one of its copy.
+    async fn handle_upload_digest(
+        &mut self,
+        digest: FileTransferDigest,
+        stream: &mut Stream,
+    ) -> ResultType<()> {
+        let Some(job) = fs::get_job(digest.id, &mut self.read_jobs) else {
+            return Ok(());
+        };
+        let Some(file) = job.files().get(digest.file_num as usize) else {
+            return Ok(());
+        };
+        let union = confirm_existing(self.options.overwrite, &digest, file.modified_time);
+        let req = FileTransferSendConfirmRequest {
+            id: digest.id,
+            file_num: digest.file_num,
+            union: Some(union),
+            ..Default::default()
+        };
+        if req.skip() {
+            self.skipped += 1;
+            report_skipped(&self.id, &file.name);
+        }
+        job.confirm(&req).await;
+        stream.send(&fs::new_send_confirm(req)).await?;
+        Ok(())
+    }
+
+    /// The peer is about to send a file, check it against the local one.
+    async fn handle_download_digest(
+        &mut self,
+        digest: FileTransferDigest,
+        stream: &mut Stream,
+    ) -> ResultType<()> {
+        let version = self.handler.get_lch().read().unwrap().version;
+        let Some(job) = fs::get_job(digest.id, &mut self.write_jobs) else {
+            return Ok(());
+        };
+        let fs::DataSource::FilePath(p) = &job.data_source else {
+            return Ok(());
+        };
+        let Some(file) = job.files().get(digest.file_num as usize) else {
+            return Ok(());
+        };
+        let name = file.name.clone();
+        let path = get_string(&fs::TransferJob::join(p, &name));
+        job.set_digest(digest.file_size, digest.last_modified);
+        let union = match fs::is_write_need_confirmation(
+            crate::is_support_file_transfer_resume_num(version),
+            &path,
+            &digest,
+        )? {
+            DigestCheckResult::IsSame => file_transfer_send_confirm_request::Union::Skip(true),
+            DigestCheckResult::NeedConfirm(local) => {
+                confirm_existing(self.options.overwrite, &local, digest.last_modified)
+            }
+            DigestCheckResult::NoSuchFile => {
+                file_transfer_send_confirm_request::Union::OffsetBlk(0)
+            }
+        };
+        let req = FileTransferSendConfirmRequest {
+            id: digest.id,
+            file_num: digest.file_num,
+            union: Some(union),
+            ..Default::default()
+        };
+        if req.skip() {
+            self.skipped += 1;
+            report_skipped(&self.id, &name);
+        } else if req.offset_blk() > 0 && job.file_num() != digest.file_num {
+            // The job only seeks into the file it is at, finish the previous
+            // file first to resume this one.
+            job.modify_time();
+            job.file_num = digest.file_num;
+        }
+        job.confirm(&req).await;
+        stream.send(&fs::new_send_confirm(req)).await?;
+        Ok(())
+    }
+}
+
+/// How to answer the digest of a file that exists at the destination,
+/// `dest` is what `fs::is_write_need_confirmation` found there.
+fn confirm_existing(
+    policy: OverwritePolicy,
+    dest: &FileTransferDigest,
+    source_modified: u64,
+) -> file_transfer_send_confirm_request::Union {
+    use file_transfer_send_confirm_request::Union;
+    if dest.is_identical {
+        if dest.transferred_size > 0 {
+            // Partly received before, continue from where it stopped.
+            return Union::OffsetBlk(dest.transferred_size as _);
+        }
+        return Union::Skip(true);
+    }
+    let overwrite = match policy {
+        OverwritePolicy::Skip => false,
+        OverwritePolicy::Overwrite => true,
+        OverwritePolicy::Newer => source_modified > dest.last_modified,
+    };
+    if overwrite {
+        Union::OffsetBlk(0)
+    } else {
+        Union::Skip(true)
+    }
+}
+
+fn current_file(job: &fs::TransferJob) -> String {
+    job.files()
+        .get(job.file_num() as usize)
+        .map(|f| f.name.clone())
+        .unwrap_or_default()
+}
+
+fn report_skipped(id: &str, file: &str) {
+    log::info!("[{}] skip {}", id, file);
+    crate::cli_events::emit("file_skipped", json!({ "id": id, "file": file }));
+}
+
+/// Whether `path` names a directory by its trailing separator, on either platform.
+fn is_dir_path(path: &str) -> bool {
+    path.ends_with('/') || path.ends_with('\\')
+}
+
+/// The last component of a path of either platform.
+fn file_name(path: &str) -> &str {
+    path.trim_end_matches(['/', '\\'])
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+}
+
+fn readable_size(n: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut size = n as f64 / 1024.;
+    let mut i = 0;
+    while size >= 1024. && i < UNITS.len() - 1 {
+        size /= 1024.;
+        i += 1;
+    }
+    format!("{:.1} {}", size, UNITS[i])
+}
+
+/// Progress on stderr, or `progress` events with `--json`.
+struct Progress {
+    id: String,
+    done: u64,
+    last: Option<Instant>,
+    shown: bool,
+}
+
+impl Progress {
+    fn new(id: &str) -> Self {
+        Self {
+            id: id.to_owned(),
+            done: 0,
+            last: None,
+            shown: false,
+        }
+    }
+
+    fn update(&mut self, done: u64, total: u64, file: &str) {
+        self.done = done;
+        if self.last.map_or(false, |t| t.elapsed() < PROGRESS_INTERVAL) {
+            return;
+        }
+        self.last = Some(Instant::now());
+        if crate::cli_events::is_enabled() {
+            crate::cli_events::emit(
+                "progress",
+                json!({ "id": self.id, "file": file, "done": done, "total": total }),
+            );
+            return;
+        }
+        let percent = if total > 0 { done * 100 / total } else { 100 };
+        let mut err = std::io::stderr().lock();
+        write!(
+            err,
+            "\r{} / {} ({}%)    ",
+            readable_size(done),
+            readable_size(total),
+            percent
+        )
+        .ok();
+        err.flush().ok();
+        self.shown = true;
+    }
+
+    fn finish(&mut self) {
+        if self.shown {
+            eprintln!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use file_transfer_send_confirm_request::Union;
+
+    #[test]
+    fn test_confirm_existing() {
+        let dest = FileTransferDigest {
+            last_modified: 100,
+            file_size: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            confirm_existing(OverwritePolicy::Skip, &dest, 200),
+            Union::Skip(true)
+        );
+        assert_eq!(
+            confirm_existing(OverwritePolicy::Overwrite, &dest, 50),
+            Union::OffsetBlk(0)
+        );
+        assert_eq!(
+            confirm_existing(OverwritePolicy::Newer, &dest, 200),
+            Union::OffsetBlk(0)
+        );
+        assert_eq!(
+            confirm_existing(OverwritePolicy::Newer, &dest, 100),
+            Union::Skip(true)
+        );
+        let identical = FileTransferDigest {
+            is_identical: true,
+            ..dest.clone()
+        };
+        assert_eq!(
+            confirm_existing(OverwritePolicy::Overwrite, &identical, 100),
+            Union::Skip(true)
+        );
+        let partial = FileTransferDigest {
+            is_identical: true,
+            transferred_size: 4,
+            ..dest
+        };
+        assert_eq!(
+            confirm_existing(OverwritePolicy::Skip, &partial, 100),
+            Union::OffsetBlk(4)
+        );
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(file_name("/tmp/a.txt"), "a.txt");
+        assert_eq!(file_name("C:\\Users\\me\\b.log"), "b.log");
+        assert_eq!(file_name("/tmp/dir/"), "dir");
+        assert_eq!(file_name("c.txt"), "c.txt");
+        assert!(is_dir_path("/tmp/"));
+        assert!(is_dir_path("C:\\tmp\\"));
+        assert!(!is_dir_path("/tmp"));
+        assert_eq!("newer".parse::<OverwritePolicy>(), Ok(OverwritePolicy::Newer));
+        assert!("always".parse::<OverwritePolicy>().is_err());
+        assert_eq!(readable_size(512), "512 B");
+        assert_eq!(readable_size(1536), "1.5 KiB");
+    }
+}
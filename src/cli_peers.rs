@@ -0,0 +1,166 @@
+// Peer lookups of the command line tool: `sdfdesk status`, `sdfdesk discover`
+// and `sdfdesk wake`.
+//
+// The online state comes from the rendezvous server, the same query as the
+// peer list of the GUI. LAN peers answer the discovery broadcast of `lan.rs`,
+// which also saves them with their MAC addresses for Wake-on-LAN.
+
+use crate::{client::peer_online, lan};
+use hbb_common::{config::DiscoveryPeer, config::LanPeers, log, serde_json::json, tokio};
+
+/// Prints the state of each peer. The exit code is `EXIT_PEER_OFFLINE` if any
+/// of them is offline.
+#[tokio::main(flavor = "current_thread")]
+pub async fn status(ids: Vec<String>) -> i32 {
+    let mut states = None;
+    peer_online::query_online_states(ids.clone(), |onlines, offlines| {
+        states = Some((onlines, offlines));
+    })
+    .await;
+    let Some((onlines, _offlines)) = states else {
+        crate::cli_events::error("", "Failed to query online states");
+        return crate::cli_events::exit_code();
+    };
+    let mut code = crate::cli_events::EXIT_OK;
+    for id in ids.iter() {
+        let online = onlines.contains(id);
+        if !online {
+            code = crate::cli_events::EXIT_PEER_OFFLINE;
+        }
+        if crate::cli_events::is_enabled() {
+            crate::cli_events::emit("status", json!({ "id": id, "online": online }));
+        } else {
+            println!("{} {}", id, if online { "online" } else { "offline" });
+        }
+    }
+    code
+}
+
+/// Lists the peers which answer on the local network.
+pub fn discover() -> i32 {
+    if let Err(err) = lan::discover() {
+        log::error!("discover failed: {}", err);
+        crate::cli_events::error("", &err.to_string());
+        return crate::cli_events::exit_code();
+    }
+    let peers: Vec<_> = LanPeers::load()
+        .peers
+        .into_iter()
+        .filter(|p| p.online)
+        .collect();
+    if crate::cli_events::is_enabled() {
+        for peer in peers.iter() {
+            let (ips, macs) = ips_and_macs(peer);
+            crate::cli_events::emit(
+                "peer",
+                json!({
+                    "id": peer.id,
+                    "username": peer.username,
+                    "hostname": peer.hostname,
+                    "platform": peer.platform,
+                    "ips": ips,
+                    "macs": macs,
+                }),
+            );
+        }
+    } else {
+        let rows: Vec<[String; 5]> = peers
+            .iter()
+            .map(|peer| {
+                let (ips, macs) = ips_and_macs(peer);
+                [
+                    peer.id.clone(),
+                    peer.hostname.clone(),
+                    peer.platform.clone(),
+                    ips.join(","),
+                    macs.join(","),
+                ]
+            })
+            .collect();
+        print_table(&["ID", "HOSTNAME", "PLATFORM", "IP", "MAC"], &rows);
+    }
+    crate::cli_events::EXIT_OK
+}
+
+/// Sends a magic packet to the MAC addresses of a peer found by `discover`.
+pub fn wake(id: String) -> i32 {
+    let peers = LanPeers::load().peers;
+    let Some(peer) = peers.iter().find(|p| p.id == id) else {
+        crate::cli_events::error(
+            &id,
+            "The peer is not known on the local network, run discover first",
+        );
+        return crate::cli_events::exit_code();
+    };
+    let (_, macs) = ips_and_macs(peer);
+    if macs.is_empty() {
+        crate::cli_events::error(&id, "No MAC address of the peer is known");
+        return crate::cli_events::exit_code();
+    }
+    lan::send_wol(id.clone());
+    log::info!("[{}] wake-on-lan sent to {}", id, macs.join(","));
+    if crate::cli_events::is_enabled() {
+        crate::cli_events::emit("wol_sent", json!({ "id": id, "macs": macs }));
+    } else {
+        println!("Wake-on-LAN sent to {}", macs.join(","));
+    }
+    crate::cli_events::EXIT_OK
+}
+
+/// Sorted IPs and the distinct, non-empty MAC addresses of a peer.
+fn ips_and_macs(peer: &DiscoveryPeer) -> (Vec<String>, Vec<String>) {
+    let mut ips: Vec<String> = peer.ip_mac.keys().cloned().collect();
+    ips.sort();
+    let mut macs: Vec<String> = peer
+        .ip_mac
+        .values()
+        .filter(|m| !m.is_empty())
+        .cloned()
+        .collect();
+    macs.sort();
+    macs.dedup();
+    (ips, macs)
+}
+
+fn print_table(header: &[&str; 5], rows: &[[String; 5]]) {
+    let mut widths = header.map(|h| h.len());
+    for row in rows {
+        for (w, c) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(c.len());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let s = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(c, w)| format!("{:<w$}", c, w = w))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", s.trim_end());
+    };
+    line(header.to_vec());
+    for row in rows {
+        line(row.iter().map(|s| s.as_str()).collect());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_ips_and_macs() {
+        let peer = DiscoveryPeer {
+            ip_mac: HashMap::from([
+                ("192.168.1.20".to_owned(), "aa:bb:cc:dd:ee:ff".to_owned()),
+                ("10.0.0.5".to_owned(), "aa:bb:cc:dd:ee:ff".to_owned()),
+                ("10.0.0.6".to_owned(), "".to_owned()),
+            ]),
+            ..Default::default()
+        };
+        let (ips, macs) = ips_and_macs(&peer);
+        assert_eq!(ips, vec!["10.0.0.5", "10.0.0.6", "192.168.1.20"]);
+        assert_eq!(macs, vec!["aa:bb:cc:dd:ee:ff"]);
+    }
+}
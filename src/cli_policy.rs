@@ -0,0 +1,452 @@
+// Approval of incoming connections by the headless connection manager.
+//
+// The policy is a TOML file whose path is the `connection-policy` option, set
+// with `sdfdesk --policy <file>`. It is read again for each connection, so
+// edits apply without a restart. The first rule matching a connection
+// decides, or `default` if none does:
+//
+//     default = "deny"
+//     hook = "/usr/local/bin/approve-connection"
+//
+//     [[rules]]
+//     ips = ["10.0.0.0/8"]
+//     conn_types = ["remote", "file-transfer"]
+//     time = "08:00-18:00"
+//     days = ["mon", "tue", "wed", "thu", "fri"]
+//     action = "allow"
+//
+// `allow` accepts the connection like the accept button of the GUI, `deny`
+// closes it, and `hook` runs a command with the connection in `SDFDESK_*`
+// environment variables, exit code 0 allows. A connection which logged in
+// with the password is already accepted, so only deny or a failing hook
+// affects it.
+
+use crate::ui_cm_interface::Client;
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{
+    anyhow::anyhow,
+    bail,
+    chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday},
+    config::Config,
+    log, toml, ResultType,
+};
+use serde::Deserialize;
+use std::{
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+pub const OPTION_CONNECTION_POLICY: &str = "connection-policy";
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+const CONN_TYPES: [&str; 5] = [
+    "remote",
+    "file-transfer",
+    "view-camera",
+    "port-forward",
+    "terminal",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    Deny,
+    Hook,
+}
+
+fn default_action() -> Action {
+    Action::Deny
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Policy {
+    #[serde(default = "default_action")]
+    pub default: Action,
+    /// Hook of the rules with the `hook` action and no hook of their own.
+    #[serde(default)]
+    pub hook: String,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+/// Empty fields match everything.
+#[derive(Debug, Default, Deserialize)]
+pub struct Rule {
+    #[serde(default)]
+    pub peer_ids: Vec<String>,
+    /// IPs or CIDRs.
+    #[serde(default)]
+    pub ips: Vec<String>,
+    /// See `CONN_TYPES`.
+    #[serde(default)]
+    pub conn_types: Vec<String>,
+    /// `HH:MM-HH:MM` in local time, may wrap midnight.
+    #[serde(default)]
+    pub time: String,
+    /// `mon` to `sun`.
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub action: Action,
+    #[serde(default)]
+    pub hook: String,
+}
+
+/// What a policy knows about a connection.
+#[derive(Debug, Clone)]
+pub struct Conn {
+    pub id: i32,
+    pub peer_id: String,
+    pub name: String,
+    pub ip: String,
+    pub conn_type: &'static str,
+}
+
+impl From<&Client> for Conn {
+    fn from(c: &Client) -> Self {
+        let conn_type = if c.is_file_transfer {
+            "file-transfer"
+        } else if c.is_terminal {
+            "terminal"
+        } else if !c.port_forward.is_empty() {
+            "port-forward"
+        } else if c.is_view_camera {
+            "view-camera"
+        } else {
+            "remote"
+        };
+        Self {
+            id: c.id,
+            peer_id: c.peer_id.clone(),
+            name: c.name.clone(),
+            ip: c.ip.clone(),
+            conn_type,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    Allow,
+    Deny,
+    Hook(String),
+}
+
+impl Policy {
+    pub fn load(path: impl AsRef<Path>) -> ResultType<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> ResultType<Self> {
+        let policy: Policy = toml::from_str(content)?;
+        let check_hook = |action: Action, hook: &str| -> ResultType<()> {
+            if action == Action::Hook && hook.is_empty() && policy.hook.is_empty() {
+                bail!("The hook action needs a hook");
+            }
+            Ok(())
+        };
+        check_hook(policy.default, "")?;
+        for (i, rule) in policy.rules.iter().enumerate() {
+            rule.validate()
+                .and_then(|_| check_hook(rule.action, &rule.hook))
+                .map_err(|e| anyhow!("Rule {}: {}", i + 1, e))?;
+        }
+        Ok(policy)
+    }
+
+    /// Returns the decision and what made it, for the log.
+    pub fn decide(&self, conn: &Conn, now: NaiveDateTime) -> (Decision, String) {
+        let (action, hook, by) = match self
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, r)| r.matches(conn, now))
+        {
+            Some((i, rule)) => (rule.action, rule.hook.as_str(), format!("rule {}", i + 1)),
+            None => (self.default, "", "default".to_owned()),
+        };
+        let decision = match action {
+            Action::Allow => Decision::Allow,
+            Action::Deny => Decision::Deny,
+            Action::Hook if hook.is_empty() => Decision::Hook(self.hook.clone()),
+            Action::Hook => Decision::Hook(hook.to_owned()),
+        };
+        (decision, by)
+    }
+}
+
+impl Rule {
+    fn validate(&self) -> ResultType<()> {
+        for ip in self.ips.iter() {
+            if IpCidr::from_str(ip).is_err() {
+                bail!("Wrong IP or CIDR: {}", ip);
+            }
+        }
+        for t in self.conn_types.iter() {
+            if !CONN_TYPES.contains(&t.as_str()) {
+                bail!(
+                    "Wrong connection type: {}, expected one of {}",
+                    t,
+                    CONN_TYPES.join(", ")
+                );
+            }
+        }
+        if !self.time.is_empty() {
+            parse_time_window(&self.time)?;
+        }
+        for d in self.days.iter() {
+            if d.parse::<Weekday>().is_err() {
+                bail!("Wrong day: {}", d);
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, conn: &Conn, now: NaiveDateTime) -> bool {
+        if !self.peer_ids.is_empty() && !self.peer_ids.contains(&conn.peer_id) {
+            return false;
+        }
+        if !self.ips.is_empty() {
+            let Some(ip) = parse_ip(&conn.ip) else {
+                return false;
+            };
+            if !self
+                .ips
+                .iter()
+                .filter_map(|s| IpCidr::from_str(s).ok())
+                .any(|cidr| cidr.contains(ip))
+            {
+                return false;
+            }
+        }
+        if !self.conn_types.is_empty() && !self.conn_types.iter().any(|t| t == conn.conn_type) {
+            return false;
+        }
+        if !self.days.is_empty()
+            && !self
+                .days
+                .iter()
+                .any(|d| d.parse::<Weekday>().ok() == Some(now.weekday()))
+        {
+            return false;
+        }
+        if !self.time.is_empty() {
+            let Ok((start, end)) = parse_time_window(&self.time) else {
+                return false;
+            };
+            let t = now.time();
+            let inside = if start <= end {
+                start <= t && t < end
+            } else {
+                t >= start || t < end
+            };
+            if !inside {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn parse_time_window(s: &str) -> ResultType<(NaiveTime, NaiveTime)> {
+    let Some((start, end)) = s.split_once('-') else {
+        bail!("Wrong time window: {}, expected HH:MM-HH:MM", s);
+    };
+    let parse = |t: &str| {
+        NaiveTime::parse_from_str(t.trim(), "%H:%M")
+            .map_err(|_| anyhow!("Wrong time window: {}, expected HH:MM-HH:MM", s))
+    };
+    Ok((parse(start)?, parse(end)?))
+}
+
+/// The IP of a connection may have a zone, and IPv4 may be mapped to IPv6.
+fn parse_ip(s: &str) -> Option<IpAddr> {
+    let ip = s.split('%').next()?.parse::<IpAddr>().ok()?;
+    Some(match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    })
+}
+
+/// Apply the policy to a new connection, if there is one. The hook may take a
+/// while, so this runs on its own thread.
+pub fn on_connection(client: &Client) {
+    let path = Config::get_option(OPTION_CONNECTION_POLICY);
+    if path.is_empty() {
+        return;
+    }
+    let conn = Conn::from(client);
+    let authorized = client.authorized;
+    std::thread::spawn(move || {
+        let (decision, by) = match Policy::load(&path) {
+            Ok(policy) => policy.decide(&conn, Local::now().naive_local()),
+            Err(err) => {
+                log::error!("Invalid connection policy {}: {}", path, err);
+                (Decision::Deny, "invalid policy".to_owned())
+            }
+        };
+        let allow = match &decision {
+            Decision::Allow => true,
+            Decision::Deny => false,
+            Decision::Hook(hook) => run_hook(hook, &conn),
+        };
+        log::info!(
+            "Connection {} from {} ({}, {}, {}): {} by {}{}",
+            conn.id,
+            conn.peer_id,
+            conn.name,
+            conn.ip,
+            conn.conn_type,
+            if allow { "allowed" } else { "denied" },
+            by,
+            if let Decision::Hook(hook) = &decision {
+                format!(", hook {}", hook)
+            } else {
+                "".to_owned()
+            }
+        );
+        if !allow {
+            crate::ui_cm_interface::close(conn.id);
+        } else if !authorized {
+            crate::ui_cm_interface::authorize(conn.id);
+        }
+    });
+}
+
+/// Whether the hook allows the connection, it is denied if the hook fails to
+/// run or takes longer than `HOOK_TIMEOUT`.
+fn run_hook(hook: &str, conn: &Conn) -> bool {
+    #[cfg(windows)]
+    let mut cmd = {
+        use std::os::windows::process::CommandExt;
+        let mut cmd = std::process::Command::new("cmd.exe");
+        cmd.arg("/C").raw_arg(hook);
+        cmd
+    };
+    #[cfg(not(windows))]
+    let mut cmd = {
+        let mut cmd = std::process::Command::new("/bin/sh");
+        cmd.arg("-c").arg(hook);
+        cmd
+    };
+    let mut child = match cmd
+        .env("SDFDESK_CONN_ID", conn.id.to_string())
+        .env("SDFDESK_PEER_ID", &conn.peer_id)
+        .env("SDFDESK_PEER_NAME", &conn.name)
+        .env("SDFDESK_PEER_IP", &conn.ip)
+        .env("SDFDESK_CONN_TYPE", conn.conn_type)
+        .stdin(std::process::Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            log::error!("Failed to run the approval hook {}: {}", hook, err);
+            return false;
+        }
+    };
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return status.success(),
+            Ok(None) if started.elapsed() < HOOK_TIMEOUT => {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Ok(None) => {
+                log::error!("The approval hook {} timed out", hook);
+                child.kill().ok();
+                child.wait().ok();
+                return false;
+            }
+            Err(err) => {
+                log::error!("Failed to wait for the approval hook {}: {}", hook, err);
+                return false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbb_common::chrono::NaiveDate;
+
+    fn conn(peer_id: &str, ip: &str, conn_type: &'static str) -> Conn {
+        Conn {
+            id: 1,
+            peer_id: peer_id.to_owned(),
+            name: "".to_owned(),
+            ip: ip.to_owned(),
+            conn_type,
+        }
+    }
+
+    #[test]
+    fn test_decide() {
+        let policy = Policy::parse(
+            r#"
+            hook = "approve"
+
+            [[rules]]
+            peer_ids = ["123"]
+            action = "deny"
+
+            [[rules]]
+            ips = ["10.0.0.0/8"]
+            conn_types = ["remote", "file-transfer"]
+            time = "22:00-06:00"
+            action = "allow"
+
+            [[rules]]
+            conn_types = ["terminal"]
+            days = ["sat", "sun"]
+            action = "hook"
+            "#,
+        )
+        .unwrap();
+        // Saturday
+        let night = NaiveDate::from_ymd_opt(2024, 6, 1)
+            .unwrap()
+            .and_hms_opt(23, 30, 0)
+            .unwrap();
+        let noon = night.date().and_hms_opt(12, 0, 0).unwrap();
+        let monday = NaiveDate::from_ymd_opt(2024, 6, 3)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let decide = |c: Conn, t| policy.decide(&c, t);
+        assert_eq!(
+            decide(conn("123", "10.1.2.3", "remote"), night),
+            (Decision::Deny, "rule 1".to_owned())
+        );
+        assert_eq!(
+            decide(conn("456", "::ffff:10.1.2.3", "remote"), night),
+            (Decision::Allow, "rule 2".to_owned())
+        );
+        assert_eq!(
+            decide(conn("456", "10.1.2.3", "remote"), noon).0,
+            Decision::Deny
+        );
+        assert_eq!(
+            decide(conn("456", "192.168.1.1", "terminal"), noon),
+            (Decision::Hook("approve".to_owned()), "rule 3".to_owned())
+        );
+        assert_eq!(
+            decide(conn("456", "192.168.1.1", "terminal"), monday),
+            (Decision::Deny, "default".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Policy::parse("default = \"hook\"").is_err());
+        assert!(Policy::parse("[[rules]]\nips = [\"10.0.0.0/33\"]\naction = \"allow\"").is_err());
+        assert!(Policy::parse("[[rules]]\ntime = \"8-18\"\naction = \"allow\"").is_err());
+        assert!(Policy::parse("[[rules]]\nconn_types = [\"rdp\"]\naction = \"allow\"").is_err());
+        assert!(Policy::parse("[[rules]]\naction = \"maybe\"").is_err());
+        assert!(Policy::parse("default = \"allow\"").is_ok());
+    }
+}
@@ -0,0 +1,249 @@
+// Headless screenshots, `sdfdesk screenshot`.
+//
+// The peer takes the screenshot from the next frame of the video service of
+// the display (see `set_take_screenshot` in `server/video_service.rs`) and
+// sends it as PNG. So the displays are captured first, then one screenshot is
+// requested at a time. A series repeats this every `interval`.
+
+use crate::{
+    cli::{connect_and_login, Session},
+    cli_secret::Secret,
+    client::*,
+};
+use hbb_common::{
+    bail, log,
+    message_proto::*,
+    protobuf::Message as _,
+    rendezvous_proto::ConnType,
+    serde_json::json,
+    tokio::{
+        self,
+        sync::mpsc,
+        time::{self, Duration, Instant},
+    },
+    ResultType, Stream,
+};
+use std::path::{Path, PathBuf};
+
+const SCREENSHOT_TIMEOUT: Duration = Duration::from_secs(15);
+const SID: &str = "cli";
+
+#[derive(Debug, Clone)]
+pub struct ScreenshotOptions {
+    /// The current display of the peer if `None`.
+    pub display: Option<usize>,
+    pub all_displays: bool,
+    /// See `output_path`.
+    pub output: String,
+    /// Number of screenshots of each display, one every `interval`.
+    pub count: usize,
+    pub interval: Duration,
+    pub force_relay: bool,
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_screenshot(id: String, key: Secret, token: String, options: ScreenshotOptions) {
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new_with_conn_type(&id, sender, &key, ConnType::DEFAULT_CONN);
+    handler.get_lch().write().unwrap().force_relay |= options.force_relay;
+    let res = match connect_and_login(&handler, &mut receiver, &key, &token).await {
+        Ok(Some((stream, _hc))) => run(&id, &handler, stream, &mut receiver, &options).await,
+        Ok(None) => return,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = res {
+        log::error!("[{}] screenshot failed: {}", id, err);
+        crate::cli_events::error(&id, &err.to_string());
+    }
+}
+
+async fn run(
+    id: &str,
+    handler: &Session,
+    mut stream: Stream,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    options: &ScreenshotOptions,
+) -> ResultType<()> {
+    let (version, pi) = {
+        let lc = handler.get_lch();
+        let lc = lc.read().unwrap();
+        (lc.version, lc.peer_info.clone().unwrap_or_default())
+    };
+    if !crate::common::is_support_screenshot_num(version) {
+        bail!("The peer does not support screenshots");
+    }
+    let displays = if options.all_displays {
+        (0..pi.displays.len()).collect::<Vec<_>>()
+    } else {
+        let display = options.display.unwrap_or(pi.current_display as _);
+        if display >= pi.displays.len() {
+            bail!(
+                "No display {}, the peer has {} display(s)",
+                display,
+                pi.displays.len()
+            );
+        }
+        vec![display]
+    };
+    if displays.is_empty() {
+        bail!("The peer has no display");
+    }
+
+    let mut misc = Misc::new();
+    misc.set_capture_displays(CaptureDisplays {
+        set: displays.iter().map(|d| *d as _).collect(),
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_misc(misc);
+    stream.send(&msg_out).await?;
+
+    let count = options.count.max(1);
+    let mut next = Instant::now();
+    for index in 0..count {
+        if index > 0 {
+            next += options.interval;
+            if wait(handler, &mut stream, receiver, next).await?.is_some() {
+                log::debug!("[{}] unexpected screenshot response", id);
+            }
+        }
+        for &display in displays.iter() {
+            let mut msg_out = Message::new();
+            msg_out.set_screenshot_request(ScreenshotRequest {
+                display: display as _,
+                sid: SID.to_owned(),
+                ..Default::default()
+            });
+            stream.send(&msg_out).await?;
+            let deadline = Instant::now() + SCREENSHOT_TIMEOUT;
+            let Some(response) = wait(handler, &mut stream, receiver, deadline).await? else {
+                bail!("Timeout waiting for the screenshot of display {}", display);
+            };
+            if !response.msg.is_empty() {
+                bail!("Display {}: {}", display, response.msg);
+            }
+            let path = output_path(
+                &options.output,
+                (displays.len() > 1).then_some(display),
+                (count > 1).then_some(index),
+            );
+            std::fs::write(&path, &response.data)?;
+            log::info!("[{}] display {} saved to {}", id, display, path.display());
+            if crate::cli_events::is_enabled() {
+                crate::cli_events::emit(
+                    "screenshot",
+                    json!({
+                        "id": id,
+                        "display": display,
+                        "index": index,
+                        "path": path.to_string_lossy(),
+                        "size": response.data.len(),
+                    }),
+                );
+            } else {
+                println!("{}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Handle the messages of the peer until a screenshot arrives, or `None` at
+/// `deadline`. Video frames and the other messages of the session are dropped.
+async fn wait(
+    handler: &Session,
+    stream: &mut Stream,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    deadline: Instant,
+) -> ResultType<Option<ScreenshotResponse>> {
+    loop {
+        tokio::select! {
+            res = stream.next() => match res {
+                Some(Ok(bytes)) => {
+                    let msg = Message::parse_from_bytes(&bytes)?;
+                    match msg.union {
+                        Some(message::Union::ScreenshotResponse(response)) => {
+                            if response.sid == SID {
+                                return Ok(Some(response));
+                            }
+                        }
+                        Some(message::Union::TestDelay(t)) => {
+                            handler.handle_test_delay(t, stream).await;
+                        }
+                        Some(message::Union::MessageBox(msgbox)) => {
+                            if msgbox.msgtype.contains("error") {
+                                bail!("{}", msgbox.text);
+                            }
+                        }
+                        Some(message::Union::Misc(misc)) => {
+                            if let Some(misc::Union::CloseReason(c)) = misc.union {
+                                bail!("{}", c);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Some(Err(err)) => {
+                    bail!("Connection closed: {}", err);
+                }
+                None => {
+                    bail!("Reset by the peer");
+                }
+            },
+            _ = time::sleep_until(deadline) => {
+                return Ok(None);
+            }
+            d = receiver.recv() => {
+                if matches!(d, Some(Data::Close) | None) {
+                    bail!("Closed");
+                }
+            }
+        }
+    }
+}
+
+/// `out.png` for a single screenshot, `out-<display>.png` for each of several
+/// displays, and `-<index>` is appended for a series.
+fn output_path(output: &str, display: Option<usize>, index: Option<usize>) -> PathBuf {
+    let path = Path::new(output);
+    let mut name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if let Some(display) = display {
+        name = format!("{}-{}", name, display);
+    }
+    if let Some(index) = index {
+        name = format!("{}-{}", name, index);
+    }
+    let ext = path
+        .extension()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or("png".to_owned());
+    path.with_file_name(format!("{}.{}", name, ext))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_output_path() {
+        assert_eq!(output_path("out.png", None, None), PathBuf::from("out.png"));
+        assert_eq!(output_path("out", None, None), PathBuf::from("out.png"));
+        assert_eq!(
+            output_path("shots/kiosk.png", Some(1), None),
+            PathBuf::from("shots/kiosk-1.png")
+        );
+        assert_eq!(
+            output_path("kiosk.png", Some(0), Some(2)),
+            PathBuf::from("kiosk-0-2.png")
+        );
+        assert_eq!(
+            output_path("kiosk.png", None, Some(3)),
+            PathBuf::from("kiosk-3.png")
+        );
+    }
+}
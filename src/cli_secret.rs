@@ -0,0 +1,245 @@
+// Secrets of the command line tool without putting them on the command line.
+//
+// Every secret `--<name>` (`--key`, `--password`, `--unlock-pw`, `--rdp-pw`) can
+// also be given as
+//   `--<name>-file PATH`     the first line of the file,
+//   `--<name>-env VAR`       the environment variable, removed after reading so
+//                            that child processes do not inherit it,
+//   `--<name>-stdin`         the first line of stdin,
+//   `--<name>-keyring ENTRY` an entry of the OS keyring, see `sdfdesk secret`.
+// The plain `--<name>` still works, but it is visible in the process list.
+//
+// Secrets are `Zeroizing`, they are wiped from memory when dropped. A password
+// typed at the prompt is one too, and without a terminal the prompt fails with
+// an error rather than a panic. The login hashes the password and wipes its
+// copy, after the first login the sessions of the command line tool keep only
+// the salted hash, like `PeerConfig` does for a remembered password. Not wiped
+// are the OS keyring's, clap's and rpassword's own buffers, and the plain
+// `String` password of the sessions started by `--connect` and
+// `--local-server`, which lives as long as the session.
+
+use clap::{Arg, ArgMatches};
+use hbb_common::{bail, ResultType};
+use std::{
+    io::BufRead,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use zeroize::Zeroizing;
+
+pub type Secret = Zeroizing<String>;
+
+const KEYRING_SERVICE: &str = "sdfdesk";
+
+static STDIN_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// The ids of the arguments of one secret.
+pub struct SecretArg {
+    pub name: &'static str,
+    pub file: &'static str,
+    pub env: &'static str,
+    pub stdin: &'static str,
+    pub keyring: &'static str,
+}
+
+pub const KEY: SecretArg = SecretArg {
+    name: "key",
+    file: "key-file",
+    env: "key-env",
+    stdin: "key-stdin",
+    keyring: "key-keyring",
+};
+
+pub const PASSWORD: SecretArg = SecretArg {
+    name: "password",
+    file: "password-file",
+    env: "password-env",
+    stdin: "password-stdin",
+    keyring: "password-keyring",
+};
+
+pub const UNLOCK_PW: SecretArg = SecretArg {
+    name: "unlock-pw",
+    file: "unlock-pw-file",
+    env: "unlock-pw-env",
+    stdin: "unlock-pw-stdin",
+    keyring: "unlock-pw-keyring",
+};
+
+pub const RDP_PW: SecretArg = SecretArg {
+    name: "rdp-pw",
+    file: "rdp-pw-file",
+    env: "rdp-pw-env",
+    stdin: "rdp-pw-stdin",
+    keyring: "rdp-pw-keyring",
+};
+
+impl SecretArg {
+    /// The arguments besides the plain `--<name>`, which is declared by the caller.
+    pub fn args(&self, global: bool) -> [Arg; 4] {
+        [
+            Arg::new(self.file)
+                .long(self.file)
+                .help(format!(
+                    "Read --{} from the first line of this file",
+                    self.name
+                ))
+                .global(global)
+                .num_args(1),
+            Arg::new(self.env)
+                .long(self.env)
+                .help(format!(
+                    "Read --{} from this environment variable",
+                    self.name
+                ))
+                .global(global)
+                .num_args(1),
+            Arg::new(self.stdin)
+                .long(self.stdin)
+                .help(format!("Read --{} from the first line of stdin", self.name))
+                .global(global)
+                .action(clap::ArgAction::SetTrue),
+            Arg::new(self.keyring)
+                .long(self.keyring)
+                .help(format!(
+                    "Read --{} from this entry of the OS keyring",
+                    self.name
+                ))
+                .global(global)
+                .num_args(1),
+        ]
+    }
+
+    /// Whether the secret is given in any way.
+    pub fn is_present(&self, matches: &ArgMatches) -> bool {
+        self.given(matches) > 0
+    }
+
+    /// The secret, `None` if it is not given at all.
+    pub fn get(&self, matches: &ArgMatches) -> ResultType<Option<Secret>> {
+        if self.given(matches) > 1 {
+            bail!(
+                "Only one of --{}, --{}, --{}, --{} and --{} can be given",
+                self.name,
+                self.file,
+                self.env,
+                self.stdin,
+                self.keyring
+            );
+        }
+        let secret = if let Some(v) = matches.get_one::<String>(self.name) {
+            Zeroizing::new(v.clone())
+        } else if let Some(path) = matches.get_one::<String>(self.file) {
+            let content = Zeroizing::new(std::fs::read_to_string(path)?);
+            first_line(&content)
+        } else if let Some(var) = matches.get_one::<String>(self.env) {
+            let Some(v) = std::env::var_os(var) else {
+                bail!("The environment variable {} is not set", var);
+            };
+            std::env::remove_var(var);
+            Zeroizing::new(v.to_string_lossy().to_string())
+        } else if matches.get_flag(self.stdin) {
+            read_stdin_line()?
+        } else if let Some(entry) = matches.get_one::<String>(self.keyring) {
+            get_keyring(entry)?
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(secret))
+    }
+
+    /// `get` with an empty secret if it is not given, and the error printed.
+    pub fn get_or_exit(&self, matches: &ArgMatches) -> Secret {
+        match self.get(matches) {
+            Ok(v) => v.unwrap_or_default(),
+            Err(err) => {
+                hbb_common::log::error!("--{}: {}", self.name, err);
+                crate::cli_events::error("", &format!("--{}: {}", self.name, err));
+                std::process::exit(crate::cli_events::EXIT_ERROR);
+            }
+        }
+    }
+
+    fn given(&self, matches: &ArgMatches) -> usize {
+        [self.name, self.file, self.env, self.keyring]
+            .iter()
+            .filter(|id| matches.get_one::<String>(id).is_some())
+            .count()
+            + matches.get_flag(self.stdin) as usize
+    }
+}
+
+fn first_line(s: &str) -> Secret {
+    Zeroizing::new(s.lines().next().unwrap_or_default().to_owned())
+}
+
+/// Stdin has one first line, so only one secret can be read from it.
+pub fn read_stdin_line() -> ResultType<Secret> {
+    if STDIN_TAKEN.swap(true, Ordering::SeqCst) {
+        bail!("Only one secret can be read from stdin");
+    }
+    let mut line = Zeroizing::new(String::new());
+    std::io::stdin().lock().read_line(&mut line)?;
+    Ok(first_line(&line))
+}
+
+pub fn prompt(text: &str) -> ResultType<Secret> {
+    Ok(Zeroizing::new(rpassword::prompt_password(text)?))
+}
+
+pub fn get_keyring(entry: &str) -> ResultType<Secret> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, entry)?;
+    Ok(Zeroizing::new(entry.get_password()?))
+}
+
+pub fn set_keyring(entry: &str, secret: &str) -> ResultType<()> {
+    keyring::Entry::new(KEYRING_SERVICE, entry)?.set_password(secret)?;
+    Ok(())
+}
+
+pub fn remove_keyring(entry: &str) -> ResultType<()> {
+    keyring::Entry::new(KEYRING_SERVICE, entry)?.delete_credential()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches(args: &[&str]) -> ArgMatches {
+        clap::Command::new("test")
+            .arg(Arg::new("key").long("key").num_args(1))
+            .args(KEY.args(false))
+            .get_matches_from(std::iter::once("test").chain(args.iter().cloned()))
+    }
+
+    #[test]
+    fn test_get() {
+        assert!(KEY.get(&matches(&[])).unwrap().is_none());
+        assert_eq!(
+            KEY.get(&matches(&["--key", "abc"]))
+                .unwrap()
+                .unwrap()
+                .as_str(),
+            "abc"
+        );
+
+        let path = std::env::temp_dir().join(format!("key-file-test-{}", std::process::id()));
+        std::fs::write(&path, "from file\r\nsecond line\n").unwrap();
+        let key = KEY
+            .get(&matches(&["--key-file", &path.to_string_lossy()]))
+            .unwrap();
+        assert_eq!(key.unwrap().as_str(), "from file");
+        std::fs::remove_file(&path).ok();
+
+        let var = format!("SDFDESK_KEY_TEST_{}", std::process::id());
+        std::env::set_var(&var, "from env");
+        let key = KEY.get(&matches(&["--key-env", &var])).unwrap();
+        assert_eq!(key.unwrap().as_str(), "from env");
+        assert!(std::env::var_os(&var).is_none());
+        assert!(KEY.get(&matches(&["--key-env", &var])).is_err());
+
+        assert!(KEY
+            .get(&matches(&["--key", "abc", "--key-env", &var]))
+            .is_err());
+    }
+}
@@ -0,0 +1,566 @@
+// Interactive remote terminal, `sdfdesk terminal`.
+//
+// The peer runs the shell in a PTY (see `server/terminal_service.rs`), this
+// side puts the local TTY in raw mode and passes the bytes through. The PTY
+// follows the size of the local terminal, which is polled as Windows has no
+// resize signal.
+// With a persistent terminal service, the shell survives the connection and
+// can be reattached by its service ID. `~.` at the start of a line detaches,
+// like ssh.
+//
+// `sdfdesk exec` runs a single command with pipes instead of a PTY, so its
+// stdout and stderr stay apart, and exits with the exit code of the command.
+// With `--json` the output comes as `stdout` and `stderr` events with base64
+// `data`, followed by an `exit` event. The terminal does not support `--json`.
+// A Windows peer installed as a service refuses to run commands, as it can only
+// start the shell of the user through the PTY.
+
+use crate::{
+    cli::{connect_and_login, Session},
+    cli_secret::Secret,
+    client::*,
+};
+use hbb_common::{
+    bail,
+    bytes::Bytes,
+    config::keys,
+    log,
+    message_proto::*,
+    protobuf::Message as _,
+    rendezvous_proto::ConnType,
+    serde_json::json,
+    sodiumoxide::base64,
+    tokio::{
+        self,
+        sync::mpsc,
+        time::{self, Duration},
+    },
+    ResultType, Stream,
+};
+use std::io::{Read, Write};
+
+const TERMINAL_ID: i32 = 0;
+const RESIZE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+const EOT: u8 = 0x04;
+
+#[derive(Debug, Default, Clone)]
+pub struct TerminalOptions {
+    /// Reattach to this terminal service instead of the last one of the peer.
+    pub service_id: Option<String>,
+    /// Keep the shell running on the peer after disconnecting. Unlike the
+    /// toggle of the GUI, it is not saved to the peer's config.
+    pub persistent: bool,
+    pub force_relay: bool,
+}
+
+/// Returns the exit code of the remote shell, or the one of the failure.
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_terminal(
+    id: String,
+    key: Secret,
+    token: String,
+    options: TerminalOptions,
+) -> i32 {
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new_with_conn_type(&id, sender, &key, ConnType::TERMINAL);
+    let persistent = {
+        let lc = handler.get_lch();
+        let mut lc = lc.write().unwrap();
+        lc.force_relay |= options.force_relay;
+        if let Some(service_id) = options.service_id.clone() {
+            let k = lc.get_key_terminal_service_id().to_owned();
+            lc.set_option(k, service_id);
+        }
+        if options.persistent {
+            // Only in memory, it is sent with the login.
+            lc.get_config().terminal_persistent.v = true;
+        }
+        lc.get_toggle_option(keys::OPTION_TERMINAL_PERSISTENT)
+    };
+    let res = match connect_and_login(&handler, &mut receiver, &key, &token).await {
+        Ok(Some((stream, _hc))) => run(&handler, stream, &mut receiver).await,
+        Ok(None) => return crate::cli_events::exit_code(),
+        Err(err) => Err(err),
+    };
+    match res {
+        Ok(exit) => {
+            let service_id = {
+                let lc = handler.get_lch();
+                let lc = lc.read().unwrap();
+                lc.get_option(lc.get_key_terminal_service_id())
+            };
+            match exit {
+                Some(code) => {
+                    log::info!("[{}] terminal exited with {}", id, code);
+                    code
+                }
+                None => {
+                    if persistent && !service_id.is_empty() {
+                        eprintln!("Detached, reattach with --service-id {}", service_id);
+                    }
+                    crate::cli_events::EXIT_OK
+                }
+            }
+        }
+        Err(err) => {
+            log::error!("[{}] terminal: {}", id, err);
+            crate::cli_events::error(&id, &err.to_string());
+            crate::cli_events::exit_code()
+        }
+    }
+}
+
+/// Returns the exit code of the command, or the one of the failure.
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_exec(
+    id: String,
+    key: Secret,
+    token: String,
+    command: String,
+    force_relay: bool,
+) -> i32 {
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new_with_conn_type(&id, sender, &key, ConnType::TERMINAL);
+    handler.get_lch().write().unwrap().force_relay |= force_relay;
+    let res = match connect_and_login(&handler, &mut receiver, &key, &token).await {
+        Ok(Some((stream, _hc))) => run_exec(&handler, stream, &mut receiver, command).await,
+        Ok(None) => return crate::cli_events::exit_code(),
+        Err(err) => Err(err),
+    };
+    match res {
+        Ok(code) => {
+            log::info!("[{}] command exited with {}", id, code);
+            crate::cli_events::emit("exit", json!({ "id": id, "code": code }));
+            code
+        }
+        Err(err) => {
+            log::error!("[{}] exec: {}", id, err);
+            crate::cli_events::error(&id, &err.to_string());
+            crate::cli_events::exit_code()
+        }
+    }
+}
+
+/// Returns the exit code of the command.
+async fn run_exec(
+    handler: &Session,
+    mut stream: Stream,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    command: String,
+) -> ResultType<i32> {
+    // The service may be a persistent one shared with the GUI, which numbers its
+    // terminals from 0, and the peer refuses to run a command in an existing one.
+    let terminal_id = exec_terminal_id();
+    let (cols, rows) = DEFAULT_SIZE;
+    let mut action = TerminalAction::new();
+    action.set_open(OpenTerminal {
+        terminal_id,
+        rows: rows as _,
+        cols: cols as _,
+        command,
+        ..Default::default()
+    });
+    send_action(&mut stream, action).await?;
+    let mut input = spawn_stdin_reader();
+    let mut input_open = true;
+    let mut stdout = std::io::stdout();
+    let mut stderr = std::io::stderr();
+    loop {
+        tokio::select! {
+            res = stream.next() => match res {
+                Some(Ok(bytes)) => {
+                    let msg = Message::parse_from_bytes(&bytes)?;
+                    match msg.union {
+                        Some(message::Union::TerminalResponse(response)) => match response.union {
+                            Some(terminal_response::Union::Opened(opened)) => {
+                                if !opened.success {
+                                    bail!("{}", opened.message);
+                                }
+                                log::info!("command {}, pid {}", opened.message, opened.pid);
+                            }
+                            Some(terminal_response::Union::Data(data)) => {
+                                if data.terminal_id == terminal_id {
+                                    let bytes = if data.compressed {
+                                        hbb_common::compress::decompress(&data.data)
+                                    } else {
+                                        data.data.to_vec()
+                                    };
+                                    if crate::cli_events::is_enabled() {
+                                        crate::cli_events::emit(
+                                            if data.is_stderr { "stderr" } else { "stdout" },
+                                            json!({
+                                                "data": base64::encode(&bytes, base64::Variant::Original),
+                                            }),
+                                        );
+                                    } else if data.is_stderr {
+                                        stderr.write_all(&bytes)?;
+                                        stderr.flush()?;
+                                    } else {
+                                        stdout.write_all(&bytes)?;
+                                        stdout.flush()?;
+                                    }
+                                }
+                            }
+                            Some(terminal_response::Union::Closed(closed)) => {
+                                if closed.terminal_id == terminal_id {
+                                    return Ok(closed.exit_code);
+                                }
+                            }
+                            Some(terminal_response::Union::Error(err)) => {
+                                bail!("{}", err.message);
+                            }
+                            _ => {}
+                        },
+                        Some(message::Union::TestDelay(t)) => {
+                            handler.handle_test_delay(t, &mut stream).await;
+                        }
+                        Some(message::Union::MessageBox(msgbox)) => {
+                            if msgbox.msgtype.contains("error") {
+                                bail!("{}", msgbox.text);
+                            }
+                        }
+                        Some(message::Union::Misc(misc)) => {
+                            if let Some(misc::Union::CloseReason(c)) = misc.union {
+                                bail!("{}", c);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Some(Err(err)) => {
+                    bail!("Connection closed: {}", err);
+                }
+                None => {
+                    bail!("Reset by the peer");
+                }
+            },
+            data = input.recv(), if input_open => {
+                // Empty data closes the stdin of the command.
+                let data = data.unwrap_or_default();
+                input_open = !data.is_empty();
+                let mut action = TerminalAction::new();
+                action.set_data(TerminalData {
+                    terminal_id,
+                    data: Bytes::from(data),
+                    ..Default::default()
+                });
+                send_action(&mut stream, action).await?;
+            }
+            d = receiver.recv() => {
+                if matches!(d, Some(Data::Close) | None) {
+                    bail!("Connection closed");
+                }
+            }
+        }
+    }
+}
+
+/// A terminal ID the GUI does not use, unique per running CLI.
+fn exec_terminal_id() -> i32 {
+    0x4000_0000 | (std::process::id() & 0x3fff_ffff) as i32
+}
+
+/// Returns the exit code of the shell, or `None` if detached.
+async fn run(
+    handler: &Session,
+    mut stream: Stream,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+) -> ResultType<Option<i32>> {
+    let mut size = terminal_size();
+    let mut action = TerminalAction::new();
+    action.set_open(OpenTerminal {
+        terminal_id: TERMINAL_ID,
+        rows: size.1 as _,
+        cols: size.0 as _,
+        ..Default::default()
+    });
+    send_action(&mut stream, action).await?;
+    let _raw = RawMode::enter()?;
+    let mut input = spawn_stdin_reader();
+    let mut input_open = true;
+    let mut escape = Escape::default();
+    let mut resize_timer = time::interval(RESIZE_POLL_INTERVAL);
+    let mut stdout = std::io::stdout();
+    loop {
+        tokio::select! {
+            res = stream.next() => match res {
+                Some(Ok(bytes)) => {
+                    let msg = Message::parse_from_bytes(&bytes)?;
+                    match msg.union {
+                        Some(message::Union::TerminalResponse(response)) => match response.union {
+                            Some(terminal_response::Union::Opened(opened)) => {
+                                if !opened.success {
+                                    bail!("{}", opened.message);
+                                }
+                                log::info!(
+                                    "terminal {}, pid {}, service {}",
+                                    opened.message,
+                                    opened.pid,
+                                    opened.service_id
+                                );
+                                if !opened.service_id.is_empty() {
+                                    let lc = handler.get_lch();
+                                    let mut lc = lc.write().unwrap();
+                                    let k = lc.get_key_terminal_service_id().to_owned();
+                                    lc.set_option(k, opened.service_id);
+                                }
+                            }
+                            Some(terminal_response::Union::Data(data)) => {
+                                if data.compressed {
+                                    stdout.write_all(&hbb_common::compress::decompress(&data.data))?;
+                                } else {
+                                    stdout.write_all(&data.data)?;
+                                }
+                                stdout.flush()?;
+                            }
+                            Some(terminal_response::Union::Closed(closed)) => {
+                                return Ok(Some(closed.exit_code));
+                            }
+                            Some(terminal_response::Union::Error(err)) => {
+                                bail!("{}", err.message);
+                            }
+                            _ => {}
+                        },
+                        Some(message::Union::TestDelay(t)) => {
+                            handler.handle_test_delay(t, &mut stream).await;
+                        }
+                        Some(message::Union::MessageBox(msgbox)) => {
+                            if msgbox.msgtype.contains("error") {
+                                bail!("{}", msgbox.text);
+                            }
+                        }
+                        Some(message::Union::Misc(misc)) => {
+                            if let Some(misc::Union::CloseReason(c)) = misc.union {
+                                bail!("{}", c);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Some(Err(err)) => {
+                    bail!("Connection closed: {}", err);
+                }
+                None => {
+                    bail!("Reset by the peer");
+                }
+            },
+            data = input.recv(), if input_open => {
+                let (data, detach) = match data {
+                    Some(data) => escape.feed(&data),
+                    None => {
+                        // Input is piped and ends, send EOF and let the shell finish.
+                        input_open = false;
+                        (vec![EOT], false)
+                    }
+                };
+                if !data.is_empty() {
+                    let mut action = TerminalAction::new();
+                    action.set_data(TerminalData {
+                        terminal_id: TERMINAL_ID,
+                        data: Bytes::from(data),
+                        ..Default::default()
+                    });
+                    send_action(&mut stream, action).await?;
+                }
+                if detach {
+                    return Ok(None);
+                }
+            }
+            _ = resize_timer.tick() => {
+                let new_size = terminal_size();
+                if new_size != size {
+                    size = new_size;
+                    let mut action = TerminalAction::new();
+                    action.set_resize(ResizeTerminal {
+                        terminal_id: TERMINAL_ID,
+                        rows: size.1 as _,
+                        cols: size.0 as _,
+                        ..Default::default()
+                    });
+                    send_action(&mut stream, action).await?;
+                }
+            }
+            d = receiver.recv() => {
+                if matches!(d, Some(Data::Close) | None) {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+async fn send_action(stream: &mut Stream, action: TerminalAction) -> ResultType<()> {
+    let mut msg_out = Message::new();
+    msg_out.set_terminal_action(action);
+    stream.send(&msg_out).await
+}
+
+/// Columns and rows of the local terminal.
+fn terminal_size() -> (u16, u16) {
+    match crossterm::terminal::size() {
+        Ok((cols, rows)) if cols > 0 && rows > 0 => (cols, rows),
+        _ => DEFAULT_SIZE,
+    }
+}
+
+/// Read stdin on a thread, reads block and can not be cancelled.
+fn spawn_stdin_reader() -> mpsc::UnboundedReceiver<Vec<u8>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin();
+        let mut buf = vec![0u8; 4096];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    rx
+}
+
+/// Raw mode of the local TTY, restored on drop.
+struct RawMode {
+    #[cfg(windows)]
+    console: Option<windows_console::Saved>,
+}
+
+impl RawMode {
+    /// `None` if stdin is not a terminal, e.g. piped input, which is passed as it is.
+    fn enter() -> ResultType<Option<Self>> {
+        use std::io::IsTerminal;
+        if !std::io::stdin().is_terminal() {
+            return Ok(None);
+        }
+        crossterm::terminal::enable_raw_mode()?;
+        Ok(Some(Self {
+            // The console passes keys and colors as VT sequences, like a unix TTY.
+            #[cfg(windows)]
+            console: windows_console::enable_vt(),
+        }))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[cfg(windows)]
+        if let Some(saved) = self.console.take() {
+            saved.restore();
+        }
+        crossterm::terminal::disable_raw_mode().ok();
+    }
+}
+
+#[cfg(windows)]
+mod windows_console {
+    use windows::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE, ENABLE_VIRTUAL_TERMINAL_INPUT,
+        ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE,
+    };
+
+    pub struct Saved {
+        input: CONSOLE_MODE,
+        output: CONSOLE_MODE,
+    }
+
+    pub fn enable_vt() -> Option<Saved> {
+        unsafe {
+            let input = GetStdHandle(STD_INPUT_HANDLE).ok()?;
+            let output = GetStdHandle(STD_OUTPUT_HANDLE).ok()?;
+            let mut saved = Saved {
+                input: CONSOLE_MODE::default(),
+                output: CONSOLE_MODE::default(),
+            };
+            GetConsoleMode(input, &mut saved.input).ok()?;
+            GetConsoleMode(output, &mut saved.output).ok()?;
+            SetConsoleMode(input, saved.input | ENABLE_VIRTUAL_TERMINAL_INPUT).ok();
+            SetConsoleMode(output, saved.output | ENABLE_VIRTUAL_TERMINAL_PROCESSING).ok();
+            Some(saved)
+        }
+    }
+
+    impl Saved {
+        pub fn restore(self) {
+            unsafe {
+                if let Ok(input) = GetStdHandle(STD_INPUT_HANDLE) {
+                    SetConsoleMode(input, self.input).ok();
+                }
+                if let Ok(output) = GetStdHandle(STD_OUTPUT_HANDLE) {
+                    SetConsoleMode(output, self.output).ok();
+                }
+            }
+        }
+    }
+}
+
+/// ssh style escape: `~.` right after a newline (or at the start) detaches,
+/// `~~` sends one `~`.
+struct Escape {
+    at_line_start: bool,
+    tilde: bool,
+}
+
+impl Default for Escape {
+    fn default() -> Self {
+        Self {
+            at_line_start: true,
+            tilde: false,
+        }
+    }
+}
+
+impl Escape {
+    /// Returns the bytes to send and whether to detach.
+    fn feed(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        let mut out = Vec::with_capacity(input.len());
+        for &c in input {
+            if self.tilde {
+                self.tilde = false;
+                match c {
+                    b'.' => return (out, true),
+                    b'~' => {
+                        out.push(b'~');
+                        self.at_line_start = false;
+                        continue;
+                    }
+                    _ => out.push(b'~'),
+                }
+            } else if self.at_line_start && c == b'~' {
+                self.tilde = true;
+                continue;
+            }
+            out.push(c);
+            self.at_line_start = c == b'\r' || c == b'\n';
+        }
+        (out, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        let mut e = Escape::default();
+        assert_eq!(e.feed(b"~."), (vec![], true));
+
+        let mut e = Escape::default();
+        assert_eq!(e.feed(b"ls ~.\r"), (b"ls ~.\r".to_vec(), false));
+        assert_eq!(e.feed(b"~"), (vec![], false));
+        assert_eq!(e.feed(b"."), (vec![], true));
+
+        let mut e = Escape::default();
+        assert_eq!(e.feed(b"~~.\r~x"), (b"~.\r~x".to_vec(), false));
+        assert_eq!(e.feed(b"\r~~"), (b"\r~".to_vec(), false));
+    }
+}
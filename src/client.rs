@@ -171,16 +171,16 @@ lazy_static::lazy_static! {
 
 // RDP credentials passed from client CLI for headless mode
 lazy_static::lazy_static! {
-    pub static ref CLIENT_RDP_CREDENTIALS: Arc<Mutex<(String, String)>> = Arc::new(Mutex::new((String::new(), String::new())));
+    pub static ref CLIENT_RDP_CREDENTIALS: Arc<Mutex<(String, zeroize::Zeroizing<String>)>> = Default::default();
 }
 
 /// Set RDP credentials for client connection (for headless mode)
-pub fn set_client_rdp_credentials(rdp_id: String, rdp_pw: String) {
+pub fn set_client_rdp_credentials(rdp_id: String, rdp_pw: zeroize::Zeroizing<String>) {
     *CLIENT_RDP_CREDENTIALS.lock().unwrap() = (rdp_id, rdp_pw);
 }
 
 /// Get RDP credentials for LoginRequest
-pub fn get_client_rdp_credentials() -> (String, String) {
+pub fn get_client_rdp_credentials() -> (String, zeroize::Zeroizing<String>) {
     CLIENT_RDP_CREDENTIALS.lock().unwrap().clone()
 }
 
@@ -1748,6 +1748,9 @@ pub struct LoginConfigHandler {
     pub remember: bool,
     config: PeerConfig,
     pub port_forward: (String, i32),
+    pub port_forward_reverse: bool,
+    pub port_forward_udp: bool,
+    pub port_forward_mux: bool,
     pub version: i64,
     features: Option<Features>,
     pub session_id: u64, // used for local <-> server communication
@@ -2680,8 +2683,21 @@ impl LoginConfigHandler {
         } else {
             Bytes::new()
         };
-        // Get RDP credentials for headless mode
+        // Get RDP credentials for headless mode, sealed to the password of this login
         let (rdp_username, rdp_password) = get_client_rdp_credentials();
+        let rdp_credentials = if rdp_username.is_empty() || rdp_password.is_empty() {
+            Bytes::new()
+        } else if self.password.is_empty() {
+            log::warn!("RDP credentials are only sent with a password login");
+            Bytes::new()
+        } else {
+            crate::common::seal_rdp_credentials(
+                &self.password,
+                &self.hash.challenge,
+                &rdp_username,
+                &rdp_password,
+            )
+        };
         
         let mut lr = LoginRequest {
             username: pure_id,
@@ -2699,8 +2715,7 @@ impl LoginConfigHandler {
             })
             .into(),
             hwid,
-            rdp_username,
-            rdp_password,
+            rdp_credentials,
             ..Default::default()
         };
         match self.conn_type {
@@ -2713,6 +2728,9 @@ impl LoginConfigHandler {
             ConnType::PORT_FORWARD | ConnType::RDP => lr.set_port_forward(PortForward {
                 host: self.port_forward.0.clone(),
                 port: self.port_forward.1,
+                reverse: self.port_forward_reverse,
+                udp: self.port_forward_udp,
+                mux: self.port_forward_mux,
                 ..Default::default()
             }),
             ConnType::TERMINAL => {
@@ -2820,6 +2838,8 @@ pub fn start_video_thread<F, T>(
         let mut count = 0;
         let mut duration = std::time::Duration::ZERO;
         let mut skip_beginning = 0;
+        let mut recording = false;
+        let mut passthrough = false;
         loop {
             if let Ok(data) = video_receiver.recv() {
                 match data {
@@ -2846,6 +2866,23 @@ pub fn start_video_thread<F, T>(
                         };
                         let display = vf.display as usize;
                         let start = std::time::Instant::now();
+                        // Recording needs the decoded frames, none are passed through meanwhile.
+                        if !recording && session.ui_handler.on_encoded_video_frame(display, &vf) {
+                            passthrough = true;
+                            fps_calculate(
+                                &mut skip_beginning,
+                                &fps,
+                                false,
+                                start.elapsed(),
+                                &mut count,
+                                &mut duration,
+                            );
+                            continue;
+                        }
+                        if std::mem::take(&mut passthrough) {
+                            // The decoder missed the frames passed through.
+                            session.refresh_video(display as _);
+                        }
                         let format = CodecFormat::from(&vf);
                         if video_handler.is_none() {
                             let mut handler = VideoHandler::new(format, display);
@@ -2932,6 +2969,7 @@ pub fn start_video_thread<F, T>(
                         }
                     }
                     MediaData::RecordScreen(start) => {
+                        recording = start;
                         let id = session.lc.read().unwrap().id.clone();
                         if let Some(handler) = video_handler.as_mut() {
                             handler.record_screen(start, id, display, is_view_camera);
@@ -3559,6 +3597,8 @@ pub async fn handle_login_from_ui(
     remember: bool,
     peer: &mut Stream,
 ) {
+    // Wiped once hashed.
+    let password = zeroize::Zeroizing::new(password);
     let mut hash_password = if password.is_empty() {
         let mut password2 = lc.read().unwrap().password.clone();
         if password2.is_empty() {
@@ -3571,7 +3611,7 @@ pub async fn handle_login_from_ui(
     } else {
         lc.write().unwrap().password_source = Default::default();
         let mut hasher = Sha256::new();
-        hasher.update(password);
+        hasher.update(password.as_bytes());
         hasher.update(&lc.read().unwrap().hash.salt);
         let res = hasher.finalize();
         lc.write().unwrap().remember = remember;
@@ -3631,6 +3671,13 @@ pub trait Interface: Send + Clone + 'static + Sized {
 
     fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>>;
 
+    /// A session to the same peer with its own login state and UI data, so that
+    /// several connections can log in at the same time, e.g. for port forwarding.
+    /// `None` if the logins have to go through this session one by one.
+    fn fork_session(&self) -> Option<(Self, UnboundedReceiver<Data>)> {
+        None
+    }
+
     fn get_id(&self) -> String {
         self.get_lch().read().unwrap().id.clone()
     }
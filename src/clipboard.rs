@@ -533,6 +533,8 @@ impl std::fmt::Display for ClipboardSide {
 }
 
 pub use proto::get_msg_if_not_support_multi_clip;
+#[cfg(not(target_os = "android"))]
+pub use proto::{create_multi_clipboards, from_multi_clipbards};
 mod proto {
     #[cfg(not(target_os = "android"))]
     use arboard::ClipboardData;
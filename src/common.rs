@@ -1700,6 +1700,62 @@ pub fn create_symmetric_key_msg(their_pk_b: [u8; 32]) -> (Bytes, Bytes, secretbo
     (Vec::from(our_pk_b.0).into(), sealed_key.into(), key)
 }
 
+fn rdp_credentials_key(password_hash: &[u8], challenge: &str) -> secretbox::Key {
+    use hbb_common::sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(b"rdp-credentials");
+    hasher.update(password_hash);
+    hasher.update(challenge);
+    let mut key = [0u8; secretbox::KEYBYTES];
+    key.copy_from_slice(&hasher.finalize());
+    secretbox::Key(key)
+}
+
+/// Seals the RDP credentials of the headless mode for `LoginRequest`.
+///
+/// `password_hash` is SHA256(password + salt), as kept by the client. Like the
+/// login password, the key also depends on the challenge of the connection,
+/// so only the peer which knows the password can open them, and only once.
+pub fn seal_rdp_credentials(
+    password_hash: &[u8],
+    challenge: &str,
+    username: &str,
+    password: &str,
+) -> Bytes {
+    let plain = zeroize::Zeroizing::new(
+        RdpCredentials {
+            username: username.to_owned(),
+            password: password.to_owned(),
+            ..Default::default()
+        }
+        .write_to_bytes()
+        .unwrap_or_default(),
+    );
+    let nonce = secretbox::gen_nonce();
+    let key = rdp_credentials_key(password_hash, challenge);
+    let mut sealed = nonce.0.to_vec();
+    sealed.extend(secretbox::seal(&plain, &nonce, &key));
+    sealed.into()
+}
+
+/// Opens what `seal_rdp_credentials` sealed, `None` if the password does not match.
+pub fn open_rdp_credentials(
+    password_hash: &[u8],
+    challenge: &str,
+    sealed: &[u8],
+) -> Option<(String, zeroize::Zeroizing<String>)> {
+    if sealed.len() < secretbox::NONCEBYTES {
+        return None;
+    }
+    let (nonce, sealed) = sealed.split_at(secretbox::NONCEBYTES);
+    let nonce = secretbox::Nonce::from_slice(nonce)?;
+    let key = rdp_credentials_key(password_hash, challenge);
+    let plain = zeroize::Zeroizing::new(secretbox::open(sealed, &nonce, &key).ok()?);
+    let mut credentials = RdpCredentials::parse_from_bytes(&plain).ok()?;
+    let password = zeroize::Zeroizing::new(std::mem::take(&mut credentials.password));
+    Some((credentials.username, password))
+}
+
 #[inline]
 pub fn using_public_server() -> bool {
     option_env!("RENDEZVOUS_SERVER").unwrap_or("").is_empty()
@@ -2278,6 +2334,18 @@ mod tests {
     };
     use std::collections::HashSet;
 
+    #[test]
+    fn test_rdp_credentials() {
+        let sealed = seal_rdp_credentials(b"hash", "challenge", "admin", "secret");
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        let (username, password) = open_rdp_credentials(b"hash", "challenge", &sealed).unwrap();
+        assert_eq!(username, "admin");
+        assert_eq!(password.as_str(), "secret");
+        assert!(open_rdp_credentials(b"other", "challenge", &sealed).is_none());
+        assert!(open_rdp_credentials(b"hash", "other", &sealed).is_none());
+        assert!(open_rdp_credentials(b"hash", "challenge", &sealed[..10]).is_none());
+    }
+
     #[inline]
     fn get_timestamp_secs() -> u128 {
         (std::time::SystemTime::UNIX_EPOCH
@@ -1,148 +1,396 @@
 use crate::ui_session_interface::InvokeUiSession;
 use crate::client::{QualityStatus, Interface};
+use crate::electron_protocol::{
+    ClientMessage, ClipboardItem, Display, InputEvent, PeerInfoData, Request, ServerMessage,
+    SessionEvent, SessionState, FRAME_ENCODED, FRAME_RGBA, VIDEO_CODECS, VERSION,
+};
 use hbb_common::{
     log,
     message_proto::*,
     rendezvous_proto::ConnType,
     tokio::{
         self,
-        net::TcpListener,
+        net::{TcpListener, TcpStream},
         sync::mpsc,
     },
     ResultType,
 };
-use scrap::ImageRgb;
-use std::sync::Arc;
-use tokio_tungstenite::{accept_async, tungstenite::Message};
+use scrap::{CodecFormat, ImageRgb};
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request as HttpRequest, Response},
+        http::StatusCode,
+        Message,
+    },
+    WebSocketStream,
+};
 use hbb_common::futures::{SinkExt, StreamExt};
 use async_trait::async_trait;
 
 use crate::client::Data;
-use serde::Deserialize;
 
 use std::sync::RwLock;
 
+lazy_static::lazy_static! {
+    // The OS password to type on the login screen of the peer, see `SendUnlockPw`.
+    static ref UNLOCK_PASSWORD: std::sync::Mutex<Option<crate::cli_secret::Secret>> = Default::default();
+}
+
+/// Origins of the pages allowed to connect, besides clients sending none. The
+/// Electron client loads its page from a file.
+const ALLOWED_ORIGINS: [&str; 2] = ["file://", "null"];
+
+/// Where the client connects to the bridge of this session, passed to it in
+/// `SDFDESK_BRIDGE_PORT` and `SDFDESK_BRIDGE_TOKEN`.
+#[derive(Clone)]
+pub struct BridgeEndpoint {
+    pub port: u16,
+    pub token: String,
+}
+
+impl BridgeEndpoint {
+    pub fn envs(&self) -> [(&'static str, String); 2] {
+        [
+            ("SDFDESK_BRIDGE_PORT", self.port.to_string()),
+            ("SDFDESK_BRIDGE_TOKEN", self.token.clone()),
+        ]
+    }
+}
+
+pub fn set_unlock_password(password: crate::cli_secret::Secret) {
+    *UNLOCK_PASSWORD.lock().unwrap() = Some(password);
+}
+
+type InputSender = Arc<std::sync::RwLock<Option<mpsc::UnboundedSender<Data>>>>;
+
 #[derive(Clone, Default)]
 pub struct ElectronUiHandler {
-    video_sender: Arc<std::sync::Mutex<Option<mpsc::UnboundedSender<Vec<u8>>>>>,
-    input_sender: Arc<std::sync::RwLock<Option<mpsc::UnboundedSender<Data>>>>,
+    ws_sender: Arc<std::sync::Mutex<Option<mpsc::UnboundedSender<Message>>>>,
+    input_sender: InputSender,
+    passthrough: Arc<std::sync::Mutex<Passthrough>>,
+    state: Arc<std::sync::Mutex<SessionState>>,
+    // A client finished the handshake, it takes the clipboard of the peer.
+    client_ready: Arc<AtomicBool>,
+}
+
+/// Encoded frames go to the client as they are, for the codecs it can decode
+/// with WebCodecs. The others are decoded here and sent as RGBA.
+#[derive(Default)]
+struct Passthrough {
+    codecs: Vec<CodecFormat>,
+    // The displays with a key frame sent since the codecs were set, the client
+    // can decode them.
+    started: HashSet<usize>,
+    refresh_requested: HashSet<usize>,
 }
 
 impl ElectronUiHandler {
-    pub fn new(video_sender: mpsc::UnboundedSender<Vec<u8>>, input_sender: Arc<std::sync::RwLock<Option<mpsc::UnboundedSender<Data>>>>) -> Self {
+    pub fn new(ws_sender: mpsc::UnboundedSender<Message>, input_sender: InputSender) -> Self {
         Self {
-            video_sender: Arc::new(std::sync::Mutex::new(Some(video_sender))),
+            ws_sender: Arc::new(std::sync::Mutex::new(Some(ws_sender))),
             input_sender,
+            passthrough: Default::default(),
+            state: Default::default(),
+            client_ready: Default::default(),
+        }
+    }
+
+    /// Sets the codecs the client decodes itself, unknown names are ignored.
+    /// The video is refreshed, the client or the decoder here needs a key frame.
+    fn set_video_codecs(&self, names: &[String]) -> Vec<String> {
+        let codecs = VIDEO_CODECS
+            .iter()
+            .filter(|(name, _, _)| names.iter().any(|n| n == name))
+            .collect::<Vec<_>>();
+        log::info!("Client decodes {:?} itself", codecs);
+        *self.passthrough.lock().unwrap() = Passthrough {
+            codecs: codecs.iter().map(|(_, format, _)| *format).collect(),
+            ..Default::default()
+        };
+        let names: Vec<String> = codecs.iter().map(|(name, _, _)| name.to_string()).collect();
+        self.state.lock().unwrap().video_codecs = names.clone();
+        self.refresh_video();
+        names
+    }
+
+    fn refresh_video(&self) {
+        self.send_input(Data::Message(crate::client::LoginConfigHandler::refresh()));
+    }
+
+    /// Peers before multiple UI sessions refresh all displays.
+    fn refresh_display(&self, display: usize) {
+        let multi_ui_session = self.state.lock().unwrap().peer_info.as_ref().map_or(false, |pi| {
+            crate::common::is_support_multi_ui_session(&pi.version)
+        });
+        if multi_ui_session {
+            self.send_input(Data::Message(crate::client::LoginConfigHandler::refresh_display(display)));
+        } else {
+            self.refresh_video();
+        }
+    }
+
+    fn send_input(&self, data: Data) {
+        if let Some(sender) = self.input_sender.read().unwrap().as_ref() {
+            sender.send(data).ok();
+        }
+    }
+
+    fn send_misc(&self, misc: Misc) {
+        let mut msg_out = hbb_common::message_proto::Message::new();
+        msg_out.set_misc(misc);
+        self.send_input(Data::Message(msg_out));
+    }
+
+    fn check_displays(&self, displays: &[i32]) -> Result<(), String> {
+        let state = self.state.lock().unwrap();
+        let count = state.peer_info.as_ref().map_or(0, |pi| pi.displays.len());
+        match displays.iter().find(|d| **d < 0 || **d as usize >= count) {
+            Some(d) => Err(format!("no display {}, the peer has {}", d, count)),
+            None => Ok(()),
+        }
+    }
+
+    /// Like `is_text_clipboard_required` of the session, the peer only accepts
+    /// the clipboard with the keyboard permission.
+    fn is_clipboard_allowed(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        ["clipboard", "keyboard"]
+            .iter()
+            .all(|p| state.permissions.get(*p) != Some(&false))
+    }
+
+    fn set_clipboard(&self, items: &[ClipboardItem]) -> Result<(), String> {
+        if !self.is_clipboard_allowed() {
+            return Err("the clipboard is not allowed by the peer".to_owned());
+        }
+        let data = items
+            .iter()
+            .map(|item| item.to_data())
+            .collect::<Result<Vec<_>, _>>()?;
+        let multi_clipboards = crate::clipboard::create_multi_clipboards(data);
+        if multi_clipboards.clipboards.is_empty() {
+            return Err("no clipboard items".to_owned());
+        }
+        let peer = self
+            .state
+            .lock()
+            .unwrap()
+            .peer_info
+            .as_ref()
+            .map(|pi| (pi.version.clone(), pi.platform.clone()));
+        let msg_out = match peer.and_then(|(version, platform)| {
+            crate::clipboard::get_msg_if_not_support_multi_clip(&version, &platform, &multi_clipboards)
+        }) {
+            Some(msg_out) => msg_out,
+            None => {
+                let mut msg_out = hbb_common::message_proto::Message::new();
+                msg_out.set_multi_clipboards(multi_clipboards);
+                msg_out
+            }
+        };
+        self.send_input(Data::Message(msg_out));
+        Ok(())
+    }
+
+    /// Dropped without a client, the channel would buffer every frame until one
+    /// connects, which drops them anyway before its handshake.
+    fn send(&self, msg: Message) {
+        if !self.client_ready.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Some(sender) = self.ws_sender.lock().unwrap().as_ref() {
+            sender.send(msg).ok();
         }
     }
-}
 
-#[derive(Deserialize, Debug)]
-#[serde(tag = "type")]
-enum InputEvent {
-    #[serde(rename = "mousemove")]
-    MouseMove { x: i32, y: i32 },
-    #[serde(rename = "mousedown")]
-    MouseDown { btn: String, x: i32, y: i32 },
-    #[serde(rename = "mouseup")]
-    MouseUp { btn: String, x: i32, y: i32 },
-    #[serde(rename = "wheel")]
-    Wheel { delta_x: i32, delta_y: i32 },
-    #[serde(rename = "keydown")]
-    KeyDown { key: String },
-    #[serde(rename = "keyup")]
-    KeyUp { key: String },
-    // Login control events
-    #[serde(rename = "send_sas")]
-    SendSas,
-    #[serde(rename = "send_text")]
-    SendText { text: String, enter: bool },
+    fn send_event(&self, event: SessionEvent) {
+        self.send(Message::Text(ServerMessage::Event(event).to_text().into()));
+    }
+
+    fn handle_request(&self, request: Request) -> Result<Value, String> {
+        match request {
+            Request::GetSession => {
+                serde_json::to_value(&*self.state.lock().unwrap()).map_err(|e| e.to_string())
+            }
+            Request::SetVideoCodecs { codecs } => {
+                Ok(serde_json::json!({ "codecs": self.set_video_codecs(&codecs) }))
+            }
+            Request::RefreshVideo => {
+                self.refresh_video();
+                Ok(Value::Null)
+            }
+            Request::SwitchDisplay { display } => {
+                self.check_displays(&[display])?;
+                let mut misc = Misc::new();
+                misc.set_switch_display(SwitchDisplay {
+                    display,
+                    ..Default::default()
+                });
+                self.send_misc(misc);
+                // Like the sessions rendering RGBA, only this display is captured.
+                self.handle_request(Request::CaptureDisplays {
+                    add: vec![],
+                    sub: vec![],
+                    set: vec![display],
+                })
+            }
+            Request::CaptureDisplays { add, sub, set } => {
+                self.check_displays(&add)?;
+                self.check_displays(&sub)?;
+                self.check_displays(&set)?;
+                let mut misc = Misc::new();
+                misc.set_capture_displays(CaptureDisplays {
+                    add,
+                    sub,
+                    set,
+                    ..Default::default()
+                });
+                self.send_misc(misc);
+                Ok(Value::Null)
+            }
+            Request::SetClipboard { items } => {
+                self.set_clipboard(&items)?;
+                Ok(Value::Null)
+            }
+        }
+    }
 }
 
 impl InvokeUiSession for ElectronUiHandler {
     fn set_cursor_data(&self, cd: CursorData) {
         use hbb_common::sodiumoxide::base64;
-        let png_data = base64::encode(&cd.colors, base64::Variant::Original);
-        let msg = serde_json::json!({
-            "type": "cursor_data",
-            "id": cd.id,
-            "hotx": cd.hotx,
-            "hoty": cd.hoty,
-            "width": cd.width,
-            "height": cd.height,
-            "data": png_data
+        self.send_event(SessionEvent::CursorData {
+            id: cd.id,
+            hotx: cd.hotx,
+            hoty: cd.hoty,
+            width: cd.width,
+            height: cd.height,
+            colors: base64::encode(&cd.colors, base64::Variant::Original),
         });
-        if let Some(sender) = self.video_sender.lock().unwrap().as_ref() {
-             // Send as text message (bytes)
-             let text = msg.to_string();
-             // We use the same channel for video (binary) and other messages?
-             // The channel is Vec<u8>.
-             // In start_electron_server, we handle rx.recv().
-             // If it's binary, we send Message::Binary.
-             // If we want to send Text, we need to differentiate.
-             // But the channel only accepts Vec<u8>.
-             // I should probably wrap it or use a convention.
-             // Current video frame: width(4) + height(4) + data.
-             // If I send JSON, I can prefix it with a magic number or just use a different channel?
-             // Or, since video frames are huge and JSON is small...
-             // Let's use a magic header for JSON?
-             // Or better: The `video_sender` is `mpsc::UnboundedSender<Vec<u8>>`.
-             // The receiver in `start_electron_server` sends `Message::Binary`.
-             // Electron `renderer.js` expects binary video frames.
-             
-             // Problem: `renderer.js` `ws.onmessage` assumes everything is a video frame!
-             // I need to change `renderer.js` to handle both.
-             // I can add a header byte: 0 = Video, 1 = JSON.
-             
-             let mut data = Vec::new();
-             data.push(1); // 1 = JSON
-             data.extend_from_slice(text.as_bytes());
-             sender.send(data).ok();
-        }
     }
     fn set_cursor_id(&self, _id: String) {}
     fn set_cursor_position(&self, cp: CursorPosition) {
-        let msg = serde_json::json!({
-            "type": "cursor_position",
-            "x": cp.x,
-            "y": cp.y
+        self.send_event(SessionEvent::CursorPosition { x: cp.x, y: cp.y });
+    }
+    fn set_display(&self, _x: i32, _y: i32, _w: i32, _h: i32, _cursor_embedded: bool, _scale: f64) {}
+    fn switch_display(&self, display: &SwitchDisplay) {
+        self.state.lock().unwrap().current_display = display.display;
+        self.send_event(SessionEvent::SwitchDisplay {
+            display: display.display,
+            x: display.x,
+            y: display.y,
+            width: display.width,
+            height: display.height,
+            cursor_embedded: display.cursor_embedded,
         });
-        if let Some(sender) = self.video_sender.lock().unwrap().as_ref() {
-             let text = msg.to_string();
-             let mut data = Vec::new();
-             data.push(1); // 1 = JSON
-             data.extend_from_slice(text.as_bytes());
-             sender.send(data).ok();
+    }
+    fn set_peer_info(&self, peer_info: &PeerInfo) {
+        let data = PeerInfoData::from(peer_info);
+        {
+            let mut state = self.state.lock().unwrap();
+            state.current_display = data.current_display;
+            state.peer_info = Some(data.clone());
         }
+        self.send_event(SessionEvent::PeerInfo(data));
     }
-    fn set_display(&self, _x: i32, _y: i32, _w: i32, _h: i32, _cursor_embedded: bool, _scale: f64) {}
-    fn switch_display(&self, _display: &SwitchDisplay) {}
-    fn set_peer_info(&self, _peer_info: &PeerInfo) {}
-    fn set_displays(&self, _displays: &Vec<DisplayInfo>) {}
-    fn set_platform_additions(&self, _data: &str) {}
-    fn on_connected(&self, _conn_type: ConnType) {
+    fn set_displays(&self, displays: &Vec<DisplayInfo>) {
+        let displays: Vec<Display> = displays.iter().map(Display::from).collect();
+        if let Some(peer_info) = self.state.lock().unwrap().peer_info.as_mut() {
+            peer_info.displays = displays.clone();
+        }
+        self.send_event(SessionEvent::Displays { displays });
+    }
+    fn set_platform_additions(&self, data: &str) {
+        self.send_event(SessionEvent::PlatformAdditions {
+            data: data.to_owned(),
+        });
+    }
+    fn on_connected(&self, conn_type: ConnType) {
         log::info!("ElectronUiHandler: Connected!");
+        // Logged in, possibly with a password re-entered after a failed attempt.
+        crate::cli_events::clear_exit_code();
+        crate::cli_events::emit(
+            "connected",
+            serde_json::json!({ "id": "", "conn_type": format!("{:?}", conn_type) }),
+        );
+        self.send_event(SessionEvent::Connected {
+            conn_type: format!("{:?}", conn_type),
+        });
+    }
+    fn update_privacy_mode(&self) {
+        self.send_event(SessionEvent::PrivacyMode);
+    }
+    fn set_permission(&self, name: &str, value: bool) {
+        self.state
+            .lock()
+            .unwrap()
+            .permissions
+            .insert(name.to_owned(), value);
+        self.send_event(SessionEvent::Permission {
+            name: name.to_owned(),
+            value,
+        });
+    }
+    fn close_success(&self) {
+        self.send_event(SessionEvent::SessionReady);
+    }
+    fn update_quality_status(&self, qs: QualityStatus) {
+        self.send_event(SessionEvent::QualityStatus {
+            speed: qs.speed,
+            fps: qs.fps,
+            delay: qs.delay,
+            target_bitrate: qs.target_bitrate,
+            codec_format: qs.codec_format.map(|c| c.to_string()),
+            chroma: qs.chroma,
+        });
     }
-    fn update_privacy_mode(&self) {}
-    fn set_permission(&self, _name: &str, _value: bool) {}
-    fn close_success(&self) {}
-    fn update_quality_status(&self, _qs: QualityStatus) {}
     fn set_connection_type(&self, is_secured: bool, direct: bool, stream_type: &str) {
-        println!("Connection Established:");
-        println!("  - Secured: {}", is_secured);
-        println!("  - Direct (P2P): {}", direct);
-        println!("  - Stream Type: '{}'", stream_type);
-        if direct {
-            println!("  => Mode: Direct Connection");
+        if crate::cli_events::is_enabled() {
+            crate::cli_events::emit(
+                "connection",
+                serde_json::json!({
+                    "id": "",
+                    "conn": if direct { "direct" } else { "relay" },
+                    "secured": is_secured,
+                    "stream_type": stream_type,
+                }),
+            );
         } else {
-            println!("  => Mode: Relay Connection");
+            println!("Connection Established:");
+            println!("  - Secured: {}", is_secured);
+            println!("  - Direct (P2P): {}", direct);
+            println!("  - Stream Type: '{}'", stream_type);
+            if direct {
+                println!("  => Mode: Direct Connection");
+            } else {
+                println!("  => Mode: Relay Connection");
+            }
         }
+        self.send_event(SessionEvent::ConnectionType {
+            secured: is_secured,
+            direct,
+            stream_type: stream_type.to_owned(),
+        });
     }
-    fn set_fingerprint(&self, _fingerprint: String) {}
+    fn set_fingerprint(&self, fingerprint: String) {
+        self.send_event(SessionEvent::Fingerprint { fingerprint });
+    }
+    // File transfer sessions do not use the bridge.
     fn job_error(&self, _id: i32, _err: String, _file_num: i32) {}
     fn job_done(&self, _id: i32, _file_num: i32) {}
     fn clear_all_jobs(&self) {}
-    fn new_message(&self, _msg: String) {}
+    fn new_message(&self, msg: String) {
+        self.send_event(SessionEvent::ChatMessage { text: msg });
+    }
     fn update_transfer_list(&self) {}
     fn load_last_job(&self, _cnt: i32, _job_json: &str, _auto_start: bool) {}
     fn update_folder_files(
@@ -164,14 +412,17 @@ impl InvokeUiSession for ElectronUiHandler {
         _is_identical: bool,
     ) {
     }
-    fn update_block_input_state(&self, _on: bool) {}
+    fn update_block_input_state(&self, on: bool) {
+        self.send_event(SessionEvent::BlockInputState { on });
+    }
     fn job_progress(&self, _id: i32, _file_num: i32, _speed: f64, _finished_size: f64) {}
     fn adapt_size(&self) {}
     
-    fn on_rgba(&self, _display: usize, rgba: &mut ImageRgb) {
-        // Simple serialization: type (1 byte) + width (4 bytes) + height (4 bytes) + raw data
-        // Type 0 = Video Frame
-        // Frame logging removed for performance
+    fn on_rgba(&self, display: usize, rgba: &mut ImageRgb) {
+        if !self.client_ready.load(Ordering::SeqCst) {
+            return;
+        }
+        // Serialization: type (1 byte) + display (1 byte) + width (4 bytes) + height (4 bytes) + raw data
         
         // Swap BGR to RGB (or vice versa)
         // RustDesk usually uses BGRA internally on Windows. HTML5 Canvas expects RGBA.
@@ -180,114 +431,197 @@ impl InvokeUiSession for ElectronUiHandler {
             chunk.swap(0, 2);
         }
 
-        let mut data = Vec::with_capacity(1 + 8 + rgba.raw.len());
-        data.push(0); // 0 = Video Frame
+        let mut data = Vec::with_capacity(2 + 8 + rgba.raw.len());
+        data.push(FRAME_RGBA);
+        data.push(display as u8);
         data.extend_from_slice(&(rgba.w as u32).to_le_bytes());
         data.extend_from_slice(&(rgba.h as u32).to_le_bytes());
         data.extend_from_slice(&rgba.raw);
+        self.send(Message::Binary(data.into()));
+    }
 
-        if let Some(sender) = self.video_sender.lock().unwrap().as_ref() {
-            if let Err(e) = sender.send(data) {
-                log::error!("Failed to send video frame to channel: {}", e);
-            } else {
-                // Frame sent successfully
+    fn on_encoded_video_frame(&self, display: usize, vf: &VideoFrame) -> bool {
+        // Nobody to show it to, not even worth decoding. The handshake of the
+        // next client refreshes the video.
+        if !self.client_ready.load(Ordering::SeqCst) {
+            return true;
+        }
+        let format = CodecFormat::from(vf);
+        let mut passthrough = self.passthrough.lock().unwrap();
+        let Some(codec) = VIDEO_CODECS
+            .iter()
+            .find(|(_, f, _)| *f == format && passthrough.codecs.contains(f))
+            .map(|(_, _, codec)| *codec)
+        else {
+            // Decoded here, the client needs a key frame again when the peer
+            // switches back to a codec it supports.
+            passthrough.started.remove(&display);
+            return false;
+        };
+        let frames = match &vf.union {
+            Some(video_frame::Union::Vp8s(frames))
+            | Some(video_frame::Union::Vp9s(frames))
+            | Some(video_frame::Union::Av1s(frames))
+            | Some(video_frame::Union::H264s(frames))
+            | Some(video_frame::Union::H265s(frames)) => frames,
+            _ => return false,
+        };
+        if !passthrough.started.contains(&display) {
+            if !frames.frames.first().map_or(false, |f| f.key) {
+                // Dropped, the client can not decode it without the key frame.
+                if passthrough.refresh_requested.insert(display) {
+                    drop(passthrough);
+                    self.refresh_display(display);
+                }
+                return true;
             }
-        } else {
-            log::error!("video_sender is None!");
+            passthrough.started.insert(display);
+            passthrough.refresh_requested.remove(&display);
+        }
+        drop(passthrough);
+        for frame in frames.frames.iter() {
+            // display (1 byte) + codec (1 byte) + key (1 byte) + pts (8 bytes) + data
+            let mut data = Vec::with_capacity(2 + 10 + frame.data.len());
+            data.push(FRAME_ENCODED);
+            data.push(display as u8);
+            data.push(codec);
+            data.push(frame.key as u8);
+            data.extend_from_slice(&frame.pts.to_le_bytes());
+            data.extend_from_slice(&frame.data);
+            self.send(Message::Binary(data.into()));
         }
+        true
     }
 
-    fn msgbox(&self, msgtype: &str, title: &str, text: &str, link: &str, _retry: bool) {
+    fn msgbox(&self, msgtype: &str, title: &str, text: &str, link: &str, retry: bool) {
         // Log critical errors
         if msgtype == "error" || msgtype == "re-input-password" {
             log::error!("Connection error - {}: {}", title, text);
         }
-        
-        // Send error notification to Electron client
-        let msg = serde_json::json!({
-            "type": "error",
-            "title": title,
-            "message": text,
-            "msgtype": msgtype,
-            "link": link
-        });
-        
-        if let Some(sender) = self.video_sender.lock().unwrap().as_ref() {
-            let mut data = Vec::new();
-            data.push(1); // 1 = JSON message
-            data.extend_from_slice(msg.to_string().as_bytes());
-            let _ = sender.send(data);
+        // For the exit code of `--connect`, the client may still retry.
+        if msgtype == "re-input-password" {
+            crate::cli_events::set_exit_code(crate::cli_events::exit_code_of(title));
+            crate::cli_events::emit("login_error", serde_json::json!({ "id": "", "error": title }));
+        } else if msgtype.contains("error") {
+            crate::cli_events::error("", text);
         }
+        self.send_event(SessionEvent::Msgbox {
+            msgtype: msgtype.to_owned(),
+            title: title.to_owned(),
+            text: text.to_owned(),
+            link: link.to_owned(),
+            retry,
+        });
     }
     #[cfg(any(target_os = "android", target_os = "ios"))]
     fn clipboard(&self, _content: String) {}
-    fn cancel_msgbox(&self, _tag: &str) {}
+    fn on_clipboards(&self, clipboards: &[Clipboard]) -> bool {
+        // Without a client the local clipboard is updated as before.
+        if !self.client_ready.load(Ordering::SeqCst) {
+            return false;
+        }
+        let items: Vec<ClipboardItem> =
+            crate::clipboard::from_multi_clipbards(clipboards.to_vec())
+                .into_iter()
+                .filter_map(ClipboardItem::from_data)
+                .collect();
+        if !items.is_empty() {
+            self.send_event(SessionEvent::Clipboard { items });
+        }
+        true
+    }
+    fn cancel_msgbox(&self, tag: &str) {
+        self.send_event(SessionEvent::CancelMsgbox {
+            tag: tag.to_owned(),
+        });
+    }
     fn switch_back(&self, _id: &str) {}
-    fn portable_service_running(&self, _running: bool) {}
-    fn on_voice_call_started(&self) {}
-    fn on_voice_call_closed(&self, _reason: &str) {}
-    fn on_voice_call_waiting(&self) {}
-    fn on_voice_call_incoming(&self) {}
+    fn portable_service_running(&self, running: bool) {
+        self.send_event(SessionEvent::PortableServiceRunning { running });
+    }
+    fn on_voice_call_started(&self) {
+        self.send_voice_call("started", "");
+    }
+    fn on_voice_call_closed(&self, reason: &str) {
+        self.send_voice_call("closed", reason);
+    }
+    fn on_voice_call_waiting(&self) {
+        self.send_voice_call("waiting", "");
+    }
+    fn on_voice_call_incoming(&self) {
+        self.send_voice_call("incoming", "");
+    }
     fn get_rgba(&self, _display: usize) -> *const u8 {
         std::ptr::null()
     }
     fn next_rgba(&self, _display: usize) {}
     #[cfg(all(feature = "vram", feature = "flutter"))]
     fn on_texture(&self, _display: usize, _texture: *mut c_void) {}
-    fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
-    fn set_current_display(&self, _disp_idx: i32) {}
+    fn set_multiple_windows_session(&self, sessions: Vec<WindowsSession>) {
+        self.send_event(SessionEvent::WindowsSessions {
+            sessions: sessions.iter().map(|s| s.into()).collect(),
+        });
+    }
+    fn set_current_display(&self, disp_idx: i32) {
+        self.state.lock().unwrap().current_display = disp_idx;
+        self.send_event(SessionEvent::CurrentDisplay { display: disp_idx });
+    }
     #[cfg(feature = "flutter")]
     fn is_multi_ui_session(&self) -> bool { false }
-    fn update_record_status(&self, _start: bool) {}
+    fn update_record_status(&self, start: bool) {
+        // Frames are decoded here while recording, the client needs a key
+        // frame when passthrough resumes.
+        self.passthrough.lock().unwrap().started.clear();
+        self.send_event(SessionEvent::RecordStatus { recording: start });
+    }
     fn printer_request(&self, _id: i32, _path: String) {}
     fn handle_screenshot_resp(&self, _sid: String, _msg: String) {}
     fn handle_terminal_response(&self, _response: TerminalResponse) {}
 }
 
-pub async fn start_electron_server(port: u16, input_sender: Arc<std::sync::RwLock<Option<mpsc::UnboundedSender<Data>>>>) -> ResultType<ElectronUiHandler> {
-    let addr = format!("127.0.0.1:{}", port);
-    let listener = TcpListener::bind(&addr).await?;
-    log::info!("Electron WebSocket server listening on: {}", addr);
+impl ElectronUiHandler {
+    fn send_voice_call(&self, state: &str, reason: &str) {
+        self.send_event(SessionEvent::VoiceCall {
+            state: state.to_owned(),
+            reason: reason.to_owned(),
+        });
+    }
+}
+
+/// Listens on `port` of localhost, any free one if it is 0. Clients have to give
+/// the token of the returned endpoint as `?token=` of the WebSocket URL.
+pub async fn start_electron_server(port: u16, input_sender: InputSender) -> ResultType<(ElectronUiHandler, BridgeEndpoint)> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    let endpoint = BridgeEndpoint {
+        port: listener.local_addr()?.port(),
+        token: hbb_common::rand::random::<[u8; 32]>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    };
+    log::info!("Electron WebSocket server listening on: 127.0.0.1:{}", endpoint.port);
+    let token = endpoint.token.clone();
 
-    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
     let handler = ElectronUiHandler::new(tx, input_sender.clone());
+    let ui_handler = handler.clone();
     
     tokio::spawn(async move {
-        while let Ok((stream, _)) = listener.accept().await {
-            match accept_async(stream).await {
-                Ok(mut ws_stream) => {
+        while let Ok((stream, addr)) = listener.accept().await {
+            let callback = |req: &HttpRequest, resp: Response| match authorize(req, &token) {
+                Ok(()) => Ok(resp),
+                Err(reason) => {
+                    log::warn!("Rejected WebSocket connection from {}: {}", addr, reason);
+                    let mut resp = ErrorResponse::new(Some(reason.to_owned()));
+                    *resp.status_mut() = StatusCode::FORBIDDEN;
+                    Err(resp)
+                }
+            };
+            match accept_hdr_async(stream, callback).await {
+                Ok(ws_stream) => {
                     log::info!("New WebSocket connection");
-                    
-                    loop {
-                        tokio::select! {
-                            Some(frame_data) = rx.recv() => {
-                                // Sending video frame to WebSocket
-                                let msg = Message::Binary(frame_data.into());
-                                if let Err(e) = ws_stream.send(msg).await {
-                                    log::error!("Error sending video frame: {}", e);
-                                    break;
-                                }
-                            }
-                            msg = ws_stream.next() => {
-                                match msg {
-                                    Some(Ok(Message::Text(text))) => {
-                                        if let Ok(event) = serde_json::from_str::<InputEvent>(&text) {
-                                            handle_input_event(&input_sender, event);
-                                        } else {
-                                            log::warn!("Failed to parse input event: {}", text);
-                                        }
-                                    }
-                                    Some(Ok(Message::Close(_))) => break,
-                                    Some(Err(e)) => {
-                                        log::error!("WebSocket error: {}", e);
-                                        break;
-                                    }
-                                    None => break,
-                                    _ => {}
-                                }
-                            }
-                        }
-                    }
+                    serve_client(ws_stream, &mut rx, &ui_handler, &input_sender).await;
+                    log::info!("WebSocket connection closed");
                 }
                 Err(e) => {
                     log::error!("Error during the websocket handshake: {}", e);
@@ -296,10 +630,121 @@ pub async fn start_electron_server(port: u16, input_sender: Arc<std::sync::RwLoc
         }
     });
 
-    Ok(handler)
+    Ok((handler, endpoint))
+}
+
+/// One client, until it disconnects. Messages of the session before the
+/// handshake are dropped.
+async fn serve_client(
+    mut ws_stream: WebSocketStream<TcpStream>,
+    rx: &mut mpsc::UnboundedReceiver<Message>,
+    ui_handler: &ElectronUiHandler,
+    input_sender: &InputSender,
+) {
+    let mut ready = false;
+    loop {
+        tokio::select! {
+            Some(msg) = rx.recv() => {
+                if !ready {
+                    continue;
+                }
+                if let Err(e) = ws_stream.send(msg).await {
+                    log::error!("Error sending to WebSocket: {}", e);
+                    break;
+                }
+            }
+            msg = ws_stream.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Err(e)) => {
+                        log::error!("WebSocket error: {}", e);
+                        break;
+                    }
+                    _ => continue,
+                };
+                let msg = match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        log::warn!("Invalid message from the client: {}", e);
+                        continue;
+                    }
+                };
+                let reply = match msg {
+                    ClientMessage::Hello { version, video_codecs } => {
+                        if version != VERSION {
+                            let message = format!("unsupported protocol version {}, this is {}", version, VERSION);
+                            log::error!("{}", message);
+                            ws_stream.send(Message::Text(ServerMessage::Error { message }.to_text().into())).await.ok();
+                            ws_stream.close(None).await.ok();
+                            break;
+                        }
+                        ready = true;
+                        ui_handler.client_ready.store(true, Ordering::SeqCst);
+                        ui_handler.set_video_codecs(&video_codecs);
+                        hello()
+                    }
+                    _ if !ready => ServerMessage::Error {
+                        message: "expected hello".to_owned(),
+                    },
+                    ClientMessage::Input { event } => {
+                        handle_input_event(input_sender, event);
+                        continue;
+                    }
+                    ClientMessage::Request { id, method, params } => ServerMessage::response(
+                        id,
+                        Request::parse(&method, params).and_then(|r| ui_handler.handle_request(r)),
+                    ),
+                };
+                if let Err(e) = ws_stream.send(Message::Text(reply.to_text().into())).await {
+                    log::error!("Error sending to WebSocket: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+    ui_handler.client_ready.store(false, Ordering::SeqCst);
+}
+
+fn hello() -> ServerMessage {
+    let mut capabilities = vec!["video_rgba", "video_encoded", "clipboard"];
+    if UNLOCK_PASSWORD.lock().unwrap().is_some() {
+        capabilities.push("unlock_password");
+    }
+    ServerMessage::Hello {
+        version: VERSION,
+        server_version: crate::VERSION.to_owned(),
+        capabilities,
+    }
 }
 
-fn handle_input_event(sender: &Arc<std::sync::RwLock<Option<mpsc::UnboundedSender<Data>>>>, event: InputEvent) {
+fn authorize(req: &HttpRequest, token: &str) -> Result<(), &'static str> {
+    if let Some(origin) = req.headers().get("origin") {
+        if !ALLOWED_ORIGINS.iter().any(|o| origin.as_bytes() == o.as_bytes()) {
+            return Err("origin not allowed");
+        }
+    }
+    let given = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|kv| kv.strip_prefix("token="))
+        .unwrap_or_default();
+    // Compared in constant time, the token is the only thing guarding the session.
+    if given.len() != token.len()
+        || given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            != 0
+    {
+        return Err("invalid token");
+    }
+    Ok(())
+}
+
+fn handle_input_event(sender: &InputSender, event: InputEvent) {
     use crate::client::send_mouse;
     use crate::input::{MOUSE_BUTTON_LEFT, MOUSE_BUTTON_RIGHT, MOUSE_TYPE_DOWN, MOUSE_TYPE_UP, MOUSE_TYPE_MOVE, MOUSE_TYPE_WHEEL};
 
@@ -410,22 +855,35 @@ fn handle_input_event(sender: &Arc<std::sync::RwLock<Option<mpsc::UnboundedSende
         }
         InputEvent::SendText { text, enter } => {
             log::info!("Sending text (len={}), enter={}", text.len(), enter);
-            // Type each character as key events
-            for c in text.chars() {
-                let key_str = c.to_string();
-                send_key(&key_str, true, &interface);
-                send_key(&key_str, false, &interface);
-            }
-            // Send Enter key if requested
-            if enter {
-                std::thread::sleep(std::time::Duration::from_millis(100));
-                send_key("Enter", true, &interface);
-                send_key("Enter", false, &interface);
-            }
+            send_text(&text, enter, &interface);
+        }
+        InputEvent::SendUnlockPw => {
+            let password = UNLOCK_PASSWORD.lock().unwrap();
+            let Some(password) = password.as_ref() else {
+                log::warn!("No unlock password given");
+                return;
+            };
+            log::info!("Sending unlock password");
+            send_text(password, true, &interface);
         }
     }
 }
 
+fn send_text(text: &str, enter: bool, interface: &impl crate::client::Interface) {
+    // Type each character as key events
+    for c in text.chars() {
+        let key_str = c.to_string();
+        send_key(&key_str, true, interface);
+        send_key(&key_str, false, interface);
+    }
+    // Send Enter key if requested
+    if enter {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        send_key("Enter", true, interface);
+        send_key("Enter", false, interface);
+    }
+}
+
 fn send_key(key: &str, down: bool, interface: &impl crate::client::Interface) {
     use hbb_common::message_proto::{KeyEvent, ControlKey, KeyboardMode};
 
@@ -495,3 +953,159 @@ fn send_key(key: &str, down: bool, interface: &impl crate::client::Interface) {
         log::warn!("Unknown key: {}", key);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio_tungstenite::{connect_async, MaybeTlsStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn connect(endpoint: &BridgeEndpoint) -> Client {
+        let url = format!("ws://127.0.0.1:{}/?token={}", endpoint.port, endpoint.token);
+        connect_async(url).await.unwrap().0
+    }
+
+    async fn send(ws: &mut Client, v: Value) {
+        ws.send(Message::Text(v.to_string().into())).await.unwrap();
+    }
+
+    async fn recv(ws: &mut Client) -> Value {
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap() {
+                Some(Ok(Message::Text(text))) => return serde_json::from_str(&text).unwrap(),
+                Some(Ok(Message::Binary(_))) => continue,
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    async fn recv_input(rx: &mut mpsc::UnboundedReceiver<Data>, f: impl Fn(&hbb_common::message_proto::Message) -> bool) {
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap() {
+                Some(Data::Message(msg)) if f(&msg) => return,
+                Some(_) => continue,
+                None => panic!("input channel closed"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bridge() {
+        let (input_tx, mut input_rx) = mpsc::unbounded_channel();
+        let (handler, endpoint) = start_electron_server(0, Arc::new(RwLock::new(Some(input_tx))))
+            .await
+            .unwrap();
+        assert!(connect_async(format!("ws://127.0.0.1:{}/", endpoint.port)).await.is_err());
+
+        let mut ws = connect(&endpoint).await;
+        send(&mut ws, json!({"type": "request", "id": 1, "method": "get_session"})).await;
+        assert_eq!(recv(&mut ws).await, json!({"type": "error", "message": "expected hello"}));
+
+        send(&mut ws, json!({"type": "hello", "version": VERSION, "video_codecs": ["vp9", "mpeg2"]})).await;
+        let hello = recv(&mut ws).await;
+        assert_eq!(hello["type"], "hello");
+        assert_eq!(hello["version"], VERSION);
+        // The new codecs need a key frame.
+        recv_input(&mut input_rx, |msg| msg.misc().refresh_video()).await;
+
+        handler.set_permission("keyboard", false);
+        assert_eq!(
+            recv(&mut ws).await,
+            json!({"type": "event", "event": "permission", "data": {"name": "keyboard", "value": false}})
+        );
+
+        send(&mut ws, json!({"type": "request", "id": 2, "method": "get_session"})).await;
+        let response = recv(&mut ws).await;
+        assert_eq!(response["id"], 2);
+        assert_eq!(response["result"]["permissions"], json!({"keyboard": false}));
+        assert_eq!(response["result"]["video_codecs"], json!(["vp9"]));
+
+        send(&mut ws, json!({"type": "request", "id": 3, "method": "format_disk"})).await;
+        assert_eq!(
+            recv(&mut ws).await,
+            json!({"type": "response", "id": 3, "error": "unknown method format_disk"})
+        );
+
+        send(&mut ws, json!({"type": "input", "event": {"type": "mousemove", "x": 10, "y": 20}})).await;
+        recv_input(&mut input_rx, |msg| msg.has_mouse_event()).await;
+
+        let mut peer_info = PeerInfo::new();
+        peer_info.displays = vec![DisplayInfo::new(), DisplayInfo::new()];
+        handler.set_peer_info(&peer_info);
+        assert_eq!(recv(&mut ws).await["event"], "peer_info");
+        send(&mut ws, json!({"type": "request", "id": 4, "method": "switch_display", "params": {"display": 2}})).await;
+        assert_eq!(recv(&mut ws).await["error"], "no display 2, the peer has 2");
+        send(&mut ws, json!({"type": "request", "id": 5, "method": "switch_display", "params": {"display": 1}})).await;
+        assert_eq!(recv(&mut ws).await, json!({"type": "response", "id": 5, "result": null}));
+        recv_input(&mut input_rx, |msg| msg.misc().has_switch_display() && msg.misc().switch_display().display == 1).await;
+        recv_input(&mut input_rx, |msg| msg.misc().capture_displays().set == vec![1]).await;
+
+        let mut rgba = ImageRgb::new(scrap::ImageFormat::ARGB, 1);
+        rgba.w = 1;
+        rgba.h = 1;
+        rgba.raw = vec![1, 2, 3, 4];
+        handler.on_rgba(1, &mut rgba);
+        match tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap() {
+            Some(Ok(Message::Binary(data))) => {
+                assert_eq!(&data[..], &[FRAME_RGBA, 1, 1, 0, 0, 0, 1, 0, 0, 0, 3, 2, 1, 4]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let set_clipboard = json!({"items": [{"format": "text", "text": "hi"}]});
+        send(&mut ws, json!({"type": "request", "id": 6, "method": "set_clipboard", "params": set_clipboard})).await;
+        assert_eq!(recv(&mut ws).await["error"], "the clipboard is not allowed by the peer");
+        handler.set_permission("keyboard", true);
+        assert_eq!(recv(&mut ws).await["event"], "permission");
+        send(&mut ws, json!({"type": "request", "id": 7, "method": "set_clipboard", "params": set_clipboard})).await;
+        assert_eq!(recv(&mut ws).await, json!({"type": "response", "id": 7, "result": null}));
+        recv_input(&mut input_rx, |msg| msg.has_clipboard() || msg.has_multi_clipboards()).await;
+
+        let clipboards = crate::clipboard::create_multi_clipboards(vec![arboard::ClipboardData::Text("peer".to_owned())]);
+        assert!(handler.on_clipboards(&clipboards.clipboards));
+        assert_eq!(
+            recv(&mut ws).await,
+            json!({"type": "event", "event": "clipboard", "data": {"items": [{"format": "text", "text": "peer"}]}})
+        );
+        drop(ws);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // Without a client the local clipboard is updated.
+        assert!(!handler.on_clipboards(&clipboards.clipboards));
+    }
+
+    #[tokio::test]
+    async fn test_bridge_version() {
+        let (_handler, endpoint) = start_electron_server(0, Default::default()).await.unwrap();
+        let mut ws = connect(&endpoint).await;
+        send(&mut ws, json!({"type": "hello", "version": VERSION + 1})).await;
+        let error = recv(&mut ws).await;
+        assert_eq!(error["type"], "error");
+        assert!(matches!(
+            tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap(),
+            Some(Ok(Message::Close(_))) | None
+        ));
+    }
+
+    #[test]
+    fn test_authorize() {
+        let req = |uri: &str, origin: Option<&str>| {
+            let mut req = HttpRequest::builder().uri(uri);
+            if let Some(origin) = origin {
+                req = req.header("Origin", origin);
+            }
+            req.body(()).unwrap()
+        };
+        let token = "0123abcd";
+        assert!(authorize(&req("/?token=0123abcd", None), token).is_ok());
+        assert!(authorize(&req("/?x=1&token=0123abcd", Some("file://")), token).is_ok());
+        assert!(authorize(&req("/", None), token).is_err());
+        assert!(authorize(&req("/?token=0123abce", None), token).is_err());
+        assert!(authorize(&req("/?token=0123abcd0", None), token).is_err());
+        assert!(
+            authorize(&req("/?token=0123abcd", Some("http://evil.example")), token).is_err()
+        );
+    }
+}
@@ -0,0 +1,576 @@
+// Wire protocol between the CLI and the Electron client, see electron_interface.rs.
+//
+// The client connects to `ws://127.0.0.1:<SDFDESK_BRIDGE_PORT>/?token=<SDFDESK_BRIDGE_TOKEN>`.
+// Text frames are JSON objects with a `type`, binary frames are video.
+//
+// Handshake, the first message of the client:
+//     {"type":"hello","version":2,"video_codecs":["vp9","h264"]}
+// answered by
+//     {"type":"hello","version":2,"server_version":"1.4.1","capabilities":["video_rgba",...]}
+// or by {"type":"error","message":...} and a close if the version is not
+// supported. Nothing else is sent or accepted before the handshake, the client
+// gets the current state with the `get_session` request.
+//
+// Client to CLI:
+//     {"type":"input","event":{"type":"mousemove","x":10,"y":20}}    see `InputEvent`
+//     {"type":"request","id":1,"method":"get_session","params":{}}  see `Request`
+// Every request is answered once, with the same id:
+//     {"type":"response","id":1,"result":{...}}
+//     {"type":"response","id":1,"error":"unknown method"}
+//
+// CLI to client, the callbacks of the session:
+//     {"type":"event","event":"permission","data":{"name":"keyboard","value":true}}
+// see `SessionEvent`.
+//
+// Binary frames, the first byte is the kind and the second the display index:
+//     0  RGBA frame: display (u8) + width (u32) + height (u32) + pixels
+//     2  encoded frame: display (u8) + codec (u8, see `VIDEO_CODECS`) + key (u8)
+//        + pts in ms (i64) + data
+// Numbers are little endian. Frames of several displays arrive when more than
+// one is captured, see the `capture_displays` request, each display is placed
+// at its `x` and `y` of the `displays` event. Mouse positions are in the same
+// coordinates.
+//
+// `VERSION` changes when a change breaks clients, new events, requests and
+// fields do not change it.
+
+use arboard::{ClipboardData, ImageData};
+use hbb_common::{
+    message_proto::{DisplayInfo, PeerInfo, WindowsSession},
+    sodiumoxide::base64,
+};
+use scrap::CodecFormat;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+pub const VERSION: u32 = 2;
+
+pub const FRAME_RGBA: u8 = 0;
+pub const FRAME_ENCODED: u8 = 2;
+
+/// The codec names of `hello` and `set_video_codecs`, and the ids of encoded frames.
+pub const VIDEO_CODECS: [(&str, CodecFormat, u8); 5] = [
+    ("vp8", CodecFormat::VP8, 0),
+    ("vp9", CodecFormat::VP9, 1),
+    ("av1", CodecFormat::AV1, 2),
+    ("h264", CodecFormat::H264, 3),
+    ("h265", CodecFormat::H265, 4),
+];
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Hello {
+        version: u32,
+        // The codecs the client decodes with WebCodecs, empty for RGBA only.
+        #[serde(default)]
+        video_codecs: Vec<String>,
+    },
+    Input {
+        event: InputEvent,
+    },
+    Request {
+        id: u64,
+        method: String,
+        #[serde(default)]
+        params: Value,
+    },
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "type")]
+pub enum InputEvent {
+    #[serde(rename = "mousemove")]
+    MouseMove { x: i32, y: i32 },
+    #[serde(rename = "mousedown")]
+    MouseDown { btn: String, x: i32, y: i32 },
+    #[serde(rename = "mouseup")]
+    MouseUp { btn: String, x: i32, y: i32 },
+    #[serde(rename = "wheel")]
+    Wheel { delta_x: i32, delta_y: i32 },
+    #[serde(rename = "keydown")]
+    KeyDown { key: String },
+    #[serde(rename = "keyup")]
+    KeyUp { key: String },
+    // Login control events
+    #[serde(rename = "send_sas")]
+    SendSas,
+    #[serde(rename = "send_text")]
+    SendText { text: String, enter: bool },
+    // Types the unlock password given to the CLI, so it never reaches the client.
+    #[serde(rename = "send_unlock_pw")]
+    SendUnlockPw,
+}
+
+/// The methods of requests with their params.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Request {
+    /// The state of the session, result `SessionState`.
+    GetSession,
+    /// Result `{"codecs": [...]}`, the ones which are forwarded encoded.
+    SetVideoCodecs {
+        codecs: Vec<String>,
+    },
+    RefreshVideo,
+    /// Shows only this display.
+    SwitchDisplay {
+        display: i32,
+    },
+    /// Adds, removes or sets the displays which are captured, `set` for all of
+    /// them side by side.
+    CaptureDisplays {
+        #[serde(default)]
+        add: Vec<i32>,
+        #[serde(default)]
+        sub: Vec<i32>,
+        #[serde(default)]
+        set: Vec<i32>,
+    },
+    /// Sets the clipboard of the peer, if its clipboard permission allows it.
+    SetClipboard {
+        items: Vec<ClipboardItem>,
+    },
+}
+
+impl Request {
+    /// `params` may be left out or empty for methods without any.
+    pub fn parse(method: &str, params: Value) -> Result<Self, String> {
+        let mut v = serde_json::json!({ "method": method });
+        if !params.is_null() && params != Value::Object(Default::default()) {
+            v["params"] = params;
+        }
+        serde_json::from_value(v).map_err(|err| {
+            if err.to_string().starts_with("unknown variant") {
+                format!("unknown method {}", method)
+            } else {
+                format!("invalid params: {}", err)
+            }
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Hello {
+        version: u32,
+        server_version: String,
+        capabilities: Vec<&'static str>,
+    },
+    Event(SessionEvent),
+    Response {
+        id: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result: Option<Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    pub fn response(id: u64, result: Result<Value, String>) -> Self {
+        match result {
+            Ok(v) => ServerMessage::Response {
+                id,
+                result: Some(v),
+                error: None,
+            },
+            Err(err) => ServerMessage::Response {
+                id,
+                result: None,
+                error: Some(err),
+            },
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum SessionEvent {
+    Connected {
+        conn_type: String,
+    },
+    ConnectionType {
+        secured: bool,
+        direct: bool,
+        stream_type: String,
+    },
+    /// The first frame arrived.
+    SessionReady,
+    PeerInfo(PeerInfoData),
+    Displays {
+        displays: Vec<Display>,
+    },
+    SwitchDisplay {
+        display: i32,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        cursor_embedded: bool,
+    },
+    CurrentDisplay {
+        display: i32,
+    },
+    PlatformAdditions {
+        data: String,
+    },
+    WindowsSessions {
+        sessions: Vec<WindowsSessionData>,
+    },
+    Permission {
+        name: String,
+        value: bool,
+    },
+    PrivacyMode,
+    BlockInputState {
+        on: bool,
+    },
+    RecordStatus {
+        recording: bool,
+    },
+    PortableServiceRunning {
+        running: bool,
+    },
+    QualityStatus {
+        speed: Option<String>,
+        fps: HashMap<usize, i32>,
+        delay: Option<i32>,
+        target_bitrate: Option<i32>,
+        codec_format: Option<String>,
+        chroma: Option<String>,
+    },
+    Fingerprint {
+        fingerprint: String,
+    },
+    /// The colors are RGBA, base64.
+    CursorData {
+        id: u64,
+        hotx: i32,
+        hoty: i32,
+        width: i32,
+        height: i32,
+        colors: String,
+    },
+    CursorPosition {
+        x: i32,
+        y: i32,
+    },
+    Msgbox {
+        msgtype: String,
+        title: String,
+        text: String,
+        link: String,
+        retry: bool,
+    },
+    CancelMsgbox {
+        tag: String,
+    },
+    ChatMessage {
+        text: String,
+    },
+    /// `started`, `waiting`, `incoming` or `closed`.
+    VoiceCall {
+        state: String,
+        reason: String,
+    },
+    /// The clipboard of the peer changed.
+    Clipboard {
+        items: Vec<ClipboardItem>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Display {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub name: String,
+    pub online: bool,
+    pub cursor_embedded: bool,
+    pub scale: f64,
+}
+
+impl From<&DisplayInfo> for Display {
+    fn from(d: &DisplayInfo) -> Self {
+        Display {
+            x: d.x,
+            y: d.y,
+            width: d.width,
+            height: d.height,
+            name: d.name.clone(),
+            online: d.online,
+            cursor_embedded: d.cursor_embedded,
+            scale: d.scale,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PeerInfoData {
+    pub username: String,
+    pub hostname: String,
+    pub platform: String,
+    pub version: String,
+    pub sas_enabled: bool,
+    pub current_display: i32,
+    pub displays: Vec<Display>,
+}
+
+impl From<&PeerInfo> for PeerInfoData {
+    fn from(pi: &PeerInfo) -> Self {
+        PeerInfoData {
+            username: pi.username.clone(),
+            hostname: pi.hostname.clone(),
+            platform: pi.platform.clone(),
+            version: pi.version.clone(),
+            sas_enabled: pi.sas_enabled,
+            current_display: pi.current_display,
+            displays: pi.displays.iter().map(Display::from).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WindowsSessionData {
+    pub sid: u32,
+    pub name: String,
+}
+
+impl From<&WindowsSession> for WindowsSessionData {
+    fn from(s: &WindowsSession) -> Self {
+        WindowsSessionData {
+            sid: s.sid,
+            name: s.name.clone(),
+        }
+    }
+}
+
+/// One format of a clipboard, `text`, `html` and `rtf` in `text`, images in
+/// `data` as base64: `image_png` a PNG file, `image_rgba` the pixels.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClipboardItem {
+    pub format: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<usize>,
+}
+
+impl ClipboardItem {
+    fn text(format: &str, text: String) -> Self {
+        ClipboardItem {
+            format: format.to_owned(),
+            text: Some(text),
+            ..Default::default()
+        }
+    }
+
+    /// None for the formats the client does not get, like SVG and the
+    /// special ones of RustDesk.
+    pub fn from_data(data: ClipboardData) -> Option<Self> {
+        let item = match data {
+            ClipboardData::Text(s) => Self::text("text", s),
+            ClipboardData::Html(s) => Self::text("html", s),
+            ClipboardData::Rtf(s) => Self::text("rtf", s),
+            ClipboardData::Image(ImageData::Png(png)) => ClipboardItem {
+                format: "image_png".to_owned(),
+                data: Some(base64::encode(&png, base64::Variant::Original)),
+                ..Default::default()
+            },
+            ClipboardData::Image(ImageData::Rgba(rgba)) => ClipboardItem {
+                format: "image_rgba".to_owned(),
+                data: Some(base64::encode(&rgba.bytes, base64::Variant::Original)),
+                width: Some(rgba.width),
+                height: Some(rgba.height),
+                ..Default::default()
+            },
+            _ => return None,
+        };
+        Some(item)
+    }
+
+    pub fn to_data(&self) -> Result<ClipboardData, String> {
+        let text = || {
+            self.text
+                .clone()
+                .ok_or_else(|| format!("no text for {}", self.format))
+        };
+        let data = || {
+            let data = self
+                .data
+                .as_deref()
+                .ok_or_else(|| format!("no data for {}", self.format))?;
+            base64::decode(data, base64::Variant::Original)
+                .map_err(|_| format!("invalid base64 for {}", self.format))
+        };
+        match self.format.as_str() {
+            "text" => Ok(ClipboardData::Text(text()?)),
+            "html" => Ok(ClipboardData::Html(text()?)),
+            "rtf" => Ok(ClipboardData::Rtf(text()?)),
+            "image_png" => Ok(ClipboardData::Image(ImageData::png(data()?.into()))),
+            "image_rgba" => {
+                let (width, height) = self.width.zip(self.height).unwrap_or_default();
+                let data = data()?;
+                let size = width
+                    .checked_mul(height)
+                    .and_then(|n| n.checked_mul(4))
+                    .ok_or_else(|| format!("invalid image size {}x{}", width, height))?;
+                if size != data.len() {
+                    return Err(format!(
+                        "{} bytes for a {}x{} image",
+                        data.len(),
+                        width,
+                        height
+                    ));
+                }
+                Ok(ClipboardData::Image(ImageData::rgba(
+                    width,
+                    height,
+                    data.into(),
+                )))
+            }
+            format => Err(format!("unknown clipboard format {}", format)),
+        }
+    }
+}
+
+/// The result of `get_session`, kept up to date by the events.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SessionState {
+    pub peer_info: Option<PeerInfoData>,
+    pub current_display: i32,
+    pub permissions: BTreeMap<String, bool>,
+    pub video_codecs: Vec<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_client_message() {
+        let parse = |v: Value| serde_json::from_value::<ClientMessage>(v);
+        assert_eq!(
+            parse(json!({"type": "hello", "version": 1})).unwrap(),
+            ClientMessage::Hello {
+                version: 1,
+                video_codecs: vec![]
+            }
+        );
+        assert_eq!(
+            parse(json!({"type": "input", "event": {"type": "mousemove", "x": 1, "y": 2}}))
+                .unwrap(),
+            ClientMessage::Input {
+                event: InputEvent::MouseMove { x: 1, y: 2 }
+            }
+        );
+        let ClientMessage::Request { id, method, params } =
+            parse(json!({"type": "request", "id": 7, "method": "get_session"})).unwrap()
+        else {
+            panic!("not a request");
+        };
+        assert_eq!(id, 7);
+        assert_eq!(Request::parse(&method, params), Ok(Request::GetSession));
+        assert_eq!(
+            Request::parse("set_video_codecs", json!({"codecs": ["vp9"]})),
+            Ok(Request::SetVideoCodecs {
+                codecs: vec!["vp9".to_owned()]
+            })
+        );
+        assert_eq!(
+            Request::parse("format_disk", Value::Null),
+            Err("unknown method format_disk".to_owned())
+        );
+        assert_eq!(
+            Request::parse("capture_displays", json!({"set": [0, 1]})),
+            Ok(Request::CaptureDisplays {
+                add: vec![],
+                sub: vec![],
+                set: vec![0, 1]
+            })
+        );
+        assert!(Request::parse("set_video_codecs", json!({"codecs": 1}))
+            .unwrap_err()
+            .starts_with("invalid params"));
+        assert!(parse(json!({"type": "mousemove", "x": 1, "y": 2})).is_err());
+    }
+
+    #[test]
+    fn test_clipboard_item() {
+        let Ok(Request::SetClipboard { items }) = Request::parse(
+            "set_clipboard",
+            json!({"items": [
+                {"format": "text", "text": "hi"},
+                {"format": "image_rgba", "data": "AQIDBA==", "width": 1, "height": 1},
+            ]}),
+        ) else {
+            panic!("not set_clipboard");
+        };
+        for item in items.iter() {
+            let data = item.to_data().unwrap();
+            assert_eq!(ClipboardItem::from_data(data).as_ref(), Some(item));
+        }
+        let bad = |v: Value| {
+            serde_json::from_value::<ClipboardItem>(v)
+                .unwrap()
+                .to_data()
+        };
+        assert!(
+            bad(json!({"format": "image_rgba", "data": "AQIDBA==", "width": 2, "height": 1}))
+                .is_err()
+        );
+        assert!(bad(
+            json!({"format": "image_rgba", "data": "AQIDBA==", "width": usize::MAX, "height": 2})
+        )
+        .is_err());
+        assert!(bad(json!({"format": "image_png", "data": "%"})).is_err());
+        assert!(bad(json!({"format": "html"})).is_err());
+        assert_eq!(
+            bad(json!({"format": "files", "text": "/etc"})).err(),
+            Some("unknown clipboard format files".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_server_message() {
+        let v = |m: ServerMessage| serde_json::from_str::<Value>(&m.to_text()).unwrap();
+        assert_eq!(
+            v(ServerMessage::Event(SessionEvent::Permission {
+                name: "keyboard".to_owned(),
+                value: true
+            })),
+            json!({"type": "event", "event": "permission", "data": {"name": "keyboard", "value": true}})
+        );
+        assert_eq!(
+            v(ServerMessage::Event(SessionEvent::SessionReady)),
+            json!({"type": "event", "event": "session_ready"})
+        );
+        assert_eq!(
+            v(ServerMessage::response(
+                3,
+                Err("unknown method x".to_owned())
+            )),
+            json!({"type": "response", "id": 3, "error": "unknown method x"})
+        );
+        assert_eq!(
+            v(ServerMessage::response(4, Ok(Value::Null))),
+            json!({"type": "response", "id": 4, "result": null})
+        );
+    }
+}
@@ -1633,6 +1633,10 @@ pub fn main_get_permanent_password() -> String {
     ui_interface::permanent_password()
 }
 
+pub fn main_has_permanent_password() -> bool {
+    ui_interface::has_permanent_password()
+}
+
 pub fn main_get_fingerprint() -> String {
     get_fingerprint()
 }
@@ -2272,10 +2276,8 @@ pub fn is_preset_password() -> bool {
         .unwrap()
         .get("password")
         .map_or(false, |p| {
-            #[cfg(not(any(target_os = "android", target_os = "ios")))]
-            return p == &crate::ipc::get_permanent_password();
-            #[cfg(any(target_os = "android", target_os = "ios"))]
-            return p == &config::Config::get_permanent_password();
+            // Not the preset one if set by its hash.
+            ui_interface::has_permanent_password() && p == &ui_interface::permanent_password()
         })
 }
 
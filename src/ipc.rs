@@ -195,6 +195,7 @@ pub enum Data {
         is_terminal: bool,
         peer_id: String,
         name: String,
+        ip: String,
         authorized: bool,
         port_forward: String,
         keyboard: bool,
@@ -533,6 +534,8 @@ async fn handle(data: Data, stream: &mut Connection) {
                     value = Some(password::temporary_password());
                 } else if name == "permanent-password" {
                     value = Some(Config::get_permanent_password());
+                } else if name == "has-permanent-password" {
+                    value = Some(Config::has_permanent_password().to_string());
                 } else if name == "salt" {
                     value = Some(Config::get_salt());
                 } else if name == "rendezvous_server" {
@@ -1017,15 +1020,28 @@ pub fn update_temporary_password() -> ResultType<()> {
     set_config("temporary-password", "".to_owned())
 }
 
+/// Empty if the permanent password is set by its hash only, see
+/// `has_permanent_password`.
 pub fn get_permanent_password() -> String {
     if let Ok(Some(v)) = get_config("permanent-password") {
-        Config::set_permanent_password(&v);
+        // Syncing an empty one would drop the hash.
+        if !v.is_empty() || !has_permanent_password() {
+            Config::set_permanent_password(&v);
+        }
         v
     } else {
         Config::get_permanent_password()
     }
 }
 
+pub fn has_permanent_password() -> bool {
+    if let Ok(Some(v)) = get_config("has-permanent-password") {
+        v == "true"
+    } else {
+        Config::has_permanent_password()
+    }
+}
+
 pub fn get_fingerprint() -> String {
     get_config("fingerprint")
         .unwrap_or_default()
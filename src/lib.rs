@@ -40,7 +40,25 @@ mod auth_2fa;
 #[cfg(feature = "cli")]
 pub mod cli;
 #[cfg(feature = "cli")]
+pub mod cli_events;
+#[cfg(feature = "cli")]
+pub mod cli_config;
+#[cfg(feature = "cli")]
+pub mod cli_file_transfer;
+#[cfg(feature = "cli")]
+pub mod cli_peers;
+#[cfg(feature = "cli")]
+pub mod cli_policy;
+#[cfg(feature = "cli")]
+pub mod cli_screenshot;
+#[cfg(feature = "cli")]
+pub mod cli_secret;
+#[cfg(feature = "cli")]
+pub mod cli_terminal;
+#[cfg(feature = "cli")]
 pub mod electron_interface;
+#[cfg(feature = "cli")]
+pub mod electron_protocol;
 #[cfg(not(target_os = "ios"))]
 mod clipboard;
 #[cfg(not(any(target_os = "android", target_os = "ios", feature = "cli")))]
@@ -49,6 +67,8 @@ mod custom_server;
 mod lang;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 mod port_forward;
+#[cfg(not(target_os = "ios"))]
+mod port_forward_mux;
 
 #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
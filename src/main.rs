@@ -46,13 +46,83 @@ fn main() {
         .version(crate::VERSION)
         .author("sdfdesk <dj14.park@gmail.com>")
         .about("sdfdesk command line tool")
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print events as JSON lines on stdout")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("port-forward")
                 .short('p')
                 .long("port-forward")
-                .help("Format: remote-id:local-port:remote-port[:remote-host]")
+                .help("Format: [udp:|mux:]remote-id:[bind-address:]local-port:remote-port[:remote-host], can be repeated. Binds to 127.0.0.1 by default. Tunnels to the same peer share the password, but each one connects to the peer on its own")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("reverse-forward")
+                .short('R')
+                .long("reverse-forward")
+                .help("Format: remote-id:remote-port:local-port[:local-host], can be repeated")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("dynamic-forward")
+                .short('D')
+                .long("dynamic-forward")
+                .help("Local SOCKS5 proxy through the peer. Format: remote-id:[bind-address:]local-port, can be repeated")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("tunnels")
+                .long("tunnels")
+                .help("File with one port-forward spec per line, run like repeated -p options")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("allow-from")
+                .long("allow-from")
+                .help("Only accept local tunnel connections from these IPs or CIDRs, comma separated, can be repeated")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .help("Bring up the tunnels of a saved forwarding profile")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("profile-add")
+                .long("profile-add")
+                .help("Save the given -p/-R/-D/--tunnels specs as a forwarding profile")
                 .num_args(1),
         )
+        .arg(
+            Arg::new("profile-list")
+                .long("profile-list")
+                .help("List forwarding profiles")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("profile-rm")
+                .long("profile-rm")
+                .help("Remove a forwarding profile")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("relay")
+                .long("relay")
+                .help("Always connect through the relay server")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("remember-password")
+                .long("remember-password")
+                .help("Remember the password of the peer after login")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("connect")
                 .short('c')
@@ -67,6 +137,14 @@ fn main() {
                 .num_args(1)
                 .required(false),
         )
+        .args(cli_secret::UNLOCK_PW.args(false))
+        .arg(
+            Arg::new("bridge-port")
+                .long("bridge-port")
+                .help("Local port of the Electron client WebSocket for -c and --local-server, any free one by default")
+                .value_parser(clap::value_parser!(u16))
+                .num_args(1),
+        )
         .arg(
             Arg::new("unlock-id")
                 .long("unlock-id")
@@ -78,9 +156,11 @@ fn main() {
             Arg::new("key")
                 .short('k')
                 .long("key")
-                .help("")
+                .help("Password of the peer, see also --key-file, --key-env, --key-stdin and --key-keyring")
+                .global(true)
                 .num_args(1),
         )
+        .args(cli_secret::KEY.args(true))
         .arg(
             Arg::new("server")
                 .short('s')
@@ -100,6 +180,19 @@ fn main() {
                 .help("Set permanent password")
                 .num_args(1),
         )
+        .args(cli_secret::PASSWORD.args(false))
+        .arg(
+            Arg::new("policy")
+                .long("policy")
+                .help("Approve incoming connections by the rules of this TOML file, empty to remove")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("audit-log")
+                .long("audit-log")
+                .help("Append a JSON line for each event of incoming sessions to this file, empty to disable")
+                .num_args(1),
+        )
         .arg(
             Arg::new("cm")
                 .long("cm")
@@ -207,85 +300,546 @@ fn main() {
                 .help("RDP password for headless mode")
                 .num_args(1),
         )
+        .args(cli_secret::RDP_PW.args(false))
+        .subcommand(file_transfer_command(
+            "push",
+            "Upload local files or directories to a peer",
+            "local",
+            "remote",
+        ))
+        .subcommand(file_transfer_command(
+            "pull",
+            "Download files or directories from a peer",
+            "remote",
+            "local",
+        ))
+        .subcommand(
+            Command::new("terminal")
+                .about("Open a shell on a peer, does not support --json")
+                .arg(Arg::new("id").help("Remote ID").required(true))
+                .arg(
+                    Arg::new("service-id")
+                        .long("service-id")
+                        .help("Reattach to this terminal service, the last one of the peer by default")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("persistent")
+                        .long("persistent")
+                        .help("Keep the shell running after disconnecting, type ~. to detach. Applies to this session only")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("status")
+                .about("Show whether peers are online, exits with 3 if any is offline")
+                .arg(
+                    Arg::new("id")
+                        .help("Remote IDs")
+                        .required(true)
+                        .num_args(1..),
+                ),
+        )
+        .subcommand(Command::new("discover").about("List the peers on the local network"))
+        .subcommand(
+            Command::new("wake")
+                .about("Wake a peer found by discover with Wake-on-LAN")
+                .arg(Arg::new("id").help("Remote ID").required(true)),
+        )
+        .subcommand(
+            Command::new("screenshot")
+                .about("Save screenshots of the displays of a peer as PNG")
+                .arg(Arg::new("id").help("Remote ID").required(true))
+                .arg(
+                    Arg::new("display")
+                        .long("display")
+                        .help("Display index, the current display of the peer by default")
+                        .value_parser(clap::value_parser!(usize))
+                        .conflicts_with("all"),
+                )
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .help("Capture all displays, saved as <output>-<display>.png")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Output file")
+                        .default_value("screenshot.png"),
+                )
+                .arg(
+                    Arg::new("count")
+                        .long("count")
+                        .help("Take a series of screenshots, saved as <output>-<n>.png")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .help("Seconds between the screenshots of a series")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("10"),
+                ),
+        )
+        .subcommand(
+            Command::new("exec")
+                .about("Run a command on a peer and exit with its exit code. With --json, the output comes as base64 stdout and stderr events and an exit event. Not supported by Windows peers installed as a service, use terminal there")
+                .arg(Arg::new("id").help("Remote ID").required(true))
+                .arg(
+                    Arg::new("command")
+                        .help("Command to run by the shell of the peer, after --")
+                        .required(true)
+                        .num_args(1..)
+                        .last(true),
+                ),
+        )
+        .subcommand(
+            Command::new("secret")
+                .about("Save secrets in the keyring of the OS, for the --*-keyring options")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("set")
+                        .about("Save a secret, asked for or read from stdin")
+                        .arg(Arg::new("entry").help("Name of the entry").required(true))
+                        .arg(
+                            Arg::new("stdin")
+                                .long("stdin")
+                                .help("Read the secret from the first line of stdin")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("rm")
+                        .about("Remove a secret")
+                        .arg(Arg::new("entry").help("Name of the entry").required(true)),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Export the settings of this device, or apply a settings file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("export")
+                        .about("Print the settings as TOML. It includes the password hash, which logs in like the password")
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .help("Write to this file instead of stdout, readable by the owner only")
+                                .num_args(1),
+                        ),
+                )
+                .subcommand(
+                    Command::new("apply")
+                        .about("Apply the settings of a TOML file, see `config export`")
+                        .arg(Arg::new("file").help("Settings file").required(true))
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .help("Only print what would change")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
+                ),
+        )
         .get_matches();
 
     use hbb_common::config::LocalConfig;
     let _logger_handle = hbb_common::init_log(false, "sdfdesk");
+    // Global flags given after a subcommand are only in its matches.
+    let sub_matches = matches.subcommand().map(|(_, m)| m).unwrap_or(&matches);
+    cli_events::set_enabled(sub_matches.get_flag("json"));
 
-    if let Some(p) = matches.get_one::<String>("port-forward") {
-        let options: Vec<String> = p.split(":").map(|x| x.to_owned()).collect();
-        if options.len() < 3 {
-            log::error!("Wrong port-forward options");
-            return;
-        }
-        let mut port = 0;
-        if let Ok(v) = options[1].parse::<i32>() {
-            port = v;
+    if let Some((cmd @ ("push" | "pull"), sub)) = matches.subcommand() {
+        let direction = if cmd == "push" {
+            cli_file_transfer::Direction::Push
         } else {
-            log::error!("Wrong local-port");
-            return;
-        }
-        let mut remote_port = 0;
-        if let Ok(v) = options[2].parse::<i32>() {
-            remote_port = v;
+            cli_file_transfer::Direction::Pull
+        };
+        let overwrite = match sub
+            .get_one::<String>("overwrite")
+            .map(|s| s.as_str())
+            .unwrap_or("overwrite")
+            .parse::<cli_file_transfer::OverwritePolicy>()
+        {
+            Ok(v) => v,
+            Err(err) => {
+                log::error!("{}", err);
+                cli_events::error("", &err);
+                std::process::exit(cli_events::EXIT_ERROR);
+            }
+        };
+        let arg = |name: &str| sub.get_one::<String>(name).cloned().unwrap_or_default();
+        let (source, dest) = if direction == cli_file_transfer::Direction::Push {
+            (arg("local"), arg("remote"))
         } else {
-            log::error!("Wrong remote-port");
-            return;
-        }
-        let mut remote_host = "localhost".to_owned();
-        if options.len() > 3 {
-            remote_host = options[3].clone();
+            (arg("remote"), arg("local"))
+        };
+        let token = LocalConfig::get_option("access_token");
+        cli_file_transfer::start_file_transfer(
+            arg("id"),
+            direction,
+            source,
+            dest,
+            cli_secret::KEY.get_or_exit(sub),
+            token,
+            cli_file_transfer::FileTransferOptions {
+                overwrite,
+                include_hidden: sub.get_flag("hidden"),
+                force_relay: sub.get_flag("relay"),
+            },
+        );
+        common::global_clean();
+        std::process::exit(cli_events::exit_code());
+    } else if let Some(("terminal", sub)) = matches.subcommand() {
+        let id = sub.get_one::<String>("id").cloned().unwrap_or_default();
+        if cli_events::is_enabled() {
+            let err = "terminal is interactive and does not support --json, use exec";
+            log::error!("{}", err);
+            cli_events::error(&id, err);
+            std::process::exit(cli_events::EXIT_ERROR);
         }
-        common::test_rendezvous_server();
-        common::test_nat_type();
-        let key = matches.get_one::<String>("key").map(|s| s.as_str()).unwrap_or("").to_owned();
+        let key = cli_secret::KEY.get_or_exit(sub);
         let token = LocalConfig::get_option("access_token");
-        cli::start_one_port_forward(
-            options[0].clone(),
-            port,
-            remote_host,
-            remote_port,
+        let code = cli_terminal::start_terminal(
+            id,
             key,
             token,
+            cli_terminal::TerminalOptions {
+                service_id: sub.get_one::<String>("service-id").cloned(),
+                persistent: sub.get_flag("persistent"),
+                force_relay: sub.get_flag("relay"),
+            },
         );
+        common::global_clean();
+        std::process::exit(code);
+    } else if let Some(("status", sub)) = matches.subcommand() {
+        let ids = sub
+            .get_many::<String>("id")
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default();
+        let code = cli_peers::status(ids);
+        common::global_clean();
+        std::process::exit(code);
+    } else if let Some(("discover", _)) = matches.subcommand() {
+        let code = cli_peers::discover();
+        common::global_clean();
+        std::process::exit(code);
+    } else if let Some(("wake", sub)) = matches.subcommand() {
+        let id = sub.get_one::<String>("id").cloned().unwrap_or_default();
+        let code = cli_peers::wake(id);
+        common::global_clean();
+        std::process::exit(code);
+    } else if let Some(("screenshot", sub)) = matches.subcommand() {
+        let id = sub.get_one::<String>("id").cloned().unwrap_or_default();
+        let key = cli_secret::KEY.get_or_exit(sub);
+        let token = LocalConfig::get_option("access_token");
+        cli_screenshot::start_screenshot(
+            id,
+            key,
+            token,
+            cli_screenshot::ScreenshotOptions {
+                display: sub.get_one::<usize>("display").cloned(),
+                all_displays: sub.get_flag("all"),
+                output: sub.get_one::<String>("output").cloned().unwrap_or_default(),
+                count: sub.get_one::<usize>("count").cloned().unwrap_or(1),
+                interval: std::time::Duration::from_secs(
+                    sub.get_one::<u64>("interval").cloned().unwrap_or(10),
+                ),
+                force_relay: sub.get_flag("relay"),
+            },
+        );
+        common::global_clean();
+        std::process::exit(cli_events::exit_code());
+    } else if let Some(("exec", sub)) = matches.subcommand() {
+        let id = sub.get_one::<String>("id").cloned().unwrap_or_default();
+        let key = cli_secret::KEY.get_or_exit(sub);
+        // Joined like ssh does, the remote shell splits it again.
+        let command = sub
+            .get_many::<String>("command")
+            .map(|v| v.cloned().collect::<Vec<_>>().join(" "))
+            .unwrap_or_default();
+        let token = LocalConfig::get_option("access_token");
+        let code = cli_terminal::start_exec(id, key, token, command, sub.get_flag("relay"));
+        common::global_clean();
+        std::process::exit(code);
+    } else if let Some(("secret", sub)) = matches.subcommand() {
+        let code = match sub.subcommand() {
+            Some(("set", sub)) => {
+                let entry = sub.get_one::<String>("entry").cloned().unwrap_or_default();
+                let secret = if sub.get_flag("stdin") {
+                    cli_secret::read_stdin_line()
+                } else {
+                    cli_secret::prompt("Secret: ")
+                };
+                match secret.and_then(|s| cli_secret::set_keyring(&entry, &s)) {
+                    Ok(()) => {
+                        cli_events::message(&format!("Secret {} saved", entry));
+                        cli_events::EXIT_OK
+                    }
+                    Err(err) => {
+                        eprintln!("{}: {}", entry, err);
+                        cli_events::EXIT_ERROR
+                    }
+                }
+            }
+            Some(("rm", sub)) => {
+                let entry = sub.get_one::<String>("entry").cloned().unwrap_or_default();
+                match cli_secret::remove_keyring(&entry) {
+                    Ok(()) => {
+                        cli_events::message(&format!("Secret {} removed", entry));
+                        cli_events::EXIT_OK
+                    }
+                    Err(err) => {
+                        eprintln!("{}: {}", entry, err);
+                        cli_events::EXIT_ERROR
+                    }
+                }
+            }
+            _ => cli_events::EXIT_ERROR,
+        };
+        common::global_clean();
+        std::process::exit(code);
+    } else if let Some(("config", sub)) = matches.subcommand() {
+        let code = match sub.subcommand() {
+            Some(("export", sub)) => match cli_config::export() {
+                Ok(content) => match sub.get_one::<String>("output") {
+                    Some(path) => match cli_config::write_export(path, &content) {
+                        Ok(()) => {
+                            cli_events::message(&format!("Settings exported to {}", path));
+                            cli_events::EXIT_OK
+                        }
+                        Err(err) => {
+                            eprintln!("{}: {}", path, err);
+                            cli_events::EXIT_ERROR
+                        }
+                    },
+                    None => {
+                        if cli_events::is_enabled() {
+                            cli_events::emit(
+                                "config_export",
+                                hbb_common::serde_json::json!({ "content": content }),
+                            );
+                        } else {
+                            print!("{}", content);
+                        }
+                        cli_events::EXIT_OK
+                    }
+                },
+                Err(err) => {
+                    eprintln!("Failed to export settings: {}", err);
+                    cli_events::EXIT_ERROR
+                }
+            },
+            Some(("apply", sub)) => {
+                let path = sub.get_one::<String>("file").cloned().unwrap_or_default();
+                let plan = std::fs::read_to_string(&path)
+                    .map_err(hbb_common::anyhow::Error::from)
+                    .and_then(|content| cli_config::parse(&content))
+                    .and_then(|provision| {
+                        cli_config::plan(&provision, &hbb_common::config::Config2::get().options)
+                    });
+                match plan {
+                    Ok(plan) => {
+                        for line in plan.diff() {
+                            cli_events::message(line);
+                        }
+                        if plan.is_empty() {
+                            cli_events::message("Nothing to change");
+                        } else if !sub.get_flag("dry-run") {
+                            cli_config::apply(&plan);
+                            cli_events::message("Settings applied");
+                        }
+                        cli_events::EXIT_OK
+                    }
+                    Err(err) => {
+                        eprintln!("{}: {}", path, err);
+                        cli_events::EXIT_ERROR
+                    }
+                }
+            }
+            _ => cli_events::EXIT_ERROR,
+        };
+        common::global_clean();
+        std::process::exit(code);
+    } else if let Some(name) = matches.get_one::<String>("profile-add") {
+        let specs = match collect_port_forward_specs(&matches) {
+            Ok(specs) => specs,
+            Err(err) => {
+                log::error!("{}", err);
+                return;
+            }
+        };
+        let Some(id) = specs.first().map(|s| s.id.clone()) else {
+            log::error!("No port-forward tunnels specified");
+            return;
+        };
+        if specs.iter().any(|s| s.id != id) {
+            log::error!("All tunnels of a profile must go to the same peer");
+            return;
+        }
+        let allow_from = match collect_allow_from(&matches) {
+            Ok(v) => v,
+            Err(err) => {
+                log::error!("{}", err);
+                return;
+            }
+        };
+        LocalConfig::set_forward_profile(
+            name.to_owned(),
+            hbb_common::config::ForwardProfile {
+                tunnels: specs.iter().map(|s| s.to_string()).collect(),
+                remember_password: matches.get_flag("remember-password"),
+                force_relay: matches.get_flag("relay"),
+                allow_from: allow_from.iter().map(|x| x.to_string()).collect(),
+            },
+        );
+        cli_events::message(&format!("Profile {} saved", name));
+    } else if matches.get_flag("profile-list") {
+        let mut profiles: Vec<_> = LocalConfig::get_forward_profiles().into_iter().collect();
+        profiles.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, profile) in profiles {
+            let id = match cli::load_port_forward_profile(&name) {
+                Ok((specs, _)) => specs.first().map(|s| s.id.clone()).unwrap_or_default(),
+                Err(err) => {
+                    cli_events::message(&format!("{}: {}", name, err));
+                    continue;
+                }
+            };
+            let password = if hbb_common::config::PeerConfig::load(&id)
+                .password
+                .is_empty()
+            {
+                "no"
+            } else {
+                "stored"
+            };
+            let relay = if profile.force_relay { "always" } else { "auto" };
+            if cli_events::is_enabled() {
+                cli_events::emit(
+                    "profile",
+                    hbb_common::serde_json::json!({
+                        "name": name,
+                        "id": id,
+                        "relay": relay,
+                        "password": password,
+                        "allow_from": profile.allow_from,
+                        "tunnels": profile.tunnels,
+                    }),
+                );
+                continue;
+            }
+            println!("{}: peer={} relay={} password={}", name, id, relay, password);
+            if !profile.allow_from.is_empty() {
+                println!("    allow from {}", profile.allow_from.join(","));
+            }
+            for t in profile.tunnels.iter() {
+                println!("    {}", t);
+            }
+        }
+    } else if let Some(name) = matches.get_one::<String>("profile-rm") {
+        if LocalConfig::remove_forward_profile(name) {
+            cli_events::message(&format!("Profile {} removed", name));
+        } else {
+            log::error!("No such profile: {}", name);
+        }
+    } else if matches.contains_id("port-forward")
+        || matches.contains_id("reverse-forward")
+        || matches.contains_id("dynamic-forward")
+        || matches.contains_id("tunnels")
+        || matches.contains_id("profile")
+    {
+        let mut specs = Vec::new();
+        let mut options = cli::PortForwardOptions::default();
+        if let Some(name) = matches.get_one::<String>("profile") {
+            match cli::load_port_forward_profile(name) {
+                Ok((v, o)) => {
+                    specs.extend(v);
+                    options = o;
+                }
+                Err(err) => {
+                    log::error!("{}", err);
+                    cli_events::error("", &err);
+                    std::process::exit(cli_events::EXIT_ERROR);
+                }
+            }
+        }
+        match collect_port_forward_specs(&matches) {
+            Ok(v) => specs.extend(v),
+            Err(err) => {
+                log::error!("{}", err);
+                cli_events::error("", &err);
+                std::process::exit(cli_events::EXIT_ERROR);
+            }
+        }
+        if specs.is_empty() {
+            log::error!("No port-forward tunnels specified");
+            cli_events::error("", "No port-forward tunnels specified");
+            std::process::exit(cli_events::EXIT_ERROR);
+        }
+        options.force_relay |= matches.get_flag("relay");
+        options.remember_password |= matches.get_flag("remember-password");
+        match collect_allow_from(&matches) {
+            Ok(v) => options.allow_from.extend(v),
+            Err(err) => {
+                log::error!("{}", err);
+                cli_events::error("", &err);
+                std::process::exit(cli_events::EXIT_ERROR);
+            }
+        }
+        let key = cli_secret::KEY.get_or_exit(&matches);
+        let token = LocalConfig::get_option("access_token");
+        cli::start_port_forwards(specs, key, token, options);
+        common::global_clean();
+        std::process::exit(cli_events::exit_code());
     } else if let Some(p) = matches.get_one::<String>("connect") {
         common::test_rendezvous_server();
         common::test_nat_type();
-        let key = matches.get_one::<String>("key").map(|s| s.as_str()).unwrap_or("").to_owned();
+        let key = cli_secret::KEY.get_or_exit(&matches);
         let token = LocalConfig::get_option("access_token");
         let unlock_id = matches.get_one::<String>("unlock-id").map(|s| s.to_owned()).unwrap_or_default();
-        let unlock_pw = matches.get_one::<String>("unlock-pw").map(|s| s.to_owned()).unwrap_or_default();
+        let unlock_pw = cli_secret::UNLOCK_PW.get_or_exit(&matches);
         // RDP credentials for headless mode
         let rdp_id = matches.get_one::<String>("rdp-id").map(|s| s.to_owned()).unwrap_or_default();
-        let rdp_pw = matches.get_one::<String>("rdp-pw").map(|s| s.to_owned()).unwrap_or_default();
-        cli::connect_test(p, key, token, unlock_id, unlock_pw, rdp_id, rdp_pw);
+        let rdp_pw = cli_secret::RDP_PW.get_or_exit(&matches);
+        let bridge_port = *matches.get_one::<u16>("bridge-port").unwrap_or(&0);
+        cli::connect_test(p, key, token, unlock_id, unlock_pw, rdp_id, rdp_pw, bridge_port);
+        common::global_clean();
+        std::process::exit(cli_events::exit_code());
     } else if matches.get_flag("server") {
         let id = hbb_common::config::Config::get_id();
-        println!("========================================");
-        println!("Server ID: {}", id);
-        println!("========================================");
+        if cli_events::is_enabled() {
+            cli_events::emit("server_id", hbb_common::serde_json::json!({ "id": id }));
+        } else {
+            println!("========================================");
+            println!("Server ID: {}", id);
+            println!("========================================");
+        }
         log::info!("id={}", id);
         log::info!("id={}", id);
         if matches.get_flag("direct-server") {
             hbb_common::config::Config::set_option("direct-server".to_owned(), "Y".to_owned());
-            println!("Direct server enabled (port 21118)");
+            cli_events::message("Direct server enabled (port 21118)");
         }
         
         // Check for RDP mode (headless support)
         #[cfg(windows)]
         {
             let rdp_id = matches.get_one::<String>("rdp-id");
-            let rdp_pw = matches.get_one::<String>("rdp-pw");
+            let rdp_pw = cli_secret::RDP_PW.is_present(&matches).then(|| cli_secret::RDP_PW.get_or_exit(&matches));
             
             if let (Some(rdp_id), Some(rdp_pw)) = (rdp_id, rdp_pw) {
-                crate::rdp_session::set_rdp_credentials(rdp_id.clone(), rdp_pw.clone());
-                println!("RDP mode enabled for headless operation");
+                crate::rdp_session::set_rdp_credentials(rdp_id.clone(), rdp_pw);
+                cli_events::message("RDP mode enabled for headless operation");
                 
                 // Check if display is connected
                 if !crate::rdp_session::has_display_connected() {
-                    println!("No display detected - will auto-create RDP session on client connect");
+                    cli_events::message("No display detected - will auto-create RDP session on client connect");
                 } else {
-                    println!("Display detected - RDP mode on standby");
+                    cli_events::message("Display detected - RDP mode on standby");
                 }
             }
         }
@@ -293,65 +847,134 @@ fn main() {
         crate::start_server(true, false);
     } else if matches.get_flag("cm") || matches.get_flag("cm-no-ui") {
         crate::cli::start_cm_no_ui();
-    } else if let Some(pwd) = matches.get_one::<String>("password") {
+    } else if cli_secret::PASSWORD.is_present(&matches) {
         use hbb_common::config::Config;
-        Config::set_permanent_password(pwd);
+        let pwd = cli_secret::PASSWORD.get_or_exit(&matches);
+        Config::set_permanent_password(&pwd);
         // Set approve mode to password-only (no UI accept needed)
         Config::set_option("approve-mode".to_owned(), "password".to_owned());
         // Use permanent password only (disable temporary password)
         Config::set_option("verification-method".to_owned(), "use-permanent-password".to_owned());
-        println!("Password set successfully");
-        println!("Approve mode set to: password-only (no UI accept required)");
+        cli_events::message("Password set successfully");
+        cli_events::message("Approve mode set to: password-only (no UI accept required)");
+    } else if let Some(path) = matches.get_one::<String>("policy") {
+        use hbb_common::config::Config;
+        if path.is_empty() {
+            Config::set_option(cli_policy::OPTION_CONNECTION_POLICY.to_owned(), "".to_owned());
+            cli_events::message("Connection policy removed");
+            // Without the policy nothing answers the headless connection manager.
+            if Config::get_option("approve-mode").is_empty() {
+                Config::set_option("approve-mode".to_owned(), "password".to_owned());
+                cli_events::message("Approve mode set to: password-only (no UI accept required)");
+            }
+            return;
+        }
+        // The connection manager runs in its own process and directory.
+        let path = match std::fs::canonicalize(path) {
+            Ok(p) => p.to_string_lossy().to_string(),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(cli_events::EXIT_ERROR);
+            }
+        };
+        if let Err(err) = cli_policy::Policy::load(&path) {
+            eprintln!("Invalid policy: {}", err);
+            std::process::exit(cli_events::EXIT_ERROR);
+        }
+        Config::set_option(cli_policy::OPTION_CONNECTION_POLICY.to_owned(), path.clone());
+        // Connections without the password wait for the connection manager,
+        // which asks the policy.
+        Config::set_option("approve-mode".to_owned(), "".to_owned());
+        cli_events::message(&format!("Connection policy set to: {}", path));
+        cli_events::message("Approve mode set to: password or policy");
+    } else if let Some(path) = matches.get_one::<String>("audit-log") {
+        use hbb_common::config::{keys, Config};
+        if path.is_empty() {
+            Config::set_option(keys::OPTION_AUDIT_LOG.to_owned(), "".to_owned());
+            cli_events::message("Audit log disabled");
+            return;
+        }
+        // The server runs in its own directory, the file may not exist yet.
+        let path = std::env::current_dir()
+            .map(|dir| dir.join(path))
+            .unwrap_or(path.into());
+        if let Err(err) = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+        {
+            eprintln!("{}: {}", path.display(), err);
+            std::process::exit(cli_events::EXIT_ERROR);
+        }
+        let path = path.to_string_lossy().to_string();
+        Config::set_option(keys::OPTION_AUDIT_LOG.to_owned(), path.clone());
+        cli_events::message(&format!("Audit log set to: {}", path));
     } else if matches.get_flag("get-id") {
         let id = hbb_common::config::Config::get_id();
-        println!("{}", id);
+        if cli_events::is_enabled() {
+            cli_events::emit("server_id", hbb_common::serde_json::json!({ "id": id }));
+        } else {
+            println!("{}", id);
+        }
     } else if let Some(server) = matches.get_one::<String>("set-hbbs") {
         use hbb_common::config::Config;
         Config::set_option("custom-rendezvous-server".to_owned(), server.to_owned());
         if server.is_empty() {
-            println!("HBBS server reset to default (rs-ny.rustdesk.com:21116)");
+            cli_events::message("HBBS server reset to default (rs-ny.rustdesk.com:21116)");
         } else {
-            println!("HBBS server set to: {}", server);
+            cli_events::message(&format!("HBBS server set to: {}", server));
         }
     } else if let Some(server) = matches.get_one::<String>("set-hbbr") {
         use hbb_common::config::Config;
         Config::set_option("relay-server".to_owned(), server.to_owned());
         if server.is_empty() {
-            println!("HBBR server reset to default (rs-ny.rustdesk.com:21117)");
+            cli_events::message("HBBR server reset to default (rs-ny.rustdesk.com:21117)");
         } else {
-            println!("HBBR server set to: {}", server);
+            cli_events::message(&format!("HBBR server set to: {}", server));
         }
     } else if let Some(key) = matches.get_one::<String>("set-key") {
         use hbb_common::config::Config;
         Config::set_option("key".to_owned(), key.to_owned());
         if key.is_empty() {
-            println!("Server key reset to default");
+            cli_events::message("Server key reset to default");
         } else {
-            println!("Server key set successfully");
+            cli_events::message("Server key set successfully");
         }
     } else if matches.get_flag("show-config") {
         use hbb_common::config::Config;
+        let rendezvous_servers = Config::get_rendezvous_servers();
+        let relay_server = Config::get_option("relay-server");
+        // Display configured server key (for connecting to custom servers)
+        let server_key = Config::get_option("key");
+        if cli_events::is_enabled() {
+            cli_events::emit(
+                "config",
+                hbb_common::serde_json::json!({
+                    "id": Config::get_id(),
+                    "hbbs": rendezvous_servers,
+                    "hbbr": relay_server,
+                    "key": server_key,
+                }),
+            );
+            return;
+        }
         println!("========================================");
         println!("Current Server Configuration:");
         println!("========================================");
         println!("ID: {}", Config::get_id());
         
-        let rendezvous_servers = Config::get_rendezvous_servers();
         if rendezvous_servers.is_empty() {
             println!("HBBS: (default) rs-ny.rustdesk.com");
         } else {
             println!("HBBS: {}", rendezvous_servers.join(", "));
         }
         
-        let relay_server = Config::get_option("relay-server");
         if relay_server.is_empty() {
             println!("HBBR: (default) rs-ny.rustdesk.com");
         } else {
             println!("HBBR: {}", relay_server);
         }
         
-        // Display configured server key (for connecting to custom servers)
-        let server_key = Config::get_option("key");
         if server_key.is_empty() {
             println!("Key: (not set)");
         } else {
@@ -362,9 +985,10 @@ fn main() {
     } else if let Some(id) = matches.get_one::<String>("local-server") {
         common::test_rendezvous_server();
         common::test_nat_type();
-        let key = matches.get_one::<String>("key").map(|s| s.as_str()).unwrap_or("").to_owned();
+        let key = cli_secret::KEY.get_or_exit(&matches);
         let token = LocalConfig::get_option("access_token");
-        cli::start_local_server(id, key, token);
+        let bridge_port = *matches.get_one::<u16>("bridge-port").unwrap_or(&0);
+        cli::start_local_server(id, key, token, bridge_port);
     } else if matches.get_flag("service") {
         if matches.get_flag("direct-server") {
             hbb_common::config::Config::set_option("direct-server".to_owned(), "Y".to_owned());
@@ -376,9 +1000,7 @@ fn main() {
             let config_file = Config::file();
             log::info!("[Service] Config file path: {:?}", config_file);
             log::info!("[Service] current_exe: {:?}", std::env::current_exe());
-            let permanent_password = Config::get_permanent_password();
-            log::info!("[Service] Permanent password length: {}", permanent_password.len());
-            log::info!("[Service] Permanent password is empty: {}", permanent_password.is_empty());
+            log::info!("[Service] Permanent password is set: {}", Config::has_permanent_password());
             
             librustdesk::start_os_service();
         }
@@ -397,7 +1019,7 @@ fn main() {
                 binpath.push_str(" --direct-server");
             }
             
-            println!("Installing service...");
+            cli_events::message("Installing service...");
             let status = std::process::Command::new("sc")
                 .arg("create")
                 .arg(app_name)
@@ -412,8 +1034,8 @@ fn main() {
             match status {
                 Ok(s) => {
                     if s.success() {
-                        println!("Service installed successfully.");
-                        println!("Run 'sdfdesk --start-service' to start it.");
+                        cli_events::message("Service installed successfully.");
+                        cli_events::message("Run 'sdfdesk --start-service' to start it.");
                     } else {
                         eprintln!("Failed to install service. Exit code: {:?}", s.code());
                     }
@@ -445,7 +1067,7 @@ fn main() {
             };
 
             if !exists {
-                println!("Service not found. Installing...");
+                cli_events::message("Service not found. Installing...");
                 let create_res = std::process::Command::new("sc")
                     .arg("create")
                     .arg(app_name)
@@ -460,16 +1082,16 @@ fn main() {
                 match create_res {
                     Ok(output) => {
                         if !output.status.success() {
-                            println!("Failed to create service: {}", String::from_utf8_lossy(&output.stdout));
+                            cli_events::message(&format!("Failed to create service: {}", String::from_utf8_lossy(&output.stdout)));
                             eprintln!("Error: {}", String::from_utf8_lossy(&output.stderr));
                         } else {
-                            println!("Service created successfully.");
+                            cli_events::message("Service created successfully.");
                         }
                     }
                     Err(e) => eprintln!("Failed to execute sc create: {}", e),
                 }
             } else {
-                println!("Service exists. Updating configuration...");
+                cli_events::message("Service exists. Updating configuration...");
                 // Update binpath in case exe moved or changed
                 let _ = std::process::Command::new("sc")
                     .arg("config")
@@ -482,7 +1104,7 @@ fn main() {
             }
 
             // 2. Start service
-            println!("Starting service...");
+            cli_events::message("Starting service...");
             let start = std::process::Command::new("sc")
                 .arg("start")
                 .arg(app_name)
@@ -490,7 +1112,7 @@ fn main() {
                 
             match start {
                 Ok(output) => {
-                    println!("{}", String::from_utf8_lossy(&output.stdout));
+                    cli_events::message(&String::from_utf8_lossy(&output.stdout));
                     if !output.stderr.is_empty() {
                         eprintln!("Error: {}", String::from_utf8_lossy(&output.stderr));
                     }
@@ -502,7 +1124,7 @@ fn main() {
         #[cfg(target_os = "windows")]
         {
             let app_name = "sdfdesk";
-            println!("Stopping service...");
+            cli_events::message("Stopping service...");
             let stop = std::process::Command::new("sc")
                 .arg("stop")
                 .arg(app_name)
@@ -510,7 +1132,7 @@ fn main() {
                 
             match stop {
                 Ok(output) => {
-                    println!("{}", String::from_utf8_lossy(&output.stdout));
+                    cli_events::message(&String::from_utf8_lossy(&output.stdout));
                     if !output.stderr.is_empty() {
                         eprintln!("Error: {}", String::from_utf8_lossy(&output.stderr));
                     }
@@ -522,13 +1144,13 @@ fn main() {
         #[cfg(target_os = "windows")]
         {
             let app_name = "sdfdesk";
-            println!("Stopping service...");
+            cli_events::message("Stopping service...");
             let _ = std::process::Command::new("sc")
                 .arg("stop")
                 .arg(app_name)
                 .output();
                 
-            println!("Deleting service...");
+            cli_events::message("Deleting service...");
             let delete = std::process::Command::new("sc")
                 .arg("delete")
                 .arg(app_name)
@@ -536,7 +1158,7 @@ fn main() {
                 
             match delete {
                 Ok(output) => {
-                    println!("{}", String::from_utf8_lossy(&output.stdout));
+                    cli_events::message(&String::from_utf8_lossy(&output.stdout));
                     if !output.stderr.is_empty() {
                         eprintln!("Error: {}", String::from_utf8_lossy(&output.stderr));
                     }
@@ -547,3 +1169,78 @@ fn main() {
     }
     common::global_clean();
 }
+
+/// `push` and `pull`, copying from `from` to `to`.
+#[cfg(feature = "cli")]
+fn file_transfer_command(
+    name: &'static str,
+    about: &'static str,
+    from: &'static str,
+    to: &'static str,
+) -> clap::Command {
+    use clap::{Arg, Command};
+    Command::new(name)
+        .about(about)
+        .arg(Arg::new("id").help("Remote ID").required(true))
+        .arg(Arg::new(from).help("Source path, a file or a directory").required(true))
+        .arg(Arg::new(to).help("Destination path, the content of a directory is copied into it").required(true))
+        .arg(
+            Arg::new("overwrite")
+                .long("overwrite")
+                .help("What to do with existing files, identical ones are always skipped")
+                .value_parser(["skip", "overwrite", "newer"])
+                .default_value("overwrite"),
+        )
+        .arg(
+            Arg::new("hidden")
+                .long("hidden")
+                .help("Include hidden files")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Collect the tunnels given with `--tunnels`, `-p`, `-R` and `-D`.
+#[cfg(feature = "cli")]
+fn collect_port_forward_specs(
+    matches: &clap::ArgMatches,
+) -> Result<Vec<cli::PortForwardSpec>, String> {
+    let mut specs = Vec::new();
+    if let Some(path) = matches.get_one::<String>("tunnels") {
+        specs.extend(cli::load_port_forward_file(path)?);
+    }
+    for p in matches
+        .get_many::<String>("port-forward")
+        .into_iter()
+        .flatten()
+    {
+        specs.push(p.parse::<cli::PortForwardSpec>()?);
+    }
+    for p in matches
+        .get_many::<String>("reverse-forward")
+        .into_iter()
+        .flatten()
+    {
+        specs.push(cli::PortForwardSpec::parse_reverse(p)?);
+    }
+    for p in matches
+        .get_many::<String>("dynamic-forward")
+        .into_iter()
+        .flatten()
+    {
+        specs.push(cli::PortForwardSpec::parse_socks(p)?);
+    }
+    Ok(specs)
+}
+
+#[cfg(feature = "cli")]
+fn collect_allow_from(
+    matches: &clap::ArgMatches,
+) -> Result<Vec<cidr_utils::cidr::IpCidr>, String> {
+    cli::parse_allow_from(
+        matches
+            .get_many::<String>("allow-from")
+            .into_iter()
+            .flatten()
+            .map(|x| x.as_str()),
+    )
+}
@@ -1,16 +1,34 @@
-use std::sync::{Arc, RwLock};
+use cidr_utils::cidr::IpCidr;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use zeroize::Zeroizing;
 
-use crate::client::*;
+use crate::{
+    client::*,
+    port_forward_mux::{Mux, Target as MuxTarget, MUX_MAX_STREAMS},
+};
 use hbb_common::{
-    allow_err, bail,
+    allow_err,
+    anyhow::anyhow,
+    bail,
     config::READ_TIMEOUT,
     futures::{SinkExt, StreamExt},
-    log,
+    get_time, log,
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
+    serde_json::{self, json},
     tcp, timeout,
-    tokio::{self, net::TcpStream, sync::mpsc},
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpStream, UdpSocket},
+        sync::{mpsc, oneshot},
+    },
     tokio_util::codec::{BytesCodec, Framed},
     ResultType, Stream,
 };
@@ -42,48 +60,289 @@ fn run_rdp(port: u16) {
         .ok();
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SessionMode {
+    Forward,
+    Reverse,
+    Udp,
+    Mux,
+}
+
+fn set_port_forward(
+    lc: &Arc<RwLock<LoginConfigHandler>>,
+    remote_host: String,
+    remote_port: i32,
+    mode: SessionMode,
+) {
+    let mut lc = lc.write().unwrap();
+    lc.port_forward = (remote_host, remote_port);
+    lc.port_forward_reverse = mode == SessionMode::Reverse;
+    lc.port_forward_udp = mode == SessionMode::Udp;
+    lc.port_forward_mux = mode == SessionMode::Mux;
+}
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+const KEEPALIVE_MAX_MISSED: u32 = 3;
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+const RECONNECT_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TunnelState {
+    Connecting,
+    Up,
+    Degraded,
+    Down,
+}
+
+/// Health of one tunnel, shared by the connections it forwards. Sessions in
+/// message mode (reverse, UDP and mux) are probed with `TestDelay` when idle,
+/// raw sessions can only be checked when they are established.
+#[derive(Clone)]
+struct Watchdog {
+    name: String,
+    health: Arc<Mutex<Health>>,
+}
+
+struct Health {
+    state: Option<TunnelState>,
+    last_recv: Instant,
+    missed: u32,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl Health {
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.backoff;
+        self.backoff = (self.backoff * 2).min(RECONNECT_MAX_DELAY);
+        delay
+    }
+}
+
+impl Watchdog {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            health: Arc::new(Mutex::new(Health {
+                state: None,
+                last_recv: Instant::now(),
+                missed: 0,
+                backoff: RECONNECT_MIN_DELAY,
+                retry_at: None,
+            })),
+        }
+    }
+
+    fn state(&self) -> Option<TunnelState> {
+        self.health.lock().unwrap().state
+    }
+
+    fn set_state(&self, state: TunnelState) {
+        self.transition(&mut self.health.lock().unwrap(), state);
+    }
+
+    fn transition(&self, health: &mut Health, state: TunnelState) {
+        if health.state == Some(state) {
+            return;
+        }
+        match health.state {
+            Some(old) => log::info!("tunnel {}: {:?} -> {:?}", self.name, old, state),
+            None => log::info!("tunnel {}: {:?}", self.name, state),
+        }
+        emit_event(
+            "tunnel_state",
+            json!({
+                "tunnel": self.name,
+                "state": format!("{:?}", state).to_lowercase(),
+            }),
+        );
+        health.state = Some(state);
+    }
+
+    fn on_recv(&self) {
+        let mut health = self.health.lock().unwrap();
+        health.last_recv = Instant::now();
+        health.missed = 0;
+        if health.state == Some(TunnelState::Degraded) {
+            self.transition(&mut health, TunnelState::Up);
+        }
+    }
+
+    fn on_connected(&self) {
+        let mut health = self.health.lock().unwrap();
+        health.backoff = RECONNECT_MIN_DELAY;
+        health.retry_at = None;
+        health.last_recv = Instant::now();
+        health.missed = 0;
+        self.transition(&mut health, TunnelState::Up);
+    }
+
+    /// The session is lost or can not be established, schedule a reconnect.
+    fn on_down(&self) {
+        let mut health = self.health.lock().unwrap();
+        self.transition(&mut health, TunnelState::Down);
+        health.retry_at = Some(Instant::now() + health.next_delay());
+    }
+
+    fn next_delay(&self) -> Duration {
+        self.health.lock().unwrap().next_delay()
+    }
+
+    fn should_reconnect(&self) -> bool {
+        matches!(self.health.lock().unwrap().retry_at, Some(t) if Instant::now() >= t)
+    }
+
+    fn retry_delay(&self) -> Duration {
+        self.health
+            .lock()
+            .unwrap()
+            .retry_at
+            .map(|t| t.saturating_duration_since(Instant::now()))
+            .unwrap_or_default()
+    }
+
+    /// Call every `KEEPALIVE_INTERVAL` on an established message mode session.
+    /// Returns false if the session should be considered dead.
+    async fn probe(&self, stream: &mut Stream) -> bool {
+        {
+            let mut health = self.health.lock().unwrap();
+            if health.last_recv.elapsed() < KEEPALIVE_INTERVAL {
+                return true;
+            }
+            if health.missed > 0 {
+                self.transition(&mut health, TunnelState::Degraded);
+            }
+            if health.missed >= KEEPALIVE_MAX_MISSED {
+                return false;
+            }
+            health.missed += 1;
+        }
+        let mut msg_out = Message::new();
+        msg_out.set_test_delay(TestDelay {
+            time: get_time(),
+            from_client: true,
+            ..Default::default()
+        });
+        stream.send(&msg_out).await.is_ok()
+    }
+}
+
+/// Structured events for `sdfdesk --json`.
+#[allow(unused_variables)]
+fn emit_event(event: &str, fields: serde_json::Value) {
+    #[cfg(feature = "cli")]
+    crate::cli_events::emit(event, fields);
+}
+
+fn report_opened(tunnel: &str, from: &str) {
+    emit_event("tunnel_opened", json!({ "tunnel": tunnel, "from": from }));
+}
+
+fn report_closed(tunnel: &str, from: &str, sent: u64, received: u64) {
+    log::info!(
+        "connection from {} closed, {} bytes sent, {} bytes received",
+        from,
+        sent,
+        received
+    );
+    emit_event(
+        "tunnel_closed",
+        json!({
+            "tunnel": tunnel,
+            "from": from,
+            "bytes_sent": sent,
+            "bytes_received": received,
+        }),
+    );
+}
+
+/// Run one forwarded connection in raw mode and report its traffic.
+async fn run_forward_connection(
+    watchdog: Watchdog,
+    from: String,
+    forward: Framed<TcpStream, BytesCodec>,
+    stream: Stream,
+    interface: impl Interface,
+) {
+    report_opened(&watchdog.name, &from);
+    let (sent, received) = match run_forward(forward, stream, &watchdog).await {
+        Ok(n) => n,
+        Err(err) => {
+            interface.msgbox("error", "Error", &err.to_string(), "");
+            (0, 0)
+        }
+    };
+    report_closed(&watchdog.name, &from, sent, received);
+}
+
+/// Whether a connection from `addr` may use a local listener.
+/// An empty `allow_from` allows every source.
+fn is_source_allowed(allow_from: &[IpCidr], addr: &SocketAddr) -> bool {
+    if allow_from.is_empty() {
+        return true;
+    }
+    // Dual stack listeners see IPv4 clients as IPv4-mapped IPv6 addresses.
+    let ip = match addr.ip() {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr.ip()),
+        ip => ip,
+    };
+    allow_from.iter().any(|cidr| cidr.contains(ip))
+}
+
 pub async fn listen(
     id: String,
-    password: String,
+    password: Zeroizing<String>,
+    local_host: String,
     port: i32,
     interface: impl Interface,
     ui_receiver: mpsc::UnboundedReceiver<Data>,
     key: &str,
     token: &str,
-    lc: Arc<RwLock<LoginConfigHandler>>,
     remote_host: String,
     remote_port: i32,
+    allow_from: &[IpCidr],
 ) -> ResultType<()> {
-    let listener = tcp::new_listener(format!("0.0.0.0:{}", port), true).await?;
+    let listener = tcp::new_listener(format!("{}:{}", local_host, port), true).await?;
     let addr = listener.local_addr()?;
     log::info!("listening on port {:?}", addr);
     let is_rdp = port == 0;
     if is_rdp {
         run_rdp(addr.port());
     }
+    let watchdog = Watchdog::new(format!(
+        ":{} -> {}:{}",
+        addr.port(),
+        remote_host,
+        remote_port
+    ));
     let mut ui_receiver = ui_receiver;
     loop {
         tokio::select! {
             Ok((forward, addr)) = listener.accept() => {
+                if !is_source_allowed(allow_from, &addr) {
+                    log::warn!("connection from {:?} is not allowed", addr);
+                    continue;
+                }
                 log::info!("new connection from {:?}", addr);
-                lc.write().unwrap().port_forward = (remote_host.clone(), remote_port);
-                let id = id.clone();
-                let password = password.clone();
-                let mut forward = Framed::new(forward, BytesCodec::new());
-                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), &mut forward, key, token, is_rdp).await {
-                    Ok(Some(stream)) => {
-                        let interface = interface.clone();
+                let target = (remote_host.clone(), remote_port);
+                // The login and its retries run off the accept loop, see `listen_socks`.
+                match interface.fork_session() {
+                    Some((interface, mut conn_receiver)) => {
+                        let (id, password, key, token, watchdog) = (
+                            id.clone(),
+                            password.clone(),
+                            key.to_owned(),
+                            token.to_owned(),
+                            watchdog.clone(),
+                        );
                         tokio::spawn(async move {
-                            if let Err(err) = run_forward(forward, stream).await {
-                                interface.msgbox("error", "Error", &err.to_string(), "");
-                            }
-                            log::info!("connection from {:?} closed", addr);
-                       });
+                            forward_connection(forward, addr, target, &id, &password, interface, &mut conn_receiver, &key, &token, is_rdp, &watchdog).await;
+                        });
                     }
-                    Err(err) => {
-                        interface.on_establish_connection_error(err.to_string());
+                    None => {
+                        forward_connection(forward, addr, target, &id, &password, interface.clone(), &mut ui_receiver, key, token, is_rdp, &watchdog).await;
                     }
-                    _ => {}
                 }
             }
             d = ui_receiver.recv() => {
@@ -103,28 +362,1021 @@ pub async fn listen(
     Ok(())
 }
 
+/// Log in a session to `target` for one accepted connection and forward it.
+async fn forward_connection(
+    forward: TcpStream,
+    addr: SocketAddr,
+    target: (String, i32),
+    id: &str,
+    password: &str,
+    interface: impl Interface,
+    ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    is_rdp: bool,
+    watchdog: &Watchdog,
+) {
+    set_port_forward(
+        &interface.get_lch(),
+        target.0,
+        target.1,
+        SessionMode::Forward,
+    );
+    let mut forward = Framed::new(forward, BytesCodec::new());
+    let res = connect_and_login(
+        id,
+        password,
+        ui_receiver,
+        interface.clone(),
+        Some(&mut forward),
+        key,
+        token,
+        is_rdp,
+        watchdog,
+    )
+    .await;
+    match res {
+        Ok(Some(stream)) => {
+            tokio::spawn(run_forward_connection(
+                watchdog.clone(),
+                addr.to_string(),
+                forward,
+                stream,
+                interface,
+            ));
+        }
+        Err(err) => {
+            interface.on_establish_connection_error(err.to_string());
+        }
+        _ => {}
+    }
+}
+
+/// Dynamic forwarding: a local SOCKS5 server (CONNECT only, no authentication).
+/// Every request opens a new port forward session to the requested destination.
+pub async fn listen_socks(
+    id: String,
+    password: Zeroizing<String>,
+    local_host: String,
+    port: i32,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    allow_from: &[IpCidr],
+) -> ResultType<()> {
+    let listener = tcp::new_listener(format!("{}:{}", local_host, port), true).await?;
+    let addr = listener.local_addr()?;
+    log::info!("SOCKS5 listening on {:?}", addr);
+    let watchdog = Watchdog::new(format!("socks {}", addr));
+    let mut ui_receiver = ui_receiver;
+    loop {
+        tokio::select! {
+            Ok((forward, addr)) = listener.accept() => {
+                if !is_source_allowed(allow_from, &addr) {
+                    log::warn!("SOCKS5 connection from {:?} is not allowed", addr);
+                    continue;
+                }
+                // The handshake and the login run off the accept loop, so that a
+                // slow client or peer does not hold up the other connections.
+                match interface.fork_session() {
+                    Some((interface, mut conn_receiver)) => {
+                        let (id, password, key, token, watchdog) = (
+                            id.clone(),
+                            password.clone(),
+                            key.to_owned(),
+                            token.to_owned(),
+                            watchdog.clone(),
+                        );
+                        tokio::spawn(async move {
+                            socks_connection(forward, addr, &id, &password, interface, &mut conn_receiver, &key, &token, &watchdog).await;
+                        });
+                    }
+                    None => {
+                        socks_connection(forward, addr, &id, &password, interface.clone(), &mut ui_receiver, key, token, &watchdog).await;
+                    }
+                }
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the request of a SOCKS5 client, log in a session to the requested
+/// destination and forward the connection.
+async fn socks_connection(
+    mut forward: TcpStream,
+    addr: SocketAddr,
+    id: &str,
+    password: &str,
+    interface: impl Interface,
+    ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    watchdog: &Watchdog,
+) {
+    let (remote_host, remote_port) =
+        match timeout(READ_TIMEOUT, socks5_handshake(&mut forward)).await {
+            Ok(Ok(target)) => target,
+            Ok(Err(err)) => {
+                log::error!("SOCKS5 handshake with {:?} failed: {}", addr, err);
+                return;
+            }
+            Err(_) => {
+                log::error!("SOCKS5 handshake with {:?} timeout", addr);
+                return;
+            }
+        };
+    log::info!(
+        "SOCKS5 connection from {:?} to {}:{}",
+        addr,
+        remote_host,
+        remote_port
+    );
+    set_port_forward(
+        &interface.get_lch(),
+        remote_host,
+        remote_port,
+        SessionMode::Forward,
+    );
+    let mut forward = Framed::new(forward, BytesCodec::new());
+    let res = connect_and_login(
+        id,
+        password,
+        ui_receiver,
+        interface.clone(),
+        Some(&mut forward),
+        key,
+        token,
+        false,
+        watchdog,
+    )
+    .await;
+    match res {
+        Ok(Some(stream)) => {
+            allow_err!(forward.send(socks5_reply(SOCKS5_SUCCEEDED)).await);
+            tokio::spawn(run_forward_connection(
+                watchdog.clone(),
+                addr.to_string(),
+                forward,
+                stream,
+                interface,
+            ));
+        }
+        Err(err) => {
+            allow_err!(forward.send(socks5_reply(SOCKS5_HOST_UNREACHABLE)).await);
+            interface.on_establish_connection_error(err.to_string());
+        }
+        _ => {
+            allow_err!(forward.send(socks5_reply(SOCKS5_NOT_ALLOWED)).await);
+        }
+    }
+}
+
+const SOCKS5_SUCCEEDED: u8 = 0x00;
+const SOCKS5_NOT_ALLOWED: u8 = 0x02;
+const SOCKS5_HOST_UNREACHABLE: u8 = 0x04;
+const SOCKS5_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const SOCKS5_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+fn socks5_reply(rep: u8) -> bytes::Bytes {
+    // The bound address is not meaningful for a tunnel, always report 0.0.0.0:0.
+    bytes::Bytes::from(vec![0x05, rep, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+}
+
+/// Read the method selection and the request, return the requested destination.
+async fn socks5_handshake(sock: &mut TcpStream) -> ResultType<(String, i32)> {
+    let mut head = [0u8; 2];
+    sock.read_exact(&mut head).await?;
+    if head[0] != 0x05 {
+        bail!("unsupported SOCKS version {}", head[0]);
+    }
+    let mut methods = vec![0u8; head[1] as usize];
+    sock.read_exact(&mut methods).await?;
+    if !methods.contains(&0x00) {
+        sock.write_all(&[0x05, 0xff]).await?;
+        bail!("no acceptable authentication method");
+    }
+    sock.write_all(&[0x05, 0x00]).await?;
+
+    let mut req = [0u8; 4];
+    sock.read_exact(&mut req).await?;
+    if req[1] != 0x01 {
+        sock.write_all(&socks5_reply(SOCKS5_COMMAND_NOT_SUPPORTED))
+            .await?;
+        bail!("unsupported SOCKS command {}", req[1]);
+    }
+    let host = match req[3] {
+        0x01 => {
+            let mut ip = [0u8; 4];
+            sock.read_exact(&mut ip).await?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        }
+        0x03 => {
+            let mut len = [0u8; 1];
+            sock.read_exact(&mut len).await?;
+            let mut domain = vec![0u8; len[0] as usize];
+            sock.read_exact(&mut domain).await?;
+            String::from_utf8(domain)?
+        }
+        0x04 => {
+            let mut ip = [0u8; 16];
+            sock.read_exact(&mut ip).await?;
+            format!("[{}]", std::net::Ipv6Addr::from(ip))
+        }
+        _ => {
+            sock.write_all(&socks5_reply(SOCKS5_ADDRESS_NOT_SUPPORTED))
+                .await?;
+            bail!("unsupported SOCKS address type {}", req[3]);
+        }
+    };
+    let mut port = [0u8; 2];
+    sock.read_exact(&mut port).await?;
+    Ok((host, u16::from_be_bytes(port) as i32))
+}
+
+/// Multiplexed forwarding: all connections accepted on the local port share a
+/// single session, opened on the first connection. Once it has been used, the
+/// session is kept alive and reconnected with backoff after it drops.
+pub async fn listen_mux(
+    id: String,
+    password: Zeroizing<String>,
+    local_host: String,
+    port: i32,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    remote_host: String,
+    remote_port: i32,
+    allow_from: &[IpCidr],
+) -> ResultType<()> {
+    let listener = tcp::new_listener(format!("{}:{}", local_host, port), true).await?;
+    let addr = listener.local_addr()?;
+    log::info!("mux listening on {:?}", addr);
+    let watchdog = Watchdog::new(format!(
+        "mux :{} -> {}:{}",
+        addr.port(),
+        remote_host,
+        remote_port
+    ));
+    let target = (remote_host, remote_port);
+    let mut ui_receiver = ui_receiver;
+    let mut stream: Option<Stream> = None;
+    let mut login: Option<oneshot::Receiver<ResultType<Option<Stream>>>> = None;
+    // Connections accepted while the session logs in.
+    let mut waiting: Vec<(TcpStream, SocketAddr)> = Vec::new();
+    let mut mux = Mux::new();
+    // stream id -> (from, bytes sent, bytes received)
+    let mut stats: HashMap<u32, (String, u64, u64)> = HashMap::new();
+    let mut next_stream_id: u32 = 0;
+    let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+    loop {
+        tokio::select! {
+            Ok((forward, addr)) = listener.accept() => {
+                if !is_source_allowed(allow_from, &addr) {
+                    log::warn!("mux connection from {:?} is not allowed", addr);
+                    continue;
+                }
+                if mux.is_full() || mux.len() + waiting.len() >= MUX_MAX_STREAMS {
+                    log::warn!("mux connection from {:?} dropped, too many streams", addr);
+                    continue;
+                }
+                let Some(s) = stream.as_mut() else {
+                    waiting.push((forward, addr));
+                    if login.is_none() {
+                        login = Some(start_login(&id, &password, &interface, &mut ui_receiver, key, token, &target, SessionMode::Mux, &watchdog, true).await);
+                    }
+                    continue;
+                };
+                if let Err(err) = open_mux_stream(s, &mut mux, &mut stats, &mut next_stream_id, forward, addr, &watchdog.name).await {
+                    log::error!("mux session closed: {}", err);
+                    stream = None;
+                    close_mux(&mut mux, &mut stats, &watchdog.name);
+                    watchdog.on_down();
+                }
+            }
+            res = async {
+                match login.as_mut() {
+                    Some(rx) => rx.await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                login = None;
+                match res {
+                    Ok(Ok(Some(mut s))) => {
+                        let mut res = Ok(());
+                        for (forward, addr) in waiting.drain(..) {
+                            if res.is_ok() {
+                                res = open_mux_stream(&mut s, &mut mux, &mut stats, &mut next_stream_id, forward, addr, &watchdog.name).await;
+                            }
+                        }
+                        match res {
+                            Ok(()) => stream = Some(s),
+                            Err(err) => {
+                                log::error!("mux session closed: {}", err);
+                                close_mux(&mut mux, &mut stats, &watchdog.name);
+                                watchdog.on_down();
+                            }
+                        }
+                    }
+                    Ok(Ok(None)) => break,
+                    _ => waiting.clear(),
+                }
+            }
+            Some(frame) = mux.next_frame() => {
+                if let Some(s) = stream.as_mut() {
+                    mux_frame_stats(&frame, &mut stats, &watchdog.name, true);
+                    let mut msg_out = Message::new();
+                    msg_out.set_port_forward_mux(frame);
+                    if let Err(err) = s.send(&msg_out).await {
+                        log::error!("mux session closed: {}", err);
+                        stream = None;
+                        close_mux(&mut mux, &mut stats, &watchdog.name);
+                        watchdog.on_down();
+                    }
+                }
+            }
+            res = async {
+                match stream.as_mut() {
+                    Some(s) => s.next().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                match res {
+                    Some(Ok(bytes)) => {
+                        watchdog.on_recv();
+                        let msg_in = match Message::parse_from_bytes(&bytes) {
+                            Ok(msg_in) => msg_in,
+                            Err(err) => {
+                                log::error!("mux session: bad message: {}", err);
+                                continue;
+                            }
+                        };
+                        match msg_in.union {
+                            Some(message::Union::PortForwardMux(frame)) => {
+                                mux_frame_stats(&frame, &mut stats, &watchdog.name, false);
+                                // The controlled side never opens streams.
+                                if let Some(stream_id) = mux.handle_frame(frame) {
+                                    mux.reset(stream_id);
+                                }
+                            }
+                            Some(message::Union::TestDelay(t)) => {
+                                if let Some(s) = stream.as_mut() {
+                                    interface.handle_test_delay(t, s).await;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ => {
+                        log::info!("mux session closed, {} stream(s) dropped", mux.len());
+                        stream = None;
+                        close_mux(&mut mux, &mut stats, &watchdog.name);
+                        watchdog.on_down();
+                    }
+                }
+            }
+            _ = keepalive.tick() => {
+                if let Some(s) = stream.as_mut() {
+                    if !watchdog.probe(s).await {
+                        log::error!("mux session timeout, {} stream(s) dropped", mux.len());
+                        stream = None;
+                        close_mux(&mut mux, &mut stats, &watchdog.name);
+                        watchdog.on_down();
+                    }
+                } else if login.is_none() && watchdog.should_reconnect() {
+                    login = Some(start_login(&id, &password, &interface, &mut ui_receiver, key, token, &target, SessionMode::Mux, &watchdog, false).await);
+                }
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add an accepted connection to the mux and ask the peer to open its stream.
+async fn open_mux_stream(
+    stream: &mut Stream,
+    mux: &mut Mux,
+    stats: &mut HashMap<u32, (String, u64, u64)>,
+    next_stream_id: &mut u32,
+    forward: TcpStream,
+    addr: SocketAddr,
+    tunnel: &str,
+) -> ResultType<()> {
+    *next_stream_id = next_stream_id.wrapping_add(1);
+    log::info!("new mux stream {} from {:?}", next_stream_id, addr);
+    mux.add(*next_stream_id, MuxTarget::Socket(forward));
+    report_opened(tunnel, &addr.to_string());
+    stats.insert(*next_stream_id, (addr.to_string(), 0, 0));
+    let mut msg_out = Message::new();
+    msg_out.set_port_forward_mux(crate::port_forward_mux::open_frame(*next_stream_id));
+    stream.send(&msg_out).await
+}
+
+/// Log in a message mode session without holding up the loop of `listen_mux`
+/// or `listen_udp`, which get the session from the returned receiver. The login
+/// runs inline if the interface can not fork a session.
+async fn start_login(
+    id: &str,
+    password: &Zeroizing<String>,
+    interface: &impl Interface,
+    ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    target: &(String, i32),
+    mode: SessionMode,
+    watchdog: &Watchdog,
+    report_error: bool,
+) -> oneshot::Receiver<ResultType<Option<Stream>>> {
+    let (tx, rx) = oneshot::channel();
+    match interface.fork_session() {
+        Some((interface, mut conn_receiver)) => {
+            let (id, password, key, token, target, watchdog) = (
+                id.to_owned(),
+                password.clone(),
+                key.to_owned(),
+                token.to_owned(),
+                target.clone(),
+                watchdog.clone(),
+            );
+            tokio::spawn(async move {
+                set_port_forward(&interface.get_lch(), target.0, target.1, mode);
+                let res = connect_and_login(
+                    &id,
+                    &password,
+                    &mut conn_receiver,
+                    interface.clone(),
+                    None,
+                    &key,
+                    &token,
+                    false,
+                    &watchdog,
+                )
+                .await;
+                tx.send(login_result(res, &interface, &watchdog, report_error))
+                    .ok();
+            });
+        }
+        None => {
+            set_port_forward(&interface.get_lch(), target.0.clone(), target.1, mode);
+            let res = connect_and_login(
+                id,
+                password,
+                ui_receiver,
+                interface.clone(),
+                None,
+                key,
+                token,
+                false,
+                watchdog,
+            )
+            .await;
+            tx.send(login_result(res, interface, watchdog, report_error))
+                .ok();
+        }
+    }
+    rx
+}
+
+/// Report a failed login of `start_login`, to the user if a local client is
+/// waiting for it, otherwise only in the log.
+fn login_result(
+    res: ResultType<Option<Stream>>,
+    interface: &impl Interface,
+    watchdog: &Watchdog,
+    report_error: bool,
+) -> ResultType<Option<Stream>> {
+    if let Err(err) = &res {
+        if report_error {
+            interface.on_establish_connection_error(err.to_string());
+        } else {
+            log::error!("tunnel {}: reconnect failed: {}", watchdog.name, err);
+        }
+    }
+    res
+}
+
+/// Count the data of a mux frame, and report the stream if the frame closes it.
+fn mux_frame_stats(
+    frame: &PortForwardMux,
+    stats: &mut HashMap<u32, (String, u64, u64)>,
+    tunnel: &str,
+    outgoing: bool,
+) {
+    match &frame.union {
+        Some(port_forward_mux::Union::Data(data)) => {
+            if let Some((_, sent, received)) = stats.get_mut(&frame.stream_id) {
+                if outgoing {
+                    *sent += data.len() as u64;
+                } else {
+                    *received += data.len() as u64;
+                }
+            }
+        }
+        Some(port_forward_mux::Union::Close(_)) => {
+            if let Some((from, sent, received)) = stats.remove(&frame.stream_id) {
+                report_closed(tunnel, &from, sent, received);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn close_mux(mux: &mut Mux, stats: &mut HashMap<u32, (String, u64, u64)>, tunnel: &str) {
+    mux.close_all();
+    for (_, (from, sent, received)) in stats.drain() {
+        report_closed(tunnel, &from, sent, received);
+    }
+}
+
+const UDP_FLOW_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+// Like the peer, which keeps a socket per flow.
+const UDP_MAX_FLOWS: usize = 256;
+// Datagrams kept while the session logs in, the rest is dropped.
+const UDP_MAX_WAITING: usize = 256;
+
+struct UdpFlow {
+    id: u32,
+    last_active: Instant,
+    sent: u64,
+    received: u64,
+}
+
+/// UDP forwarding: datagrams from each local client address are tagged with a
+/// flow id and carried over a single session to `remote_host:remote_port`.
+/// The session is opened on the first datagram, and reconnected with backoff
+/// after it drops while there are active flows.
+pub async fn listen_udp(
+    id: String,
+    password: Zeroizing<String>,
+    local_host: String,
+    port: i32,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    remote_host: String,
+    remote_port: i32,
+    allow_from: &[IpCidr],
+) -> ResultType<()> {
+    let socket = UdpSocket::bind(format!("{}:{}", local_host, port)).await?;
+    let addr = socket.local_addr()?;
+    log::info!("UDP listening on {:?}", addr);
+    let watchdog = Watchdog::new(format!(
+        "udp :{} -> {}:{}",
+        addr.port(),
+        remote_host,
+        remote_port
+    ));
+    let target = (remote_host, remote_port);
+    let mut ui_receiver = ui_receiver;
+    let mut stream: Option<Stream> = None;
+    let mut login: Option<oneshot::Receiver<ResultType<Option<Stream>>>> = None;
+    // Datagrams received while the session logs in, with their flow id.
+    let mut waiting: Vec<(u32, Vec<u8>)> = Vec::new();
+    let mut flows: HashMap<SocketAddr, UdpFlow> = HashMap::new();
+    // flow id -> client address, for the datagrams from the peer
+    let mut flow_addrs: HashMap<u32, SocketAddr> = HashMap::new();
+    let mut next_flow_id: u32 = 0;
+    let mut buf = vec![0u8; 65536];
+    let mut timer = tokio::time::interval(Duration::from_secs(30));
+    let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+    loop {
+        tokio::select! {
+            res = socket.recv_from(&mut buf) => {
+                // e.g. a connection reset reported for an earlier send_to on Windows
+                let (n, addr) = match res {
+                    Ok(res) => res,
+                    Err(err) => {
+                        log::warn!("UDP receive failed: {}", err);
+                        continue;
+                    }
+                };
+                if !is_source_allowed(allow_from, &addr) {
+                    log::debug!("UDP datagram from {:?} is not allowed", addr);
+                    continue;
+                }
+                if !flows.contains_key(&addr) && flows.len() >= UDP_MAX_FLOWS {
+                    // Make room by dropping the least recently active flow.
+                    if let Some(oldest) = flows
+                        .iter()
+                        .min_by_key(|(_, flow)| flow.last_active)
+                        .map(|(addr, _)| *addr)
+                    {
+                        if let Some(flow) = flows.remove(&oldest) {
+                            log::info!("UDP flow {} from {:?} evicted", flow.id, oldest);
+                            flow_addrs.remove(&flow.id);
+                            report_closed(&watchdog.name, &oldest.to_string(), flow.sent, flow.received);
+                        }
+                    }
+                }
+                let flow = flows.entry(addr).or_insert_with(|| {
+                    next_flow_id = next_flow_id.wrapping_add(1);
+                    log::info!("new UDP flow {} from {:?}", next_flow_id, addr);
+                    report_opened(&watchdog.name, &addr.to_string());
+                    flow_addrs.insert(next_flow_id, addr);
+                    UdpFlow {
+                        id: next_flow_id,
+                        last_active: Instant::now(),
+                        sent: 0,
+                        received: 0,
+                    }
+                });
+                flow.last_active = Instant::now();
+                flow.sent += n as u64;
+                let flow_id = flow.id;
+                let Some(s) = stream.as_mut() else {
+                    if waiting.len() < UDP_MAX_WAITING {
+                        waiting.push((flow_id, buf[..n].to_vec()));
+                    }
+                    if login.is_none() {
+                        login = Some(start_login(&id, &password, &interface, &mut ui_receiver, key, token, &target, SessionMode::Udp, &watchdog, true).await);
+                    }
+                    continue;
+                };
+                if let Err(err) = send_datagram(s, flow_id, buf[..n].to_vec()).await {
+                    log::error!("UDP session closed: {}", err);
+                    stream = None;
+                    watchdog.on_down();
+                }
+            }
+            res = async {
+                match login.as_mut() {
+                    Some(rx) => rx.await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                login = None;
+                match res {
+                    Ok(Ok(Some(mut s))) => {
+                        let mut res = Ok(());
+                        for (flow_id, data) in waiting.drain(..) {
+                            if res.is_ok() {
+                                res = send_datagram(&mut s, flow_id, data).await;
+                            }
+                        }
+                        match res {
+                            Ok(()) => stream = Some(s),
+                            Err(err) => {
+                                log::error!("UDP session closed: {}", err);
+                                watchdog.on_down();
+                            }
+                        }
+                    }
+                    Ok(Ok(None)) => break,
+                    _ => waiting.clear(),
+                }
+            }
+            res = async {
+                match stream.as_mut() {
+                    Some(s) => s.next().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                match res {
+                    Some(Ok(bytes)) => {
+                        watchdog.on_recv();
+                        let msg_in = match Message::parse_from_bytes(&bytes) {
+                            Ok(msg_in) => msg_in,
+                            Err(err) => {
+                                log::error!("UDP session: bad message: {}", err);
+                                continue;
+                            }
+                        };
+                        match msg_in.union {
+                            Some(message::Union::PortForwardDatagram(d)) => {
+                                let flow = flow_addrs
+                                    .get(&d.flow_id)
+                                    .and_then(|addr| flows.get_mut(addr).map(|flow| (*addr, flow)));
+                                if let Some((addr, flow)) = flow {
+                                    flow.last_active = Instant::now();
+                                    flow.received += d.data.len() as u64;
+                                    allow_err!(socket.send_to(&d.data, addr).await);
+                                }
+                            }
+                            Some(message::Union::TestDelay(t)) => {
+                                if let Some(s) = stream.as_mut() {
+                                    interface.handle_test_delay(t, s).await;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ => {
+                        log::info!("UDP session closed");
+                        stream = None;
+                        watchdog.on_down();
+                    }
+                }
+            }
+            _ = keepalive.tick() => {
+                if let Some(s) = stream.as_mut() {
+                    if !watchdog.probe(s).await {
+                        log::error!("UDP session timeout");
+                        stream = None;
+                        watchdog.on_down();
+                    }
+                } else if !flows.is_empty() && login.is_none() && watchdog.should_reconnect() {
+                    login = Some(start_login(&id, &password, &interface, &mut ui_receiver, key, token, &target, SessionMode::Udp, &watchdog, false).await);
+                }
+            }
+            _ = timer.tick() => {
+                flows.retain(|addr, flow| {
+                    if flow.last_active.elapsed() >= UDP_FLOW_IDLE_TIMEOUT {
+                        log::info!("UDP flow {} from {:?} idle timeout", flow.id, addr);
+                        flow_addrs.remove(&flow.id);
+                        report_closed(&watchdog.name, &addr.to_string(), flow.sent, flow.received);
+                        false
+                    } else {
+                        true
+                    }
+                });
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn send_datagram(stream: &mut Stream, flow_id: u32, data: Vec<u8>) -> ResultType<()> {
+    let mut msg_out = Message::new();
+    msg_out.set_port_forward_datagram(PortForwardDatagram {
+        flow_id,
+        data: data.into(),
+        ..Default::default()
+    });
+    stream.send(&msg_out).await
+}
+
+/// Reverse tunnel: the peer listens on `remote_host:remote_port` and every
+/// connection it accepts is forwarded to `local_host:local_port` on this side.
+///
+/// The peer keeps listening for the whole session and carries the connections
+/// as mux streams. The peer only listens on loopback unless its
+/// `allow-tunnel-gateway-ports` option is set.
+pub async fn listen_reverse(
+    id: String,
+    password: Zeroizing<String>,
+    local_host: String,
+    local_port: i32,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    remote_host: String,
+    remote_port: i32,
+) -> ResultType<()> {
+    let target = format!("{}:{}", local_host, local_port);
+    log::info!(
+        "reverse forwarding remote port {} to {}",
+        remote_port,
+        target
+    );
+    let watchdog = Watchdog::new(format!("remote :{} -> {}", remote_port, target));
+    let mut ui_receiver = ui_receiver;
+    loop {
+        set_port_forward(&lc, remote_host.clone(), remote_port, SessionMode::Reverse);
+        let res = connect_and_login(
+            &id,
+            &password,
+            &mut ui_receiver,
+            interface.clone(),
+            None,
+            key,
+            token,
+            false,
+            &watchdog,
+        )
+        .await;
+        let mut stream = match res {
+            Ok(Some(stream)) => stream,
+            Ok(None) => break,
+            Err(err) => {
+                interface.on_establish_connection_error(err.to_string());
+                let closed = tokio::select! {
+                    _ = tokio::time::sleep(watchdog.retry_delay()) => false,
+                    d = ui_receiver.recv() => matches!(d, Some(Data::Close) | None),
+                };
+                if closed {
+                    break;
+                }
+                continue;
+            }
+        };
+        match run_reverse(&mut stream, &target, interface.clone(), &mut ui_receiver, &watchdog)
+            .await
+        {
+            Ok(()) => break,
+            Err(err) => {
+                log::error!("reverse port forward session ended: {}", err);
+                watchdog.on_down();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Carry the connections accepted by the peer's listener to `target`, each one
+/// a mux stream opened by the peer. Returns `Ok` if the tunnel is closed locally.
+async fn run_reverse(
+    stream: &mut Stream,
+    target: &str,
+    interface: impl Interface,
+    ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
+    watchdog: &Watchdog,
+) -> ResultType<()> {
+    let mut mux = Mux::new();
+    // stream id -> (from, bytes sent, bytes received)
+    let mut stats: HashMap<u32, (String, u64, u64)> = HashMap::new();
+    let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+    let res = loop {
+        tokio::select! {
+            res = stream.next() => match res {
+                Some(Ok(bytes)) => {
+                    watchdog.on_recv();
+                    let msg_in = match Message::parse_from_bytes(&bytes) {
+                        Ok(msg_in) => msg_in,
+                        Err(err) => {
+                            log::error!("reverse port forward: bad message: {}", err);
+                            continue;
+                        }
+                    };
+                    match msg_in.union {
+                        Some(message::Union::PortForwardMux(frame)) => {
+                            let from = frame.addr.clone();
+                            mux_frame_stats(&frame, &mut stats, &watchdog.name, false);
+                            if let Some(stream_id) = mux.handle_frame(frame) {
+                                log::info!(
+                                    "new reverse connection {} from {} to {}",
+                                    stream_id,
+                                    from,
+                                    target
+                                );
+                                mux.add(stream_id, MuxTarget::Connect(target.to_owned()));
+                                report_opened(&watchdog.name, &from);
+                                stats.insert(stream_id, (from, 0, 0));
+                            }
+                        }
+                        Some(message::Union::TestDelay(t)) => {
+                            interface.handle_test_delay(t, stream).await;
+                        }
+                        Some(message::Union::Misc(misc)) => {
+                            if let Some(misc::Union::CloseReason(reason)) = misc.union {
+                                break Err(anyhow!("{}", reason));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Some(Err(err)) => {
+                    break Err(anyhow!("Connection closed: {}", err));
+                }
+                None => {
+                    break Err(anyhow!("Reset by the peer"));
+                }
+            },
+            Some(frame) = mux.next_frame() => {
+                mux_frame_stats(&frame, &mut stats, &watchdog.name, true);
+                let mut msg_out = Message::new();
+                msg_out.set_port_forward_mux(frame);
+                if let Err(err) = stream.send(&msg_out).await {
+                    break Err(err);
+                }
+            }
+            _ = keepalive.tick() => {
+                if !watchdog.probe(stream).await {
+                    break Err(anyhow!("Timeout"));
+                }
+            }
+            d = ui_receiver.recv() => match d {
+                Some(Data::Close) | None => {
+                    break Ok(());
+                }
+                _ => {}
+            },
+        }
+    };
+    close_mux(&mut mux, &mut stats, &watchdog.name);
+    res
+}
+
+/// Log in a new session, retrying with backoff if the peer can not be reached.
 async fn connect_and_login(
     id: &str,
     password: &str,
     ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
     interface: impl Interface,
-    forward: &mut Framed<TcpStream, BytesCodec>,
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
     key: &str,
     token: &str,
     is_rdp: bool,
+    watchdog: &Watchdog,
 ) -> ResultType<Option<Stream>> {
+    // A tunnel that is up stays up while a new connection logs in.
+    let reconnecting = watchdog.state() != Some(TunnelState::Up);
+    if reconnecting {
+        watchdog.set_state(TunnelState::Connecting);
+    }
+    // Data read from the local socket survives failed attempts.
+    let mut buffer = Vec::new();
+    let mut attempts = 0;
     let conn_type = if is_rdp {
         ConnType::RDP
     } else {
         ConnType::PORT_FORWARD
     };
+    loop {
+        let res = try_connect_and_login(
+            id,
+            password,
+            ui_receiver,
+            interface.clone(),
+            forward.as_deref_mut(),
+            key,
+            token,
+            conn_type,
+            &mut buffer,
+        )
+        .await;
+        match res {
+            Ok(Some((stream, _hc))) => {
+                watchdog.on_connected();
+                return Ok(Some(stream));
+            }
+            Ok(None) => {
+                if reconnecting {
+                    watchdog.set_state(TunnelState::Down);
+                }
+                return Ok(None);
+            }
+            Err(err) => {
+                attempts += 1;
+                if attempts > RECONNECT_ATTEMPTS {
+                    watchdog.on_down();
+                    return Err(err);
+                }
+                let delay = watchdog.next_delay();
+                log::warn!("tunnel {}: {}, retry in {:?}", watchdog.name, err, delay);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    d = ui_receiver.recv() => {
+                        if matches!(d, Some(Data::Close) | None) {
+                            watchdog.set_state(TunnelState::Down);
+                            return Ok(None);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Connect to the peer and log in once. Without `forward` the stream stays in
+/// message mode, as the command line tools use it.
+/// Returns `None` if the login is given up, e.g. on a wrong password.
+/// The health check connection, if any, lives as long as the returned sender.
+pub(crate) async fn try_connect_and_login(
+    id: &str,
+    password: &str,
+    ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
+    interface: impl Interface,
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
+    key: &str,
+    token: &str,
+    conn_type: ConnType,
+    buffer: &mut Vec<u8>,
+) -> ResultType<Option<(Stream, Option<mpsc::UnboundedSender<()>>)>> {
     let ((mut stream, direct, _pk, _kcp, _stream_type), (feedback, rendezvous_server)) =
         Client::start(id, key, token, conn_type, interface.clone()).await?;
     interface.update_direct(Some(direct));
-    let mut buffer = Vec::new();
     let mut received = false;
 
-    let _keep_it = hc_connection(feedback, rendezvous_server, token).await;
+    let hc = hc_connection(feedback, rendezvous_server, token).await;
 
     loop {
         tokio::select! {
@@ -157,6 +1409,9 @@ async fn connect_and_login(
                         Some(message::Union::TestDelay(t)) => {
                             interface.handle_test_delay(t, &mut stream).await;
                         }
+                        Some(message::Union::MessageBox(msgbox)) => {
+                            interface.msgbox(&msgbox.msgtype, &msgbox.title, &msgbox.text, &msgbox.link);
+                        }
                         _ => {}
                     }
                 }
@@ -175,10 +1430,18 @@ async fn connect_and_login(
                     Some(Data::Message(msg)) => {
                         allow_err!(stream.send(&msg).await);
                     }
+                    Some(Data::Close) | None => {
+                        return Ok(None);
+                    }
                     _ => {}
                 }
             },
-            res = forward.next() => {
+            res = async {
+                match forward.as_deref_mut() {
+                    Some(forward) => forward.next().await,
+                    None => std::future::pending().await,
+                }
+            } => {
                 if let Some(Ok(bytes)) = res {
                     buffer.extend(bytes);
                 } else {
@@ -187,34 +1450,58 @@ async fn connect_and_login(
             },
         }
     }
+    if forward.is_none() {
+        // Reverse, UDP and mux modes stay in message mode.
+        return Ok(Some((stream, hc)));
+    }
     stream.set_raw();
     if !buffer.is_empty() {
-        allow_err!(stream.send_bytes(buffer.into()).await);
+        allow_err!(stream.send_bytes(std::mem::take(buffer).into()).await);
     }
-    Ok(Some(stream))
+    Ok(Some((stream, hc)))
 }
 
-async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) -> ResultType<()> {
+/// Returns the number of bytes sent to and received from the peer.
+/// A broken peer connection marks the tunnel down, the next connection logs in again.
+async fn run_forward(
+    forward: Framed<TcpStream, BytesCodec>,
+    stream: Stream,
+    watchdog: &Watchdog,
+) -> ResultType<(u64, u64)> {
     log::info!("new port forwarding connection started");
     let mut forward = forward;
     let mut stream = stream;
+    let mut sent = 0u64;
+    let mut received = 0u64;
     loop {
         tokio::select! {
             res = forward.next() => {
                 if let Some(Ok(bytes)) = res {
-                    allow_err!(stream.send_bytes(bytes.into()).await);
+                    sent += bytes.len() as u64;
+                    if let Err(err) = stream.send_bytes(bytes.into()).await {
+                        log::error!("tunnel {}: {}", watchdog.name, err);
+                        watchdog.set_state(TunnelState::Down);
+                        break;
+                    }
                 } else {
                     break;
                 }
             },
             res = stream.next() => {
-                if let Some(Ok(bytes)) = res {
-                    allow_err!(forward.send(bytes).await);
-                } else {
-                    break;
+                match res {
+                    Some(Ok(bytes)) => {
+                        received += bytes.len() as u64;
+                        allow_err!(forward.send(bytes).await);
+                    }
+                    Some(Err(err)) => {
+                        log::error!("tunnel {}: {}", watchdog.name, err);
+                        watchdog.set_state(TunnelState::Down);
+                        break;
+                    }
+                    None => break,
                 }
             },
         }
     }
-    Ok(())
+    Ok((sent, received))
 }
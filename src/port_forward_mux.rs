@@ -0,0 +1,325 @@
+// Multiplexing of many forwarded TCP connections over one port forward session.
+//
+// Streams are opened by the controlling side, or by the controlled side for the
+// connections accepted by a reverse port forward listener.
+// Both sides keep a `Mux` with a table of streams. Each stream has a reader task
+// that sends `data` frames within the send window granted by the peer, and a
+// writer task that writes received data to the socket and returns the window
+// to the peer with `window_update` frames once it is consumed. A peer that sends
+// more than the window it was granted gets the stream reset.
+// Half-closed connections are not supported, EOF on either side closes the stream.
+
+use hbb_common::{
+    bytes::Bytes,
+    log,
+    message_proto::*,
+    timeout,
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::{mpsc, Semaphore},
+        task::JoinHandle,
+    },
+};
+use std::{collections::HashMap, sync::Arc};
+
+pub const MUX_INITIAL_WINDOW: u32 = 256 * 1024;
+const MUX_CHUNK_SIZE: usize = 32 * 1024;
+const MUX_WINDOW_UPDATE_THRESHOLD: u32 = 64 * 1024;
+const MUX_CONNECT_TIMEOUT: u64 = 3_000;
+pub const MUX_MAX_STREAMS: usize = 256;
+
+enum Event {
+    Data(u32, Bytes),
+    Consumed(u32, u32),
+    Closed(u32),
+    Reset(u32),
+}
+
+struct MuxStream {
+    tx_data: mpsc::UnboundedSender<Bytes>,
+    send_window: Arc<Semaphore>,
+    // What the peer may still send, the data queued for the writer is bounded by it.
+    recv_window: u32,
+    consumed: u32,
+    reader: JoinHandle<()>,
+}
+
+pub struct Mux {
+    streams: HashMap<u32, MuxStream>,
+    tx_event: mpsc::UnboundedSender<Event>,
+    rx_event: mpsc::UnboundedReceiver<Event>,
+}
+
+pub enum Target {
+    Socket(TcpStream),
+    Connect(String),
+}
+
+impl Mux {
+    pub fn new() -> Self {
+        let (tx_event, rx_event) = mpsc::unbounded_channel();
+        Self {
+            streams: HashMap::new(),
+            tx_event,
+            rx_event,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.streams.len() >= MUX_MAX_STREAMS
+    }
+
+    /// Add a stream, either an accepted socket (controlling side) or a
+    /// destination to connect to (controlled side).
+    pub fn add(&mut self, stream_id: u32, target: Target) {
+        let (tx_data, rx_data) = mpsc::unbounded_channel();
+        let send_window = Arc::new(Semaphore::new(MUX_INITIAL_WINDOW as _));
+        let reader = tokio::spawn(run_stream(
+            stream_id,
+            target,
+            rx_data,
+            send_window.clone(),
+            self.tx_event.clone(),
+        ));
+        if let Some(old) = self.streams.insert(
+            stream_id,
+            MuxStream {
+                tx_data,
+                send_window,
+                recv_window: MUX_INITIAL_WINDOW,
+                consumed: 0,
+                reader,
+            },
+        ) {
+            old.reader.abort();
+        }
+    }
+
+    /// Handle a frame from the peer. Returns the stream id if the peer asks to
+    /// open a new stream, the caller decides where to connect it.
+    pub fn handle_frame(&mut self, frame: PortForwardMux) -> Option<u32> {
+        let stream_id = frame.stream_id;
+        match frame.union {
+            Some(port_forward_mux::Union::Open(_)) => {
+                if self.is_full() {
+                    log::warn!("mux stream {}: too many streams, reset", stream_id);
+                    self.reset(stream_id);
+                    return None;
+                }
+                return Some(stream_id);
+            }
+            Some(port_forward_mux::Union::Data(data)) => {
+                let Some(s) = self.streams.get_mut(&stream_id) else {
+                    return None;
+                };
+                if data.len() > s.recv_window as usize {
+                    log::warn!("mux stream {}: receive window exceeded, reset", stream_id);
+                    self.reset(stream_id);
+                    return None;
+                }
+                s.recv_window -= data.len() as u32;
+                s.tx_data.send(data).ok();
+            }
+            Some(port_forward_mux::Union::WindowUpdate(n)) => {
+                let Some(s) = self.streams.get(&stream_id) else {
+                    return None;
+                };
+                // The peer only returns the window it has consumed.
+                if s.send_window.available_permits() + n as usize > MUX_INITIAL_WINDOW as usize {
+                    log::warn!("mux stream {}: send window exceeded, reset", stream_id);
+                    self.reset(stream_id);
+                    return None;
+                }
+                s.send_window.add_permits(n as _);
+            }
+            Some(port_forward_mux::Union::Close(_)) => {
+                // Dropping `tx_data` lets the writer flush the pending data and shut down.
+                if let Some(s) = self.streams.remove(&stream_id) {
+                    s.reader.abort();
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Close a stream on both sides, also one the caller refuses to open.
+    pub fn reset(&mut self, stream_id: u32) {
+        if let Some(s) = self.streams.remove(&stream_id) {
+            s.reader.abort();
+        }
+        self.tx_event.send(Event::Reset(stream_id)).ok();
+    }
+
+    /// Wait for the next frame to send to the peer.
+    pub async fn next_frame(&mut self) -> Option<PortForwardMux> {
+        loop {
+            let event = self.rx_event.recv().await?;
+            let mut frame = PortForwardMux::new();
+            match event {
+                Event::Data(stream_id, data) => {
+                    if !self.streams.contains_key(&stream_id) {
+                        continue;
+                    }
+                    frame.stream_id = stream_id;
+                    frame.set_data(data);
+                }
+                Event::Consumed(stream_id, n) => {
+                    let Some(s) = self.streams.get_mut(&stream_id) else {
+                        continue;
+                    };
+                    s.consumed += n;
+                    if s.consumed < MUX_WINDOW_UPDATE_THRESHOLD {
+                        continue;
+                    }
+                    frame.stream_id = stream_id;
+                    frame.set_window_update(s.consumed);
+                    s.recv_window += s.consumed;
+                    s.consumed = 0;
+                }
+                Event::Closed(stream_id) => {
+                    let Some(s) = self.streams.remove(&stream_id) else {
+                        continue;
+                    };
+                    s.reader.abort();
+                    frame.stream_id = stream_id;
+                    frame.set_close(true);
+                }
+                Event::Reset(stream_id) => {
+                    frame.stream_id = stream_id;
+                    frame.set_close(true);
+                }
+            }
+            return Some(frame);
+        }
+    }
+
+    pub fn close_all(&mut self) {
+        for (_, s) in self.streams.drain() {
+            s.reader.abort();
+        }
+    }
+}
+
+impl Drop for Mux {
+    fn drop(&mut self) {
+        self.close_all();
+    }
+}
+
+pub fn open_frame(stream_id: u32) -> PortForwardMux {
+    let mut frame = PortForwardMux::new();
+    frame.stream_id = stream_id;
+    frame.set_open(true);
+    frame
+}
+
+async fn run_stream(
+    stream_id: u32,
+    target: Target,
+    rx_data: mpsc::UnboundedReceiver<Bytes>,
+    send_window: Arc<Semaphore>,
+    tx_event: mpsc::UnboundedSender<Event>,
+) {
+    let socket = match target {
+        Target::Socket(socket) => socket,
+        Target::Connect(addr) => {
+            match timeout(MUX_CONNECT_TIMEOUT, TcpStream::connect(&addr)).await {
+                Ok(Ok(socket)) => socket,
+                _ => {
+                    log::error!("mux stream {}: failed to connect to {}", stream_id, addr);
+                    tx_event.send(Event::Closed(stream_id)).ok();
+                    return;
+                }
+            }
+        }
+    };
+    let (mut rd, wr) = socket.into_split();
+    tokio::spawn(write_stream(stream_id, wr, rx_data, tx_event.clone()));
+    let mut buf = vec![0u8; MUX_CHUNK_SIZE];
+    loop {
+        let n = match rd.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        match send_window.acquire_many(n as _).await {
+            Ok(permit) => permit.forget(),
+            Err(_) => break,
+        }
+        if tx_event
+            .send(Event::Data(stream_id, Bytes::copy_from_slice(&buf[..n])))
+            .is_err()
+        {
+            break;
+        }
+    }
+    tx_event.send(Event::Closed(stream_id)).ok();
+}
+
+async fn write_stream(
+    stream_id: u32,
+    mut wr: tokio::net::tcp::OwnedWriteHalf,
+    mut rx_data: mpsc::UnboundedReceiver<Bytes>,
+    tx_event: mpsc::UnboundedSender<Event>,
+) {
+    while let Some(data) = rx_data.recv().await {
+        if let Err(err) = wr.write_all(&data).await {
+            log::debug!("mux stream {}: write error: {}", stream_id, err);
+            tx_event.send(Event::Closed(stream_id)).ok();
+            return;
+        }
+        tx_event
+            .send(Event::Consumed(stream_id, data.len() as _))
+            .ok();
+    }
+    wr.shutdown().await.ok();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(stream_id: u32, data: Option<usize>) -> PortForwardMux {
+        let mut frame = PortForwardMux::new();
+        frame.stream_id = stream_id;
+        match data {
+            Some(len) => frame.set_data(Bytes::from(vec![0u8; len])),
+            None => frame.set_open(true),
+        }
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_mux_limits() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let _peer = listener.accept().await.unwrap();
+        let mut mux = Mux::new();
+        mux.add(1, Target::Socket(socket));
+        // the whole window, then one byte more
+        assert!(mux
+            .handle_frame(frame(1, Some(MUX_INITIAL_WINDOW as usize)))
+            .is_none());
+        assert_eq!(mux.len(), 1);
+        mux.handle_frame(frame(1, Some(1)));
+        assert_eq!(mux.len(), 0);
+        let reset = mux.next_frame().await.unwrap();
+        assert_eq!(reset.stream_id, 1);
+        assert!(reset.has_close());
+
+        for i in 0..MUX_MAX_STREAMS as u32 {
+            assert_eq!(mux.handle_frame(frame(i, None)), Some(i));
+            mux.add(i, Target::Connect("127.0.0.1:1".to_owned()));
+        }
+        assert!(mux.is_full());
+        assert_eq!(mux.handle_frame(frame(MUX_MAX_STREAMS as u32, None)), None);
+    }
+}
@@ -5,6 +5,10 @@
 use std::process::{Child, Command, Stdio};
 use std::sync::{Arc, Mutex};
 use hbb_common::log;
+use zeroize::Zeroizing;
+
+// Where mstsc looks for the credentials of localhost.
+const CREDENTIAL_TARGET: &str = "TERMSRV/127.0.0.1";
 
 lazy_static::lazy_static! {
     pub static ref RDP_SESSION: Arc<Mutex<Option<RdpSession>>> = Arc::new(Mutex::new(None));
@@ -14,7 +18,7 @@ lazy_static::lazy_static! {
 #[derive(Clone)]
 pub struct RdpCredentials {
     pub username: String,
-    pub password: String,
+    pub password: Zeroizing<String>,
 }
 
 pub struct RdpSession {
@@ -26,35 +30,16 @@ impl RdpSession {
     /// This will disconnect any existing RDP sessions to avoid conflicts
     pub fn start(credentials: &RdpCredentials) -> std::io::Result<Self> {
         log::info!("Starting localhost RDP session for headless mode...");
-        log::info!("RDP username: {}", credentials.username);
         
         // Step 1: Disconnect any existing RDP sessions to this machine
         log::info!("Disconnecting existing RDP sessions to avoid conflicts...");
         Self::disconnect_existing_sessions();
         
-        // Step 2: Store credentials for localhost RDP
-        log::debug!("Storing RDP credentials with cmdkey...");
-        let cmdkey_result = Command::new("cmdkey")
-            .args(&[
-                "/generic:TERMSRV/127.0.0.1",
-                &format!("/user:{}", credentials.username),
-                &format!("/pass:{}", credentials.password),
-            ])
-            .output();
-        
-        match &cmdkey_result {
-            Ok(output) => {
-                if output.status.success() {
-                    log::info!("RDP credentials stored successfully");
-                } else {
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    log::warn!("cmdkey output: {} {}", stdout, stderr);
-                }
-            }
-            Err(e) => {
-                log::warn!("Failed to run cmdkey: {}", e);
-            }
+        // Step 2: Store credentials for localhost RDP in the Credential Manager,
+        // not on the command line of cmdkey
+        match write_credential(credentials) {
+            Ok(()) => log::info!("RDP credentials stored successfully"),
+            Err(e) => log::warn!("Failed to store RDP credentials: {}", e),
         }
         
         // Step 3: Create RDP file with auto-login settings
@@ -126,7 +111,7 @@ username:s:{}
             .spawn()?;
         
         log::info!("RDP session started (PID: {})", process.id());
-        log::info!("If login prompt appears, credentials should auto-fill from cmdkey");
+        log::info!("If login prompt appears, credentials should auto-fill from the Credential Manager");
         
         // Give some time for RDP to establish
         std::thread::sleep(std::time::Duration::from_secs(3));
@@ -169,11 +154,7 @@ username:s:{}
         }
         
         // Clean up stored credentials
-        let _ = Command::new("cmdkey")
-            .args(&["/delete:127.0.0.1"])
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status();
+        delete_credential();
         
         // Also try to logoff the RDP session
         let _ = Command::new("cmd")
@@ -202,8 +183,44 @@ impl Drop for RdpSession {
     }
 }
 
+/// Save the credentials as a generic credential of the logon session, which is
+/// only readable by it and removed at logoff.
+fn write_credential(credentials: &RdpCredentials) -> std::io::Result<()> {
+    use winapi::um::wincred::{CredWriteW, CREDENTIALW, CRED_PERSIST_SESSION, CRED_TYPE_GENERIC};
+
+    let mut target: Vec<u16> = CREDENTIAL_TARGET.encode_utf16().chain(Some(0)).collect();
+    let mut username: Vec<u16> = credentials.username.encode_utf16().chain(Some(0)).collect();
+    let mut blob = Zeroizing::new(
+        credentials
+            .password
+            .encode_utf16()
+            .flat_map(|c| c.to_le_bytes())
+            .collect::<Vec<u8>>(),
+    );
+    let mut credential: CREDENTIALW = unsafe { std::mem::zeroed() };
+    credential.Type = CRED_TYPE_GENERIC;
+    credential.TargetName = target.as_mut_ptr();
+    credential.UserName = username.as_mut_ptr();
+    credential.CredentialBlobSize = blob.len() as _;
+    credential.CredentialBlob = blob.as_mut_ptr();
+    credential.Persist = CRED_PERSIST_SESSION;
+    if unsafe { CredWriteW(&mut credential, 0) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn delete_credential() {
+    use winapi::um::wincred::{CredDeleteW, CRED_TYPE_GENERIC};
+
+    let target: Vec<u16> = CREDENTIAL_TARGET.encode_utf16().chain(Some(0)).collect();
+    unsafe {
+        CredDeleteW(target.as_ptr(), CRED_TYPE_GENERIC, 0);
+    }
+}
+
 /// Set RDP credentials for headless mode
-pub fn set_rdp_credentials(username: String, password: String) {
+pub fn set_rdp_credentials(username: String, password: Zeroizing<String>) {
     let credentials = RdpCredentials { username, password };
     *RDP_CREDENTIALS.lock().unwrap() = Some(credentials);
     log::info!("RDP mode enabled for headless operation");
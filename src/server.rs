@@ -64,11 +64,13 @@ pub mod input_service {
     pub const NAME_WINDOW_FOCUS: &'static str = "";
 }
 
+mod audit_log;
 mod connection;
 pub mod display_service;
 #[cfg(windows)]
 pub mod portable_service;
 mod service;
+mod tunnel_acl;
 mod video_qos;
 pub mod video_service;
 
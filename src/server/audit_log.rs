@@ -0,0 +1,121 @@
+// Local audit log of incoming sessions.
+//
+// Enabled by the `audit-log` option, the path of a file which gets one JSON
+// object per line, appended with the time in milliseconds, the event name and
+// the ID of this device. When the file reaches `MAX_SIZE` it is renamed to
+// `<path>.1`, the older ones shift up to `<path>.<MAX_FILES>`, which is dropped.
+// The lines are written by a dedicated thread, so that the callers, mostly
+// async tasks, never wait for the disk.
+//
+// This is independent of the audit server, the same events are still posted
+// there when it is configured.
+
+use hbb_common::{
+    config::{keys, Config},
+    get_time, log,
+    serde_json::{json, Value},
+    ResultType,
+};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+const MAX_SIZE: u64 = 10 * 1024 * 1024;
+const MAX_FILES: usize = 5;
+
+lazy_static::lazy_static! {
+    // (path, line)
+    static ref WRITER: Mutex<mpsc::Sender<(String, String)>> = Mutex::new(start_writer());
+}
+
+/// Appends `event` with the fields of the `fields` object, if the audit log is
+/// enabled.
+pub fn write(event: &str, fields: Value) {
+    let path = Config::get_option(keys::OPTION_AUDIT_LOG);
+    if path.is_empty() {
+        return;
+    }
+    let mut v = json!({
+        "time": get_time(),
+        "event": event,
+        "id": Config::get_id(),
+    });
+    if let (Some(v), Value::Object(fields)) = (v.as_object_mut(), fields) {
+        v.extend(fields);
+    }
+    WRITER.lock().unwrap().send((path, v.to_string())).ok();
+}
+
+fn start_writer() -> mpsc::Sender<(String, String)> {
+    let (tx, rx) = mpsc::channel::<(String, String)>();
+    thread::spawn(move || {
+        for (path, line) in rx {
+            if let Err(err) = append(Path::new(&path), &line, MAX_SIZE) {
+                log::error!("Failed to write audit log {}: {}", path, err);
+            }
+        }
+    });
+    tx
+}
+
+fn append(path: &Path, line: &str, max_size: u64) -> ResultType<()> {
+    if let Ok(meta) = fs::metadata(path) {
+        if meta.len() > 0 && meta.len() + line.len() as u64 + 1 > max_size {
+            rotate(path)?;
+        }
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(format!("{}\n", line).as_bytes())?;
+    Ok(())
+}
+
+fn rotate(path: &Path) -> ResultType<()> {
+    let rotated = |n: usize| {
+        let mut s = path.as_os_str().to_owned();
+        s.push(format!(".{}", n));
+        PathBuf::from(s)
+    };
+    fs::remove_file(rotated(MAX_FILES)).ok();
+    for n in (1..MAX_FILES).rev() {
+        let from = rotated(n);
+        if from.exists() {
+            fs::rename(from, rotated(n + 1))?;
+        }
+    }
+    fs::rename(path, rotated(1))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_append_and_rotate() {
+        let dir = std::env::temp_dir().join(format!("audit-log-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.log");
+        let line = "x".repeat(9);
+        // 10 bytes a line, two lines fit.
+        for _ in 0..2 {
+            append(&path, &line, 20).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        assert!(!dir.join("audit.log.1").exists());
+        for _ in 0..2 * (MAX_FILES + 2) {
+            append(&path, &line, 20).unwrap();
+        }
+        for n in 1..=MAX_FILES {
+            let rotated = dir.join(format!("audit.log.{}", n));
+            assert_eq!(fs::read_to_string(rotated).unwrap().lines().count(), 2);
+        }
+        assert!(!dir.join(format!("audit.log.{}", MAX_FILES + 1)).exists());
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -36,7 +36,7 @@ use hbb_common::{
     sha2::{Digest, Sha256},
     sleep, timeout,
     tokio::{
-        net::TcpStream,
+        net::{TcpListener, TcpStream, UdpSocket},
         sync::mpsc,
         time::{self, Duration, Instant},
     },
@@ -185,6 +185,18 @@ pub enum AuthConnType {
     Terminal,
 }
 
+impl AuthConnType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Remote => "remote",
+            Self::FileTransfer => "file-transfer",
+            Self::PortForward => "port-forward",
+            Self::ViewCamera => "view-camera",
+            Self::Terminal => "terminal",
+        }
+    }
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 #[derive(Clone, Debug)]
 enum TerminalUserToken {
@@ -203,6 +215,18 @@ impl TerminalUserToken {
         }
     }
 }
+
+enum PortForwardSocket {
+    // Connected to the destination, for normal port forwarding.
+    Connected(Framed<TcpStream, BytesCodec>),
+    // Address to listen on once authorized, for reverse port forwarding.
+    Reverse(SocketAddr),
+    // Destination of UDP port forwarding.
+    Udp(SocketAddr),
+    // Destination of multiplexed port forwarding.
+    Mux(String),
+}
+
 pub struct Connection {
     inner: ConnInner,
     display_idx: usize,
@@ -215,7 +239,7 @@ pub struct Connection {
     file_transfer: Option<(String, bool)>,
     view_camera: bool,
     terminal: bool,
-    port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
+    port_forward_socket: Option<PortForwardSocket>,
     port_forward_address: String,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
@@ -334,6 +358,9 @@ impl Subscriber for ConnInner {
 const TEST_DELAY_TIMEOUT: Duration = Duration::from_secs(1);
 const SEC30: Duration = Duration::from_secs(30);
 const H1: Duration = Duration::from_secs(3600);
+const UDP_FLOW_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+// Each flow has its own socket and task, so the flows of a session are limited.
+const UDP_MAX_FLOWS: usize = 256;
 const MILLI1: Duration = Duration::from_millis(1);
 const SEND_TIMEOUT_VIDEO: u64 = 12_000;
 const SEND_TIMEOUT_OTHER: u64 = SEND_TIMEOUT_VIDEO * 10;
@@ -1065,7 +1092,20 @@ impl Connection {
         rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
     ) -> ResultType<()> {
         let mut last_recv_time = Instant::now();
-        if let Some(mut forward) = self.port_forward_socket.take() {
+        let forward = match self.port_forward_socket.take() {
+            Some(PortForwardSocket::Udp(target)) => {
+                return self.run_udp_port_forward(target, rx_from_cm).await;
+            }
+            Some(PortForwardSocket::Mux(target)) => {
+                return self.run_mux_port_forward(Some(target), None, rx_from_cm).await;
+            }
+            Some(PortForwardSocket::Reverse(addr)) => {
+                return self.run_reverse_port_forward(addr, rx_from_cm).await;
+            }
+            Some(PortForwardSocket::Connected(forward)) => Some(forward),
+            None => None,
+        };
+        if let Some(mut forward) = forward {
             log::info!("Running port forwarding loop");
             self.stream.set_raw();
             let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
@@ -1116,6 +1156,272 @@ impl Connection {
         Ok(())
     }
 
+    async fn run_reverse_port_forward(
+        &mut self,
+        addr: SocketAddr,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        if !self.authorized {
+            return Ok(());
+        }
+        let listener = match new_listener(addr, false).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                self.send_close_reason_no_retry(&format!("Failed to listen on remote {}", addr))
+                    .await;
+                bail!("Failed to listen on {}: {}", addr, err);
+            }
+        };
+        log::info!("Reverse port forwarding, listening on {}", addr);
+        self.run_mux_port_forward(None, Some(listener), rx_from_cm)
+            .await
+    }
+
+    async fn run_udp_port_forward(
+        &mut self,
+        target: SocketAddr,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        log::info!("Running UDP port forwarding loop to {}", target);
+        let mut flows = HashMap::new();
+        let res = self
+            .udp_port_forward_loop(target, rx_from_cm, &mut flows)
+            .await;
+        for (_, (_, _, task)) in flows.drain() {
+            task.abort();
+        }
+        res
+    }
+
+    async fn udp_port_forward_loop(
+        &mut self,
+        target: SocketAddr,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+        flows: &mut HashMap<u32, (Arc<UdpSocket>, Instant, tokio::task::JoinHandle<()>)>,
+    ) -> ResultType<()> {
+        let bind_addr = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let mut last_recv_time = Instant::now();
+        let (tx_datagram, mut rx_datagram) = mpsc::unbounded_channel::<(u32, Vec<u8>)>();
+        let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        loop {
+            tokio::select! {
+                Some(data) = rx_from_cm.recv() => {
+                    match data {
+                        ipc::Data::Close => {
+                            bail!("Close requested from connection manager");
+                        }
+                        ipc::Data::CmErr(e) => {
+                            log::error!("Connection manager error: {e}");
+                            bail!("{e}");
+                        }
+                        _ => {}
+                    }
+                }
+                res = self.stream.next() => {
+                    let Some(bytes) = res else {
+                        bail!("Stream reset by the peer");
+                    };
+                    last_recv_time = Instant::now();
+                    let Ok(msg) = Message::parse_from_bytes(&bytes?) else {
+                        continue;
+                    };
+                    let Some(message::Union::PortForwardDatagram(d)) = msg.union else {
+                        self.reply_port_forward_probe(msg).await?;
+                        continue;
+                    };
+                    if !flows.contains_key(&d.flow_id) {
+                        if flows.len() >= UDP_MAX_FLOWS {
+                            // Make room by dropping the least recently active flow.
+                            if let Some(oldest) = flows
+                                .iter()
+                                .min_by_key(|(_, (_, last_active, _))| *last_active)
+                                .map(|(flow_id, _)| *flow_id)
+                            {
+                                if let Some((_, _, task)) = flows.remove(&oldest) {
+                                    log::debug!("UDP flow {} evicted", oldest);
+                                    task.abort();
+                                }
+                            }
+                        }
+                        let socket = UdpSocket::bind(bind_addr).await?;
+                        socket.connect(target).await?;
+                        let socket = Arc::new(socket);
+                        let task = tokio::spawn(Self::udp_flow_recv_loop(
+                            d.flow_id,
+                            socket.clone(),
+                            tx_datagram.clone(),
+                        ));
+                        log::debug!("New UDP flow {} to {}", d.flow_id, target);
+                        flows.insert(d.flow_id, (socket, Instant::now(), task));
+                    }
+                    if let Some((socket, last_active, _)) = flows.get_mut(&d.flow_id) {
+                        *last_active = Instant::now();
+                        allow_err!(socket.send(&d.data).await);
+                    }
+                }
+                Some((flow_id, data)) = rx_datagram.recv() => {
+                    if let Some((_, last_active, _)) = flows.get_mut(&flow_id) {
+                        *last_active = Instant::now();
+                    }
+                    let mut msg_out = Message::new();
+                    msg_out.set_port_forward_datagram(PortForwardDatagram {
+                        flow_id,
+                        data: data.into(),
+                        ..Default::default()
+                    });
+                    self.stream.send(&msg_out).await?;
+                }
+                _ = self.timer.tick() => {
+                    flows.retain(|flow_id, (_, last_active, task)| {
+                        if last_active.elapsed() >= UDP_FLOW_IDLE_TIMEOUT {
+                            log::debug!("UDP flow {} idle timeout", flow_id);
+                            task.abort();
+                            false
+                        } else {
+                            true
+                        }
+                    });
+                    if last_recv_time.elapsed() >= H1 {
+                        bail!("Timeout");
+                    }
+                }
+                Ok(conns) = hbbs_rx.recv() => {
+                    if conns.contains(&self.inner.id) {
+                        bail!("Closed manually by the web console");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Carry the connections of a mux session, either opened by the controlling
+    /// side to `target`, or accepted by `listener` for reverse port forwarding.
+    async fn run_mux_port_forward(
+        &mut self,
+        target: Option<String>,
+        listener: Option<TcpListener>,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        if let Some(target) = target.as_ref() {
+            log::info!("Running mux port forwarding loop to {}", target);
+        }
+        let mut last_recv_time = Instant::now();
+        let mut mux = crate::port_forward_mux::Mux::new();
+        let mut next_stream_id: u32 = 0;
+        let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        loop {
+            tokio::select! {
+                Some(data) = rx_from_cm.recv() => {
+                    match data {
+                        ipc::Data::Close => {
+                            bail!("Close requested from connection manager");
+                        }
+                        ipc::Data::CmErr(e) => {
+                            log::error!("Connection manager error: {e}");
+                            bail!("{e}");
+                        }
+                        _ => {}
+                    }
+                }
+                res = async {
+                    match listener.as_ref() {
+                        Some(listener) => listener.accept().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    let (sock, addr) = res?;
+                    if mux.is_full() {
+                        log::warn!("Reverse port forwarding, too many streams, drop {}", addr);
+                        continue;
+                    }
+                    next_stream_id = next_stream_id.wrapping_add(1);
+                    log::info!("Reverse port forwarding, new stream {} from {}", next_stream_id, addr);
+                    mux.add(next_stream_id, crate::port_forward_mux::Target::Socket(sock));
+                    let mut frame = crate::port_forward_mux::open_frame(next_stream_id);
+                    frame.addr = addr.to_string();
+                    let mut msg_out = Message::new();
+                    msg_out.set_port_forward_mux(frame);
+                    self.stream.send(&msg_out).await?;
+                }
+                res = self.stream.next() => {
+                    let Some(bytes) = res else {
+                        bail!("Stream reset by the peer");
+                    };
+                    last_recv_time = Instant::now();
+                    let Ok(msg) = Message::parse_from_bytes(&bytes?) else {
+                        continue;
+                    };
+                    let Some(message::Union::PortForwardMux(frame)) = msg.union else {
+                        self.reply_port_forward_probe(msg).await?;
+                        continue;
+                    };
+                    if let Some(stream_id) = mux.handle_frame(frame) {
+                        match target.as_ref() {
+                            Some(target) => {
+                                log::debug!("New mux stream {} to {}", stream_id, target);
+                                mux.add(
+                                    stream_id,
+                                    crate::port_forward_mux::Target::Connect(target.clone()),
+                                );
+                            }
+                            // Only the listening side opens streams in reverse mode.
+                            None => mux.reset(stream_id),
+                        }
+                    }
+                }
+                Some(frame) = mux.next_frame() => {
+                    last_recv_time = Instant::now();
+                    let mut msg_out = Message::new();
+                    msg_out.set_port_forward_mux(frame);
+                    self.stream.send(&msg_out).await?;
+                }
+                _ = self.timer.tick() => {
+                    if last_recv_time.elapsed() >= H1 {
+                        bail!("Timeout");
+                    }
+                }
+                Ok(conns) = hbbs_rx.recv() => {
+                    if conns.contains(&self.inner.id) {
+                        bail!("Closed manually by the web console");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Echo the keepalive probes of the controlling side, see `Watchdog` in port_forward.rs.
+    async fn reply_port_forward_probe(&mut self, msg: Message) -> ResultType<()> {
+        if let Some(message::Union::TestDelay(t)) = msg.union {
+            if t.from_client {
+                let mut msg_out = Message::new();
+                msg_out.set_test_delay(t);
+                self.stream.send(&msg_out).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn udp_flow_recv_loop(
+        flow_id: u32,
+        socket: Arc<UdpSocket>,
+        tx: mpsc::UnboundedSender<(u32, Vec<u8>)>,
+    ) {
+        let mut buf = vec![0u8; 65536];
+        loop {
+            match socket.recv(&mut buf).await {
+                Ok(n) => {
+                    if tx.send((flow_id, buf[..n].to_vec())).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    log::debug!("UDP flow {} recv error: {}", flow_id, err);
+                    break;
+                }
+            }
+        }
+    }
+
     async fn send_permission(&mut self, permission: Permission, enabled: bool) {
         let mut misc = Misc::new();
         misc.set_permission_info(PermissionInfo {
@@ -1138,6 +1444,20 @@ impl Connection {
         }
     }
 
+    async fn reject_port_forward_target(&mut self, addr: &str, peer_id: &str) {
+        self.send_login_error(format!("Access to remote {} is not allowed", addr))
+            .await;
+        Self::post_alarm_audit(
+            AlarmAuditType::TunnelDestination,
+            json!({
+                "ip": self.ip,
+                "peer_id": peer_id,
+                "target": addr,
+            }),
+        );
+        sleep(1.).await;
+    }
+
     async fn check_whitelist(&mut self, addr: &SocketAddr) -> bool {
         let whitelist: Vec<String> = Config::get_option("whitelist")
             .split(",")
@@ -1188,6 +1508,7 @@ impl Connection {
             "ip": addr.ip(),
             "action": "new",
         }));
+        self.write_audit_log("connect", json!({}));
         true
     }
 
@@ -1217,6 +1538,17 @@ impl Connection {
         allow_err!(self.tx_post_seq.send((url, v)));
     }
 
+    fn write_audit_log(&self, event: &str, v: Value) {
+        let mut v = v;
+        v["conn_id"] = json!(self.inner.id);
+        v["ip"] = json!(self.ip);
+        if !self.lr.my_id.is_empty() {
+            v["peer_id"] = json!(self.lr.my_id);
+            v["peer_name"] = json!(self.lr.my_name);
+        }
+        super::audit_log::write(event, v);
+    }
+
     fn get_files_for_audit(job_type: fs::JobType, mut files: Vec<FileEntry>) -> Vec<(String, i64)> {
         files
             .drain(..)
@@ -1240,14 +1572,26 @@ impl Connection {
         files: Vec<(String, i64)>,
         info: Value,
     ) {
-        if self.server_audit_file.is_empty() {
-            return;
-        }
-        let url = self.server_audit_file.clone();
         let file_num = files.len();
         let mut files = files;
         files.sort_by(|a, b| b.1.cmp(&a.1));
         files.truncate(10);
+        self.write_audit_log(
+            "file_transfer",
+            json!({
+                "direction": match r#type {
+                    FileAuditType::RemoteSend => "send",
+                    FileAuditType::RemoteReceive => "receive",
+                },
+                "path": path,
+                "num": file_num,
+                "files": files,
+            }),
+        );
+        if self.server_audit_file.is_empty() {
+            return;
+        }
+        let url = self.server_audit_file.clone();
         let is_file = files.len() == 1 && files[0].0.is_empty();
         let mut info = info;
         info["ip"] = json!(self.ip.clone());
@@ -1269,6 +1613,7 @@ impl Connection {
     }
 
     pub fn post_alarm_audit(typ: AlarmAuditType, info: Value) {
+        super::audit_log::write("alarm", json!({ "type": typ.as_str(), "info": info }));
         let url = crate::get_audit_server(
             Config::get_option("api-server"),
             Config::get_option("custom-rendezvous-server"),
@@ -1354,6 +1699,11 @@ impl Connection {
         self.post_conn_audit(
             json!({"peer": ((&self.lr.my_id, &self.lr.my_name)), "type": conn_type}),
         );
+        let mut v = json!({ "conn_type": auth_conn_type.as_str() });
+        if !self.port_forward_address.is_empty() {
+            v["port_forward"] = json!(self.port_forward_address);
+        }
+        self.write_audit_log("authorize", v);
         #[allow(unused_mut)]
         let mut username = crate::platform::get_active_username();
         let mut res = LoginResponse::new();
@@ -1760,6 +2110,7 @@ impl Connection {
             port_forward: self.port_forward_address.clone(),
             peer_id,
             name,
+            ip: self.ip.clone(),
             authorized,
             keyboard: self.keyboard,
             clipboard: self.clipboard,
@@ -1858,8 +2209,16 @@ impl Connection {
         let mut hasher = Sha256::new();
         hasher.update(password);
         hasher.update(&self.hash.salt);
+        self.validate_password_hash(&hasher.finalize()[..])
+    }
+
+    /// `password_hash` is SHA256(password + salt).
+    fn validate_password_hash(&self, password_hash: &[u8]) -> bool {
+        if password_hash.is_empty() {
+            return false;
+        }
         let mut hasher2 = Sha256::new();
-        hasher2.update(&hasher.finalize()[..]);
+        hasher2.update(password_hash);
         hasher2.update(&self.hash.challenge);
         hasher2.finalize()[..] == self.lr.password[..]
     }
@@ -1877,7 +2236,7 @@ impl Connection {
             }
         }
         if password::permanent_enabled() {
-            if self.validate_one_password(Config::get_permanent_password()) {
+            if self.validate_password_hash(&Config::get_permanent_password_hash()) {
                 return true;
             }
         }
@@ -1965,18 +2324,37 @@ impl Connection {
             }
         }
         self.video_ack_required = lr.video_ack_required;
-        
-        // Handle RDP credentials for headless mode
-        #[cfg(windows)]
-        {
-            if !lr.rdp_username.is_empty() && !lr.rdp_password.is_empty() {
+    }
+
+    /// Opens the RDP credentials for headless mode, sealed by the client to the
+    /// password which has just passed `validate_password`.
+    #[cfg(windows)]
+    fn open_rdp_credentials(&self) {
+        if self.lr.rdp_credentials.is_empty() {
+            return;
+        }
+        let mut password_hashes = Vec::new();
+        if password::temporary_enabled() {
+            let mut hasher = Sha256::new();
+            hasher.update(password::temporary_password());
+            hasher.update(&self.hash.salt);
+            password_hashes.push(hasher.finalize()[..].to_vec());
+        }
+        if password::permanent_enabled() {
+            password_hashes.push(Config::get_permanent_password_hash());
+        }
+        for password_hash in password_hashes {
+            if let Some((username, password)) = crate::common::open_rdp_credentials(
+                &password_hash,
+                &self.hash.challenge,
+                &self.lr.rdp_credentials,
+            ) {
                 log::info!("RDP credentials received from client for headless mode");
-                crate::rdp_session::set_rdp_credentials(
-                    lr.rdp_username.clone(),
-                    lr.rdp_password.clone(),
-                );
+                crate::rdp_session::set_rdp_credentials(username, password);
+                return;
             }
         }
+        log::warn!("Failed to open the RDP credentials of the client");
     }
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -2102,31 +2480,115 @@ impl Connection {
                         sleep(1.).await;
                         return false;
                     }
-                    let mut is_rdp = false;
-                    if pf.host == "RDP" && pf.port == 0 {
-                        pf.host = "localhost".to_owned();
-                        pf.port = 3389;
-                        is_rdp = true;
-                    }
-                    if pf.host.is_empty() {
-                        pf.host = "localhost".to_owned();
-                    }
-                    let mut addr = format!("{}:{}", pf.host, pf.port);
-                    self.port_forward_address = addr.clone();
-                    match timeout(3000, TcpStream::connect(&addr)).await {
-                        Ok(Ok(sock)) => {
-                            self.port_forward_socket = Some(Framed::new(sock, BytesCodec::new()));
+                    if pf.reverse {
+                        if pf.host.is_empty() || pf.host == "localhost" {
+                            pf.host = "127.0.0.1".to_owned();
                         }
-                        _ => {
-                            if is_rdp {
-                                addr = "RDP".to_owned();
+                        let addr = format!("{}:{}", pf.host, pf.port);
+                        self.port_forward_address = addr.clone();
+                        // Listens only once authorized, see `run_reverse_port_forward`.
+                        match super::tunnel_acl::resolve_reverse_bind(&pf.host, pf.port).await {
+                            Ok(bind_addr) => {
+                                self.port_forward_socket = Some(PortForwardSocket::Reverse(bind_addr));
+                            }
+                            Err(err) => {
+                                log::warn!("Reject reverse port forward on {}: {}", addr, err);
+                                self.reject_port_forward_target(&addr, &lr.my_id).await;
+                                return false;
+                            }
+                        }
+                    } else if pf.mux {
+                        if pf.host.is_empty() {
+                            pf.host = "localhost".to_owned();
+                        }
+                        let addr = format!("{}:{}", pf.host, pf.port);
+                        self.port_forward_address = addr.clone();
+                        let mut target = addr.clone();
+                        if super::tunnel_acl::is_enabled() {
+                            match super::tunnel_acl::resolve_allowed(&pf.host, pf.port).await {
+                                Ok(sock_addr) => target = sock_addr.to_string(),
+                                Err(err) => {
+                                    log::warn!("Reject mux port forward to {}: {}", addr, err);
+                                    self.reject_port_forward_target(&addr, &lr.my_id).await;
+                                    return false;
+                                }
+                            }
+                        }
+                        self.port_forward_socket = Some(PortForwardSocket::Mux(target));
+                    } else if pf.udp {
+                        if pf.host.is_empty() {
+                            pf.host = "localhost".to_owned();
+                        }
+                        let addr = format!("{}:{}", pf.host, pf.port);
+                        self.port_forward_address = format!("udp/{}", addr);
+                        let target = if super::tunnel_acl::is_enabled() {
+                            match super::tunnel_acl::resolve_allowed(&pf.host, pf.port).await {
+                                Ok(sock_addr) => Some(sock_addr),
+                                Err(err) => {
+                                    log::warn!("Reject UDP port forward to {}: {}", addr, err);
+                                    self.reject_port_forward_target(&addr, &lr.my_id).await;
+                                    return false;
+                                }
+                            }
+                        } else {
+                            tokio::net::lookup_host(addr.as_str())
+                                .await
+                                .ok()
+                                .and_then(|mut x| x.next())
+                        };
+                        match target {
+                            Some(target) => {
+                                self.port_forward_socket = Some(PortForwardSocket::Udp(target));
+                            }
+                            None => {
+                                self.send_login_error(format!(
+                                    "Failed to resolve remote {}",
+                                    addr
+                                ))
+                                .await;
+                                return false;
+                            }
+                        }
+                    } else {
+                        let mut is_rdp = false;
+                        if pf.host == "RDP" && pf.port == 0 {
+                            pf.host = "localhost".to_owned();
+                            pf.port = 3389;
+                            is_rdp = true;
+                        }
+                        if pf.host.is_empty() {
+                            pf.host = "localhost".to_owned();
+                        }
+                        let mut addr = format!("{}:{}", pf.host, pf.port);
+                        self.port_forward_address = addr.clone();
+                        let mut target = addr.clone();
+                        if super::tunnel_acl::is_enabled() {
+                            match super::tunnel_acl::resolve_allowed(&pf.host, pf.port).await {
+                                Ok(sock_addr) => target = sock_addr.to_string(),
+                                Err(err) => {
+                                    log::warn!("Reject port forward to {}: {}", addr, err);
+                                    self.reject_port_forward_target(&addr, &lr.my_id).await;
+                                    return false;
+                                }
+                            }
+                        }
+                        match timeout(3000, TcpStream::connect(&target)).await {
+                            Ok(Ok(sock)) => {
+                                self.port_forward_socket = Some(PortForwardSocket::Connected(
+                                    Framed::new(sock, BytesCodec::new()),
+                                ));
+                            }
+                            _ => {
+                                if is_rdp {
+                                    addr = "RDP".to_owned();
+                                }
+                                self.send_login_error(format!(
+                                    "Failed to access remote {}, please make sure if it is open",
+                                    addr
+                                ))
+                                .await;
+                                return false;
                             }
-                            self.send_login_error(format!(
-                                "Failed to access remote {}, please make sure if it is open",
-                                addr
-                            ))
-                            .await;
-                            return false;
                         }
                     }
                 }
@@ -2220,6 +2682,7 @@ impl Connection {
                 }
                 if !self.validate_password() {
                     self.update_failure(failure, false, 0);
+                    self.write_audit_log("login_failed", json!({ "reason": "password" }));
                     if err_msg.is_empty() {
                         self.send_login_error(crate::client::LOGIN_MSG_PASSWORD_WRONG)
                             .await;
@@ -2232,6 +2695,8 @@ impl Connection {
                     }
                 } else {
                     self.update_failure(failure, true, 0);
+                    #[cfg(windows)]
+                    self.open_rdp_credentials();
                     if err_msg.is_empty() {
                         #[cfg(target_os = "linux")]
                         self.linux_headless_handle.wait_desktop_cm_ready().await;
@@ -2270,6 +2735,7 @@ impl Connection {
                         }
                     } else {
                         self.update_failure(failure, false, 1);
+                        self.write_audit_log("login_failed", json!({ "reason": "2fa" }));
                         self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
                             .await;
                     }
@@ -2784,6 +3250,10 @@ impl Connection {
                                 self.file_transferred = true;
                             }
                             Some(file_action::Union::RemoveDir(d)) => {
+                                self.write_audit_log(
+                                    "file_action",
+                                    json!({ "action": "remove_dir", "path": d.path }),
+                                );
                                 self.send_fs(ipc::FS::RemoveDir {
                                     path: d.path.clone(),
                                     id: d.id,
@@ -2792,6 +3262,10 @@ impl Connection {
                                 self.file_remove_log_control.on_remove_dir(d);
                             }
                             Some(file_action::Union::RemoveFile(f)) => {
+                                self.write_audit_log(
+                                    "file_action",
+                                    json!({ "action": "remove_file", "path": f.path }),
+                                );
                                 self.send_fs(ipc::FS::RemoveFile {
                                     path: f.path.clone(),
                                     id: f.id,
@@ -2800,6 +3274,10 @@ impl Connection {
                                 self.file_remove_log_control.on_remove_file(f);
                             }
                             Some(file_action::Union::Create(c)) => {
+                                self.write_audit_log(
+                                    "file_action",
+                                    json!({ "action": "create_dir", "path": c.path }),
+                                );
                                 self.send_fs(ipc::FS::CreateDir {
                                     path: c.path.clone(),
                                     id: c.id,
@@ -2834,6 +3312,14 @@ impl Connection {
                                 }
                             }
                             Some(file_action::Union::Rename(r)) => {
+                                self.write_audit_log(
+                                    "file_action",
+                                    json!({
+                                        "action": "rename",
+                                        "path": r.path,
+                                        "new_name": r.new_name,
+                                    }),
+                                );
                                 self.send_fs(ipc::FS::Rename {
                                     id: r.id,
                                     path: r.path.clone(),
@@ -3995,6 +4481,9 @@ impl Connection {
         // But it's not necessary now and we have to consider two audio services(client, server).
         crate::audio_service::set_voice_call_input_device(None, true);
         log::info!("#{} Connection closed: {}", self.inner.id(), reason);
+        if !self.ip.is_empty() {
+            self.write_audit_log("disconnect", json!({ "reason": reason }));
+        }
         if lock && self.lock_after_session_end && self.keyboard {
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             lock_screen().await;
@@ -4297,6 +4786,16 @@ impl Connection {
             // unreacheable, but keep it for safety
             bail!("Terminal user token is not set.");
         };
+        if let Some(terminal_action::Union::Open(open)) = &action.union {
+            self.write_audit_log(
+                "terminal_open",
+                json!({
+                    "terminal_id": open.terminal_id,
+                    "service_id": self.terminal_service_id,
+                    "command": open.command,
+                }),
+            );
+        }
         let mut proxy = terminal_service::TerminalServiceProxy::new(
             self.terminal_service_id.clone(),
             Some(self.terminal_persistent),
@@ -4496,6 +4995,19 @@ pub enum AlarmAuditType {
     // MultipleLoginsAttemptsWithinOneMinute = 4,
     // MultipleLoginsAttemptsWithinOneHour = 5,
     ExceedIPv6PrefixAttempts = 6,
+    TunnelDestination = 7,
+}
+
+impl AlarmAuditType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::IpWhitelist => "ip_whitelist",
+            Self::ExceedThirtyAttempts => "exceed_thirty_attempts",
+            Self::SixAttemptsWithinOneMinute => "six_attempts_within_one_minute",
+            Self::ExceedIPv6PrefixAttempts => "exceed_ipv6_prefix_attempts",
+            Self::TunnelDestination => "tunnel_destination",
+        }
+    }
 }
 
 pub enum FileAuditType {
@@ -446,6 +446,8 @@ pub struct TerminalSession {
     input_tx: Option<SyncSender<Vec<u8>>>,
     // Channel for receiving output from the reader thread
     output_rx: Option<Receiver<Vec<u8>>>,
+    // Set if the session runs a command instead of a shell
+    exec: Option<ExecProcess>,
     exiting: Arc<AtomicBool>,
     // Thread handles
     reader_thread: Option<thread::JoinHandle<()>>,
@@ -469,6 +471,7 @@ impl TerminalSession {
             child: None,
             input_tx: None,
             output_rx: None,
+            exec: None,
             exiting: Arc::new(AtomicBool::new(false)),
             reader_thread: None,
             writer_thread: None,
@@ -492,8 +495,17 @@ impl TerminalSession {
         self.is_opened = false;
         self.exiting.store(true, Ordering::SeqCst);
 
+        // The reader thread of a command only exits after the process has exited.
+        // Dropping `stderr_rx` also unblocks the stderr reader.
+        let is_exec = if let Some(exec) = self.exec.take() {
+            let _ = exec.child.lock().unwrap().kill();
+            true
+        } else {
+            false
+        };
+
         // Drop the input channel to signal writer thread to exit
-        if let Some(input_tx) = self.input_tx.take() {
+        if let Some(input_tx) = self.input_tx.take().filter(|_| !is_exec) {
             // Send a final newline to ensure the reader can read some data, and then exit.
             // This is required on Windows and Linux.
             // Although `self.pty_pair = None;` is called below, we can still send a final newline here.
@@ -541,6 +553,48 @@ impl TerminalSession {
     }
 }
 
+/// A command run with pipes instead of a PTY, see `OpenTerminal.command`.
+struct ExecProcess {
+    child: Arc<Mutex<std::process::Child>>,
+    stderr_rx: Receiver<Vec<u8>>,
+    // Set by the reader thread once the process has exited
+    exit_code: Arc<Mutex<Option<i32>>>,
+}
+
+fn exec_exit_code(status: std::process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        // Same convention as the shells, 128 + signal number.
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(-1)
+}
+
+/// Read from a pipe of a command until EOF.
+fn read_pipe(terminal_id: i32, mut reader: impl Read, output_tx: SyncSender<Vec<u8>>) {
+    let mut buf = vec![0u8; 4096];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                // Block instead of dropping data, the output of a command is not redrawn.
+                if output_tx.send(buf[..n].to_vec()).is_err() {
+                    log::debug!("Terminal {} output channel disconnected", terminal_id);
+                    break;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                log::error!("Terminal {} read error: {}", terminal_id, e);
+                break;
+            }
+        }
+    }
+}
+
 impl Drop for TerminalSession {
     fn drop(&mut self) {
         // Ensure child process is properly handled when session is dropped
@@ -711,6 +765,10 @@ impl TerminalServiceProxy {
     ) -> Result<Option<TerminalResponse>> {
         let mut response = TerminalResponse::new();
 
+        if !open.command.is_empty() {
+            return self.handle_exec(service, open);
+        }
+
         // Check if terminal already exists
         if let Some(session_arc) = service.sessions.get(&open.terminal_id) {
             // Reconnect to existing terminal
@@ -915,6 +973,137 @@ impl TerminalServiceProxy {
         Ok(Some(response))
     }
 
+    fn handle_exec(
+        &self,
+        service: &mut PersistentTerminalService,
+        open: &OpenTerminal,
+    ) -> Result<Option<TerminalResponse>> {
+        let mut response = TerminalResponse::new();
+        let mut opened = TerminalOpened::new();
+        opened.terminal_id = open.terminal_id;
+        opened.service_id = service.service_id.clone();
+
+        if service.sessions.contains_key(&open.terminal_id) {
+            opened.message = format!("Terminal {} already exists", open.terminal_id);
+            response.set_opened(opened);
+            return Ok(Some(response));
+        }
+        // `std::process` can't start a process with the token of another user,
+        // which an installed service would need. `sdfdesk exec` documents this.
+        #[cfg(target_os = "windows")]
+        if self.user_token.is_some() {
+            opened.message = "Running a command is not supported by a Windows peer installed as a service, use the terminal instead".to_string();
+            response.set_opened(opened);
+            return Ok(Some(response));
+        }
+
+        log::info!(
+            "Running command in terminal {} for service: {}",
+            open.terminal_id,
+            service.service_id
+        );
+        #[cfg(target_os = "windows")]
+        let mut cmd = {
+            use std::os::windows::process::CommandExt;
+            let mut cmd = std::process::Command::new("cmd.exe");
+            // `cmd` has its own quoting rules, pass the command as is.
+            cmd.arg("/C")
+                .raw_arg(&open.command)
+                .creation_flags(winapi::um::winbase::CREATE_NO_WINDOW);
+            cmd
+        };
+        #[cfg(not(target_os = "windows"))]
+        let mut cmd = {
+            let mut cmd = std::process::Command::new("/bin/sh");
+            cmd.arg("-c").arg(&open.command);
+            cmd
+        };
+        let mut child = cmd
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .context("Failed to spawn command")?;
+        let (Some(mut stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            let _ = child.kill();
+            return Err(anyhow!("Failed to get the pipes of the command"));
+        };
+
+        let mut session =
+            TerminalSession::new(open.terminal_id, open.rows as u16, open.cols as u16);
+        session.pid = child.id();
+        let child = Arc::new(Mutex::new(child));
+        let exit_code = Arc::new(Mutex::new(None));
+
+        let (input_tx, input_rx) = mpsc::sync_channel::<Vec<u8>>(CHANNEL_BUFFER_SIZE);
+        let (output_tx, output_rx) = mpsc::sync_channel::<Vec<u8>>(CHANNEL_BUFFER_SIZE);
+        let (stderr_tx, stderr_rx) = mpsc::sync_channel::<Vec<u8>>(CHANNEL_BUFFER_SIZE);
+
+        let terminal_id = open.terminal_id;
+        let writer_thread = thread::spawn(move || {
+            // Dropping `stdin` on return closes the pipe.
+            while let Ok(data) = input_rx.recv() {
+                if data.is_empty() {
+                    break;
+                }
+                if let Err(e) = stdin.write_all(&data).and_then(|_| stdin.flush()) {
+                    log::error!("Terminal {} write error: {}", terminal_id, e);
+                    break;
+                }
+            }
+            log::debug!("Terminal {} writer thread exiting", terminal_id);
+        });
+
+        let stderr_thread = thread::spawn(move || read_pipe(terminal_id, stderr, stderr_tx));
+        let reader_child = child.clone();
+        let reader_exit_code = exit_code.clone();
+        // `read_outputs` sends the closed message when this thread finishes,
+        // so it only returns after all output is read and the exit code is known.
+        let reader_thread = thread::spawn(move || {
+            read_pipe(terminal_id, stdout, output_tx);
+            let _ = stderr_thread.join();
+            loop {
+                match reader_child.lock().unwrap().try_wait() {
+                    Ok(Some(status)) => {
+                        *reader_exit_code.lock().unwrap() = Some(exec_exit_code(status));
+                        break;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        log::error!("Terminal {} wait error: {}", terminal_id, e);
+                        break;
+                    }
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            log::debug!("Terminal {} reader thread exiting", terminal_id);
+        });
+
+        session.exec = Some(ExecProcess {
+            child,
+            stderr_rx,
+            exit_code,
+        });
+        session.input_tx = Some(input_tx);
+        session.output_rx = Some(output_rx);
+        session.reader_thread = Some(reader_thread);
+        session.writer_thread = Some(writer_thread);
+        session.is_opened = true;
+
+        opened.success = true;
+        opened.message = "Command started".to_string();
+        opened.pid = session.pid;
+        response.set_opened(opened);
+
+        service
+            .sessions
+            .insert(open.terminal_id, Arc::new(Mutex::new(session)));
+
+        Ok(Some(response))
+    }
+
     fn handle_resize(
         &self,
         session: Option<Arc<Mutex<TerminalSession>>>,
@@ -975,6 +1164,9 @@ impl TerminalServiceProxy {
                 child.kill()?;
                 add_to_reaper(child);
                 -1 // -1 indicates forced termination
+            } else if let Some(exec) = &session.exec {
+                exec.child.lock().unwrap().kill()?;
+                -1
             } else {
                 0
             };
@@ -1027,7 +1219,7 @@ impl TerminalServiceProxy {
                 // Because the `reader_thread` is joined in `stop()`,
                 // and `stop()` is called before the session is dropped.
                 if should_send_closed {
-                    closed_terminals.push(terminal_id);
+                    closed_terminals.push((terminal_id, session.exec.is_some()));
                 }
 
                 if !session.is_opened {
@@ -1051,11 +1243,23 @@ impl TerminalServiceProxy {
                     session.output_buffer.append(data);
                 }
 
+                let mut received_data: Vec<_> = received_data
+                    .into_iter()
+                    .map(|data| (data, false))
+                    .collect();
+                if let Some(exec) = &session.exec {
+                    while let Ok(data) = exec.stderr_rx.try_recv() {
+                        has_activity = true;
+                        received_data.push((data, true));
+                    }
+                }
+
                 // Process received data for responses
-                for data in received_data {
+                for (data, is_stderr) in received_data {
                     let mut response = TerminalResponse::new();
                     let mut terminal_data = TerminalData::new();
                     terminal_data.terminal_id = terminal_id;
+                    terminal_data.is_stderr = is_stderr;
 
                     // Compress data if it exceeds threshold
                     if data.len() > COMPRESS_THRESHOLD {
@@ -1084,10 +1288,11 @@ impl TerminalServiceProxy {
         // Clean up closed terminals (requires service lock briefly)
         if !closed_terminals.is_empty() {
             let mut sessions = service.lock().unwrap().sessions.clone();
-            for terminal_id in closed_terminals {
+            for (terminal_id, is_exec) in closed_terminals {
                 let mut exit_code = 0;
 
-                if !self.is_persistent {
+                // A command has nothing to reattach to, so it is removed like in a non-persistent service.
+                if !self.is_persistent || is_exec {
                     if let Some(session_arc) = sessions.remove(&terminal_id) {
                         service.lock().unwrap().sessions.remove(&terminal_id);
                         let mut session = session_arc.lock().unwrap();
@@ -1099,6 +1304,9 @@ impl TerminalServiceProxy {
                             }
                             add_to_reaper(child);
                         }
+                        if let Some(exec) = &session.exec {
+                            exit_code = exec.exit_code.lock().unwrap().unwrap_or(-1);
+                        }
                     }
                 } else {
                     // For persistent sessions, just clear the child reference
@@ -1131,6 +1339,13 @@ impl TerminalServiceProxy {
         if !self.is_persistent {
             // Remove non-persistent service
             remove_service(&self.service_id);
+        } else if let Some(service) = get_service(&self.service_id) {
+            // Commands are not kept, no one is waiting for their output any more.
+            service
+                .lock()
+                .unwrap()
+                .sessions
+                .retain(|_, session| session.lock().unwrap().exec.is_none());
         }
     }
 }
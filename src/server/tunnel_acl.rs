@@ -0,0 +1,222 @@
+// Destination filtering for port forwarding and tunnels.
+//
+// Both lists are comma separated entries of `HOST[:PORT[-PORT]]`, where `HOST` is
+// `*`, an IP, a CIDR (`192.168.1.0/24`), `[IPv6]`, `[IPv6/len]` or a host name.
+// A destination is rejected if it matches the denylist, or if the allowlist is
+// not empty and it matches none of its entries. Invalid entries are ignored,
+// but an allowlist made only of invalid entries permits nothing.
+
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{
+    bail,
+    config::{keys, Config},
+    log, tokio, ResultType,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+#[derive(Debug)]
+enum Host {
+    Any,
+    Cidr(IpCidr),
+    Name(String),
+}
+
+#[derive(Debug)]
+struct Rule {
+    host: Host,
+    ports: Option<(u16, u16)>,
+}
+
+impl Rule {
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return None;
+        }
+        let (host, ports) = if let Some(rest) = s.strip_prefix('[') {
+            let end = rest.find(']')?;
+            let ports = rest[end + 1..].strip_prefix(':');
+            (&rest[..end], ports)
+        } else if s.matches(':').count() == 1 {
+            let (host, ports) = s.split_once(':')?;
+            (host, Some(ports))
+        } else {
+            (s, None)
+        };
+        let ports = match ports {
+            Some(ports) => Some(Self::parse_ports(ports)?),
+            None => None,
+        };
+        let host = if host == "*" {
+            Host::Any
+        } else if let Ok(cidr) = IpCidr::from_str(host) {
+            Host::Cidr(cidr)
+        } else {
+            Host::Name(host.to_lowercase())
+        };
+        Some(Self { host, ports })
+    }
+
+    fn parse_ports(s: &str) -> Option<(u16, u16)> {
+        if let Some((lo, hi)) = s.split_once('-') {
+            let lo = lo.trim().parse::<u16>().ok()?;
+            let hi = hi.trim().parse::<u16>().ok()?;
+            if lo > hi {
+                return None;
+            }
+            Some((lo, hi))
+        } else {
+            let port = s.trim().parse::<u16>().ok()?;
+            Some((port, port))
+        }
+    }
+
+    fn matches(&self, host: &str, ip: IpAddr, port: u16) -> bool {
+        if let Some((lo, hi)) = self.ports {
+            if port < lo || port > hi {
+                return false;
+            }
+        }
+        match &self.host {
+            Host::Any => true,
+            Host::Cidr(cidr) => cidr.contains(ip),
+            Host::Name(name) => name.eq_ignore_ascii_case(host),
+        }
+    }
+}
+
+fn parse_rules(s: &str) -> Vec<Rule> {
+    s.split(',')
+        .filter(|x| !x.trim().is_empty())
+        .filter_map(|x| {
+            let rule = Rule::parse(x);
+            if rule.is_none() {
+                log::warn!("Ignore invalid tunnel rule: {}", x);
+            }
+            rule
+        })
+        .collect()
+}
+
+fn check_rules(allowlist: &str, denylist: &str, host: &str, ip: IpAddr, port: u16) -> bool {
+    if parse_rules(denylist)
+        .iter()
+        .any(|r| r.matches(host, ip, port))
+    {
+        return false;
+    }
+    let allow = parse_rules(allowlist);
+    if allow.is_empty() {
+        // Fail closed if the allowlist was set but none of it could be parsed.
+        return allowlist.split(',').all(|x| x.trim().is_empty());
+    }
+    allow.iter().any(|r| r.matches(host, ip, port))
+}
+
+pub fn is_enabled() -> bool {
+    !Config::get_option(keys::OPTION_TUNNEL_ALLOWLIST).is_empty()
+        || !Config::get_option(keys::OPTION_TUNNEL_DENYLIST).is_empty()
+}
+
+/// Resolve `host:port` and return the first address permitted by the tunnel
+/// allowlist and denylist. The caller must connect to the returned address, not
+/// resolve `host` again.
+pub async fn resolve_allowed(host: &str, port: i32) -> ResultType<SocketAddr> {
+    if port <= 0 || port > u16::MAX as i32 {
+        bail!("invalid port {}", port);
+    }
+    let port = port as u16;
+    // IPv6 literals arrive in brackets, e.g. from SOCKS5 requests.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let allowlist = Config::get_option(keys::OPTION_TUNNEL_ALLOWLIST);
+    let denylist = Config::get_option(keys::OPTION_TUNNEL_DENYLIST);
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+    if addrs.is_empty() {
+        bail!("failed to resolve {}", host);
+    }
+    for addr in addrs.iter() {
+        if check_rules(&allowlist, &denylist, host, addr.ip(), port) {
+            return Ok(*addr);
+        }
+    }
+    bail!("destination {}:{} is not allowed", host, port);
+}
+
+/// Resolve the address a reverse tunnel asks to listen on. It is checked like a
+/// destination, and must be a loopback address unless
+/// `allow-tunnel-gateway-ports` is set.
+pub async fn resolve_reverse_bind(host: &str, port: i32) -> ResultType<SocketAddr> {
+    let addr = if is_enabled() {
+        resolve_allowed(host, port).await?
+    } else {
+        if port <= 0 || port > u16::MAX as i32 {
+            bail!("invalid port {}", port);
+        }
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        match tokio::net::lookup_host((host, port as u16)).await?.next() {
+            Some(addr) => addr,
+            None => bail!("failed to resolve {}", host),
+        }
+    };
+    if !addr.ip().is_loopback() && !Config::get_bool_option(keys::OPTION_ALLOW_TUNNEL_GATEWAY_PORTS)
+    {
+        bail!("listening on {} is not allowed", addr.ip());
+    }
+    Ok(addr)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_rules() {
+        let ip = |s: &str| IpAddr::from_str(s).unwrap();
+        // no rules
+        assert!(check_rules("", "", "host", ip("10.0.0.1"), 22));
+        // allowlist by cidr and port range
+        let allow = "192.168.1.0/24:3389, 10.0.0.5:8000-8100, localhost";
+        assert!(check_rules(allow, "", "x", ip("192.168.1.20"), 3389));
+        assert!(!check_rules(allow, "", "x", ip("192.168.1.20"), 22));
+        assert!(check_rules(allow, "", "x", ip("10.0.0.5"), 8080));
+        assert!(!check_rules(allow, "", "x", ip("10.0.0.5"), 8101));
+        assert!(check_rules(allow, "", "LocalHost", ip("127.0.0.1"), 5432));
+        assert!(!check_rules(allow, "", "x", ip("172.16.0.1"), 80));
+        // denylist wins over allowlist
+        assert!(!check_rules("*", "*:22", "x", ip("10.0.0.1"), 22));
+        assert!(check_rules("*", "*:22", "x", ip("10.0.0.1"), 23));
+        assert!(!check_rules(
+            "",
+            "169.254.0.0/16",
+            "x",
+            ip("169.254.169.254"),
+            80
+        ));
+        // ipv6
+        assert!(check_rules("[fd00::/8]:443", "", "x", ip("fd00::1"), 443));
+        assert!(check_rules("::1", "", "x", ip("::1"), 443));
+        assert!(!check_rules("[fd00::/8]:443", "", "x", ip("fe80::1"), 443));
+        // an allowlist of only invalid entries denies everything
+        assert!(!check_rules("10.0.0.1:abc", "", "x", ip("10.0.0.1"), 22));
+        assert!(!check_rules("x:1-, [::1", "", "x", ip("::1"), 22));
+        assert!(check_rules(" , ", "", "x", ip("10.0.0.1"), 22));
+        // valid entries still apply next to invalid ones
+        assert!(check_rules(
+            "10.0.0.1:abc, 10.0.0.1:22",
+            "",
+            "x",
+            ip("10.0.0.1"),
+            22
+        ));
+        assert!(!check_rules(
+            "10.0.0.1:abc, 10.0.0.1:22",
+            "",
+            "x",
+            ip("10.0.0.1"),
+            23
+        ));
+    }
+}
@@ -53,6 +53,7 @@ pub struct Client {
     pub port_forward: String,
     pub name: String,
     pub peer_id: String,
+    pub ip: String,
     pub keyboard: bool,
     pub clipboard: bool,
     pub audio: bool,
@@ -136,6 +137,7 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
         port_forward: String,
         peer_id: String,
         name: String,
+        ip: String,
         authorized: bool,
         keyboard: bool,
         clipboard: bool,
@@ -157,6 +159,7 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
             port_forward,
             name: name.clone(),
             peer_id: peer_id.clone(),
+            ip,
             keyboard,
             clipboard,
             audio,
@@ -409,9 +412,9 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                         }
                         Ok(Some(data)) => {
                             match data {
-                                Data::Login{id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, file_transfer_enabled: _file_transfer_enabled, restart, recording, block_input, from_switch} => {
+                                Data::Login{id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, ip, authorized, keyboard, clipboard, audio, file, file_transfer_enabled: _file_transfer_enabled, restart, recording, block_input, from_switch} => {
                                     log::debug!("conn_id: {}", id);
-                                    self.cm.add_connection(id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, restart, recording, block_input, from_switch, self.tx.clone());
+                                    self.cm.add_connection(id, is_file_transfer, is_view_camera, is_terminal, port_forward, peer_id, name, ip, authorized, keyboard, clipboard, audio, file, restart, recording, block_input, from_switch, self.tx.clone());
                                     self.conn_id = id;
                                     #[cfg(target_os = "windows")]
                                     {
@@ -684,6 +687,7 @@ pub async fn start_listen<T: InvokeUiCM>(
                 port_forward,
                 peer_id,
                 name,
+                ip,
                 authorized,
                 keyboard,
                 clipboard,
@@ -704,6 +708,7 @@ pub async fn start_listen<T: InvokeUiCM>(
                     port_forward,
                     peer_id,
                     name,
+                    ip,
                     authorized,
                     keyboard,
                     clipboard,
@@ -597,6 +597,15 @@ pub fn permanent_password() -> String {
     return ipc::get_permanent_password();
 }
 
+/// Unlike an empty `permanent_password`, true for a password set by its hash.
+#[inline]
+pub fn has_permanent_password() -> bool {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    return Config::has_permanent_password();
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return ipc::has_permanent_password();
+}
+
 #[inline]
 pub fn set_permanent_password(password: String) {
     #[cfg(any(target_os = "android", target_os = "ios"))]
@@ -1718,6 +1718,16 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn is_multi_ui_session(&self) -> bool;
     fn update_record_status(&self, start: bool);
     fn update_empty_dirs(&self, _res: ReadEmptyDirsResponse) {}
+    /// Called with every frame before it is decoded. Returns true if the UI
+    /// takes the encoded frame itself, then it is not decoded.
+    fn on_encoded_video_frame(&self, _display: usize, _vf: &VideoFrame) -> bool {
+        false
+    }
+    /// Called with the clipboards of the peer. Returns true if the UI takes
+    /// them itself, then the local clipboard is not updated.
+    fn on_clipboards(&self, _clipboards: &[Clipboard]) -> bool {
+        false
+    }
     fn printer_request(&self, id: i32, path: String);
     fn handle_screenshot_resp(&self, sid: String, msg: String);
     fn handle_terminal_response(&self, response: TerminalResponse);
@@ -2025,15 +2035,16 @@ async fn start_one_port_forward<T: InvokeUiSession>(
 ) {
     if let Err(err) = crate::port_forward::listen(
         handler.get_id(),
-        handler.password.clone(),
+        zeroize::Zeroizing::new(handler.password.clone()),
+        "127.0.0.1".to_owned(),
         port,
         handler.clone(),
         receiver,
         key,
         token,
-        handler.lc.clone(),
         remote_host,
         remote_port,
+        &[],
     )
     .await
     {